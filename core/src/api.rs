@@ -22,6 +22,22 @@ use serde_json::Value as JsonValue;
 use crate::error::Result;
 use QueryNode::*;
 
+// functions that trigger the automatic grouping by the other selected columns
+pub const AGGREGATE_FUNCTIONS: &[&str] = &[
+    "avg",
+    "count",
+    "every",
+    "max",
+    "min",
+    "sum",
+    "array_agg",
+    "json_agg",
+    "jsonb_agg",
+    "json_object_agg",
+    "jsonb_object_agg",
+    "string_agg",
+];
+
 pub const DEFAULT_SAFE_SELECT_FUNCTIONS: &[&str] = &[
     "avg",
     "count",
@@ -89,8 +105,11 @@ lazy_static! {
         ,("neq", "<>")
         ,("like", "like")
         ,("ilike", "ilike")
+        ,("match", "~")
+        ,("imatch", "~*")
         //,("in", "in")
         ,("is", "is")
+        ,("isdistinct", "is distinct from")
         ,("cs", "@>")
         ,("cd", "<@")
         ,("ov", "&&")
@@ -108,9 +127,13 @@ lazy_static! {
 
     ].iter().copied().collect();
 
+    // operators that accept the (any) and (all) modifiers
+    pub static ref QUANTIFIABLE_OPERATORS: Vec<&'static str> = vec!["eq", "gte", "gt", "lte", "lt", "like", "ilike", "match", "imatch"];
+
     pub static ref OPERATORS_START: Vec<String> = {
         OPERATORS.keys().chain(["not","in"].iter()).chain(FTS_OPERATORS.keys()).map(|&op| format!("{op}.") )
         .chain(FTS_OPERATORS.keys().map(|&op| format!("{op}(") ))
+        .chain(QUANTIFIABLE_OPERATORS.iter().map(|&op| format!("{op}(") ))
         .collect()
     };
 
@@ -133,17 +156,30 @@ pub enum Representation {
     HeadersOnly,
 }
 #[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Handling {
+    Strict,
+    Lenient,
+}
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Transaction {
+    Commit,
+    Rollback,
+}
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub enum Count {
     ExactCount,
     PlannedCount,
     EstimatedCount,
 }
 
-#[derive(Debug, PartialEq, Eq, Clone)]
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
 pub struct Preferences {
     pub resolution: Option<Resolution>,
     pub representation: Option<Representation>,
     pub count: Option<Count>,
+    pub transaction: Option<Transaction>,
+    pub handling: Option<Handling>,
+    pub max_affected: Option<u64>,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -174,10 +210,31 @@ pub struct ApiResponse {
 pub enum ContentType {
     ApplicationJSON,
     SingularJSON,
+    GeoJSON,
     TextCSV,
+    TextPlain,
+    TextXML,
+    OctetStream,
+    // application/vnd.pgrst.plan, the execution plan of the query instead of its result
+    Plan(PlanFormat, Vec<PlanOption>),
     Other(String),
 }
 
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum PlanFormat {
+    Json,
+    Text,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum PlanOption {
+    Analyze,
+    Verbose,
+    Settings,
+    Buffers,
+    Wal,
+}
+
 #[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
 pub struct ProcParam<'a> {
     pub name: &'a str,
@@ -212,7 +269,21 @@ impl<'a, 'b> Query<'a> {
                 Delete { where_, .. } => where_.conditions.as_mut(),
                 FunctionCall { where_, .. } => where_.conditions.as_mut(),
             };
-            p.into_iter().for_each(|c| query_conditions.push(c));
+            for c in p {
+                // embed=is.null and embed=not.is.null filter the parent rows by the presence of the embedded rows
+                if let Condition::Single {
+                    field: Field { name, json_path: None },
+                    filter: Filter::Is(TrileanVal::TriNull),
+                    negate,
+                } = &c
+                {
+                    if let Some(s) = q.sub_selects.iter_mut().find(|s| s.alias.unwrap_or(s.query.node.name()) == *name) {
+                        s.join_type = if *negate { JoinType::Inner } else { JoinType::Anti };
+                        continue;
+                    }
+                }
+                query_conditions.push(c);
+            }
             Ok(())
         })
     }
@@ -533,6 +604,11 @@ impl<'a> QueryNode<'a> {
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct OrderTerm<'a> {
     pub term: Field<'a>,
+    // order by a column of a to-one relation: relation(column)
+    pub relation: Option<&'a str>,
+    pub join: Option<Join<'a>>,
+    // the row level policies of the relation (added when the permissions are checked)
+    pub relation_policy: Option<Condition<'a>>,
     pub direction: Option<OrderDirection>,
     pub null_order: Option<OrderNulls>,
 }
@@ -554,6 +630,15 @@ pub enum OrderNulls {
 
 pub type JoinHint<'a> = &'a str;
 
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum JoinType {
+    Left,
+    // parent rows without embedded rows are dropped (!inner, embed=not.is.null)
+    Inner,
+    // only parent rows without embedded rows are kept (embed=is.null)
+    Anti,
+}
+
 #[derive(Debug, PartialEq, Eq, Hash, Clone, Deserialize, Serialize)]
 pub struct Qi<'a>(pub &'a str, pub &'a str);
 
@@ -570,6 +655,19 @@ pub struct ForeignKey<'a> {
     pub referenced_columns: Vec<&'a str>,
 }
 
+// a function taking a row of table and returning rows of referenced_table
+#[derive(Debug, PartialEq, Eq, Clone, Deserialize, Serialize)]
+pub struct ComputedRelationship<'a> {
+    #[serde(borrow)]
+    pub function: Qi<'a>,
+    #[serde(borrow)]
+    pub table: Qi<'a>,
+    #[serde(borrow)]
+    pub referenced_table: Qi<'a>,
+    #[serde(default)]
+    pub to_one: bool,
+}
+
 #[allow(clippy::large_enum_variant)]
 #[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
 pub enum Join<'a> {
@@ -578,6 +676,8 @@ pub enum Join<'a> {
     #[serde(borrow)]
     Parent(ForeignKey<'a>),
     Many(#[serde(borrow)] Qi<'a>, #[serde(borrow)] ForeignKey<'a>, #[serde(borrow)] ForeignKey<'a>),
+    #[serde(borrow)]
+    Computed(ComputedRelationship<'a>),
 }
 
 #[derive(Debug, PartialEq)]
@@ -613,6 +713,7 @@ pub enum SelectItem<'a> {
         partitions: Vec<Field<'a>>,
         orders: Vec<OrderTerm<'a>>,
         alias: Option<&'a str>,
+        cast: Option<&'a str>,
     },
 }
 
@@ -622,6 +723,9 @@ pub struct SubSelect<'a> {
     pub alias: Option<&'a str>,
     pub hint: Option<JoinHint<'a>>,
     pub join: Option<Join<'a>>,
+    // the columns of a to-one embed are added to the parent row (...relation)
+    pub spread: bool,
+    pub join_type: JoinType,
 }
 
 #[derive(Debug, PartialEq, Eq, Hash, Clone, Serialize, Deserialize)]
@@ -660,6 +764,13 @@ pub enum Condition<'a> {
     },
 }
 
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Quantifier {
+    Any,
+    All,
+}
+
 #[derive(Debug, PartialEq, Eq, Hash, Clone)]
 pub enum TrileanVal {
     TriTrue,
@@ -715,6 +826,7 @@ pub struct EnvVar<'a> {
 #[derive(Debug, PartialEq, Eq, Hash, Clone)]
 pub enum Filter<'a> {
     Op(Operator<'a>, SingleVal<'a>),
+    Quant(Operator<'a>, Quantifier, ListVal<'a>),
     In(ListVal<'a>),
     Is(TrileanVal),
     Fts(Operator<'a>, Option<Language<'a>>, SingleVal<'a>),
@@ -731,6 +843,11 @@ impl<'a> Serialize for Filter<'a> {
                 operator,
                 value: value.clone(),
             },
+            Filter::Quant(operator, quantifier, value) => FilterHelper::Quant {
+                operator,
+                quantifier: quantifier.clone(),
+                value: value.clone(),
+            },
             Filter::In(value) => FilterHelper::In { value: value.clone() },
             Filter::Is(value) => FilterHelper::Is { value: value.clone() },
             Filter::Fts(operator, language, value) => FilterHelper::Fts {
@@ -754,6 +871,7 @@ impl<'a, 'de: 'a> Deserialize<'de> for Filter<'a> {
     {
         Deserialize::deserialize(deserializer).map(|v| match v {
             FilterHelper::Op { operator, value } => Filter::Op(operator, value),
+            FilterHelper::Quant { operator, quantifier, value } => Filter::Quant(operator, quantifier, value),
             FilterHelper::In { value } => Filter::In(value),
             FilterHelper::Is { value } => Filter::Is(value),
             FilterHelper::Fts { operator, language, value } => Filter::Fts(operator, language, value),
@@ -773,6 +891,14 @@ enum FilterHelper<'a> {
         #[serde(borrow, rename = "val")]
         value: SingleVal<'a>,
     },
+    Quant {
+        #[serde(borrow, rename = "op")]
+        operator: Operator<'a>,
+        #[serde(rename = "quant")]
+        quantifier: Quantifier,
+        #[serde(borrow, rename = "vals")]
+        value: ListVal<'a>,
+    },
     In {
         #[serde(borrow, rename = "in")]
         value: ListVal<'a>,
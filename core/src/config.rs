@@ -95,10 +95,27 @@ pub struct VhostConfig {
     pub db_pool: usize,
     #[serde(default)]
     pub db_tx_rollback: bool,
+    #[serde(default)]
+    pub db_tx_allow_override: bool,
+    #[serde(default)]
+    pub db_stream_responses: bool,
+    #[serde(default)]
+    pub db_plan_cache_size: usize,
+    #[serde(default)]
+    pub db_plan_enabled: bool,
+    pub db_plan_roles: Option<Vec<String>>,
     #[serde(deserialize_with = "to_tuple", default)]
     pub db_pre_request: Option<(String, String)>,
+    #[serde(default = "db_channel")]
+    pub db_channel: String,
+    #[serde(default)]
+    pub db_channel_enabled: bool,
+    pub admin_token: Option<String>,
     pub jwt_secret: Option<String>,
+    pub jwt_public_key: Option<String>,
+    pub jwt_jwks: Option<String>,
     pub jwt_aud: Option<String>,
+    pub jwt_iss: Option<String>,
     #[serde(default = "role_claim_key")]
     pub role_claim_key: String,
     #[serde(default)]
@@ -114,6 +131,9 @@ fn db_type() -> String {
 fn db_schemas() -> Vec<String> {
     vec!["public".to_string()]
 }
+fn db_channel() -> String {
+    "pgrst".to_string()
+}
 fn role_claim_key() -> String {
     ".role".to_string()
 }
@@ -159,10 +179,21 @@ mod test {
                     db_anon_role: Some("anonymous".to_string()),
                     db_use_legacy_gucs: false,
                     db_tx_rollback: false,
+                    db_tx_allow_override: false,
                     db_pre_request: Some(("api".to_string(), "test".to_string())),
+                    db_channel: "pgrst".to_string(),
+                    db_channel_enabled: false,
+                    db_stream_responses: false,
+                    db_plan_cache_size: 0,
+                    db_plan_enabled: false,
+                    db_plan_roles: None,
+                    admin_token: None,
                     db_allowed_select_functions: vec![],
                     jwt_secret: None,
+                    jwt_public_key: None,
+                    jwt_jwks: None,
                     jwt_aud: None,
+                    jwt_iss: None,
                     role_claim_key: ".role".to_string(),
                     db_pool: 10,
                     db_max_rows: None,
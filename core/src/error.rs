@@ -53,6 +53,12 @@ pub enum Error {
         compressed_rel: JsonValue,
     },
 
+    #[snafu(display("SpreadNotToOne {} {}", origin, target))]
+    SpreadNotToOne { origin: String, target: String },
+
+    #[snafu(display("RelatedOrderNotToOne {} {}", origin, target))]
+    RelatedOrderNotToOne { origin: String, target: String },
+
     #[snafu(display("InvalidFilters"))]
     InvalidFilters,
 
@@ -79,6 +85,9 @@ pub enum Error {
     #[snafu(display("UnsupportedVerb"))]
     UnsupportedVerb,
 
+    #[snafu(display("InvalidPreferences: {}", invalid))]
+    InvalidPreferences { invalid: String },
+
     #[snafu(display("UnsupportedFeature: {}", message))]
     UnsupportedFeature { message: String },
 
@@ -106,6 +115,12 @@ pub enum Error {
     #[snafu(display("ContentTypeError {}", message))]
     ContentTypeError { message: String },
 
+    #[snafu(display("NotAcceptable {}", accept))]
+    NotAcceptable { accept: String, available: Vec<String> },
+
+    #[snafu(display("MaxAffectedViolation {}", count))]
+    MaxAffectedViolation { count: u64 },
+
     #[snafu(display("SingularityError {}", count))]
     SingularityError { count: u64, content_type: String },
 
@@ -137,6 +152,7 @@ impl Error {
         match self {
             Error::Serde { .. } => 400,
             Error::UnsupportedFeature { .. } => 400,
+            Error::InvalidPreferences { .. } => 400,
             Error::ContentTypeError { .. } => 415,
             Error::GucHeadersError => 500,
             Error::GucStatusError => 500,
@@ -149,6 +165,8 @@ impl Error {
             Error::ParseRequestError { .. } => 400,
             Error::NoRelBetween { .. } => 400,
             Error::AmbiguousRelBetween { .. } => 300,
+            Error::SpreadNotToOne { .. } => 400,
+            Error::RelatedOrderNotToOne { .. } => 400,
             Error::InvalidFilters => 405,
             Error::UnacceptableSchema { .. } => 406,
             Error::UnknownRelation { .. } => 400,
@@ -164,6 +182,8 @@ impl Error {
             Error::PutMatchingPkError => 400,
             Error::JsonSerialize { .. } => 500,
             Error::SingularityError { .. } => 406,
+            Error::NotAcceptable { .. } => 406,
+            Error::MaxAffectedViolation { .. } => 400,
         }
     }
 
@@ -173,6 +193,10 @@ impl Error {
                 json!({ "message": format!("{source}") })
             }
             Error::UnsupportedFeature { message } => json!({ "message": message }),
+            Error::InvalidPreferences { invalid } => json!({
+                "message": "Invalid preferences given with handling=strict",
+                "details": format!("Invalid preferences: {invalid}")
+            }),
             Error::ContentTypeError { message } => json!({ "message": message }),
             Error::GucHeadersError => {
                 json!({"message": "response.headers guc must be a JSON array composed of objects with a single key and a string value"})
@@ -198,6 +222,14 @@ impl Error {
             Error::NoRelBetween { origin, target } => json!({
                 "message": format!("Could not find foreign keys between these entities. No relationship found between {origin} and {target}")
             }),
+            Error::SpreadNotToOne { origin, target } => json!({
+                "message": format!("A spread operation on '{target}' is not possible"),
+                "details": format!("'{origin}' and '{target}' do not form a many-to-one or one-to-one relationship")
+            }),
+            Error::RelatedOrderNotToOne { origin, target } => json!({
+                "message": format!("A related order on '{target}' is not possible"),
+                "details": format!("'{origin}' and '{target}' do not form a many-to-one or one-to-one relationship")
+            }),
             Error::AmbiguousRelBetween {
                 origin,
                 target,
@@ -254,6 +286,14 @@ impl Error {
                 "message": "JSON object requested, multiple (or no) rows returned",
                 "details": format!("Results contain {count} rows, {content_type} requires 1 row")
             }),
+            Error::MaxAffectedViolation { count } => json!({
+                "message": "Query result exceeds max-affected preference constraint",
+                "details": format!("The query affects {count} rows")
+            }),
+            Error::NotAcceptable { accept, available } => json!({
+                "message": format!("None of these media types are available: {accept}"),
+                "details": format!("Available media types: {}", available.join(", "))
+            }),
         }
     }
 }
@@ -265,6 +305,7 @@ pub fn rel_hint(joins: &[Join]) -> String {
             Child(fk) => format!("'{}!{}'", fk.table.1, fk.name),
             Parent(fk) => format!("'{}!{}'", fk.referenced_table.1, fk.name),
             Many(t, _fk1, fk2) => format!("'{}!{}'", fk2.referenced_table.1, t.1),
+            Computed(r) => format!("'{}'", r.function.1),
         })
         .collect::<Vec<_>>()
         .join(", ")
@@ -287,5 +328,10 @@ pub fn compressed_rel(join: &Join) -> JsonValue {
             "relationship": format!("{}.{}[{}][{}]", t.0, t.1, fk1.name, fk2.name),
             "embedding": format!("{} with {}", fk1.referenced_table.1, fk2.referenced_table.1 )
         }),
+        Computed(r) => json!({
+            "cardinality": if r.to_one { "many-to-one" } else { "one-to-many" },
+            "relationship": format!("{}.{}", r.function.0, r.function.1),
+            "embedding": format!("{} with {}", r.table.1, r.referenced_table.1 )
+        }),
     }
 }
@@ -52,6 +52,31 @@ macro_rules! cast_select_item_format {
 #[allow(unused_imports)]
 pub(super) use cast_select_item_format;
 
+// quantified operators are expanded into a group of conditions for the databases without any/all
+#[allow(unused_macros)]
+macro_rules! fmt_quant_filter {
+    ($fld:ident, $o:ident, $q:ident, $l:ident) => {
+        match ($q, $l.is_empty()) {
+            (Quantifier::Any, true) => sql("false"),
+            (Quantifier::All, true) => sql("true"),
+            (q, false) => {
+                let separator = match q {
+                    Quantifier::Any => " or ",
+                    Quantifier::All => " and ",
+                };
+                "(" + $l
+                    .iter()
+                    .map(|v| fmt_operator_term($fld.clone(), $o, v))
+                    .collect::<Result<Vec<_>>>()?
+                    .join(separator)
+                    + ")"
+            }
+        }
+    };
+}
+#[allow(unused_imports)]
+pub(super) use fmt_quant_filter;
+
 #[allow(unused_macros)]
 macro_rules! get_body_snippet {
     ($return_representation:ident, $accept_content_type:ident, $query:ident ) => {
@@ -89,10 +114,20 @@ macro_rules! get_body_snippet {
                     ..
                 },
             ) => Ok(body_snippet!(function_any)),
+            // the parser only allows these for functions returning text, xml or bytea
+            (true, TextPlain | TextXML, FunctionCall { is_scalar: true, .. }) => Ok(body_snippet!(function_scalar_text)),
+            (true, OctetStream, FunctionCall { is_scalar: true, .. }) => Ok(body_snippet!(function_scalar_binary)),
 
             (true, ApplicationJSON, _) => Ok(body_snippet!(json_array)),
             (true, SingularJSON, _) => Ok(body_snippet!(json_object)),
+            (true, GeoJSON, _) => Ok(body_snippet!(geojson)),
             (true, TextCSV, _) => Ok(body_snippet!(csv)),
+            (_, TextPlain | TextXML | OctetStream, _) => Err(Error::ContentTypeError {
+                message: "Raw output is only available for scalar functions".to_string(),
+            }),
+            (_, Plan(..), _) => Err(Error::ContentTypeError {
+                message: "The execution plan is only available for the main query".to_string(),
+            }),
             (_, Other(t), _) => Err(Error::ContentTypeError {
                 message: format!("None of these Content-Types are available: {}", t),
             }),
@@ -161,13 +196,45 @@ macro_rules! fmt_main_query {
         pub fn fmt_main_query<'a>(
             db_schema: &'a DbSchema<'_>, schema: &'a str, request: &'a ApiRequest, env: &'a HashMap<&'a str, &'a str>,
         ) -> Result<Snippet<'a>> {
-            fmt_main_query_internal(db_schema, schema, &request.method, &request.accept_content_type, &request.query, &request.preferences, env)
+            match &request.accept_content_type {
+                // the plan is the one of the query returning the rows as json
+                Plan(format, options) => Ok(fmt_explain(format, options)
+                    + fmt_main_query_internal(db_schema, schema, &request.method, &ApplicationJSON, &request.query, &request.preferences, env)?),
+                accept_content_type => {
+                    fmt_main_query_internal(db_schema, schema, &request.method, accept_content_type, &request.query, &request.preferences, env)
+                }
+            }
         }
     };
 }
 #[allow(unused_imports)]
 pub(super) use fmt_main_query;
 
+#[allow(unused_macros)]
+macro_rules! fmt_explain {
+    () => {
+        fn fmt_explain<'a>(format: &PlanFormat, options: &[PlanOption]) -> Snippet<'a> {
+            let format = match format {
+                PlanFormat::Json => "json",
+                PlanFormat::Text => "text",
+            };
+            let options = options
+                .iter()
+                .map(|o| match o {
+                    PlanOption::Analyze => ", analyze",
+                    PlanOption::Verbose => ", verbose",
+                    PlanOption::Settings => ", settings",
+                    PlanOption::Buffers => ", buffers",
+                    PlanOption::Wal => ", wal",
+                })
+                .collect::<String>();
+            sql(format!("explain (format {format}{options}) "))
+        }
+    };
+}
+#[allow(unused_imports)]
+pub(super) use fmt_explain;
+
 #[allow(unused_macros)]
 macro_rules! fmt_env_query {
     () => {
@@ -333,12 +400,17 @@ pub fn fmt_query<'a>(
             ..
         } => {
             //let table_alias = table_alias_suffix.map(|s| format!("{}{}", table, s)).unwrap_or_default();
-            let (_qi, from_snippet) = match table_alias {
-                None => (
+            let (_qi, from_snippet) = match (table_alias, _join) {
+                // computed relationship, the function is called with the parent row
+                (Some(a), Some(Computed(r))) => (
+                    Qi("", a),
+                    format!("{}({}) as {}", fmt_qi(&r.function), fmt_identity(r.table.1), fmt_identity(a)),
+                ),
+                (None, _) => (
                     Qi(schema, table),
                     fmt_qi(&Qi(schema, table)),
                 ),
-                Some(a) => (
+                (Some(a), _) => (
                     Qi("", a),
                     // format!(
                     //     "{} as {}",
@@ -350,7 +422,28 @@ pub fn fmt_query<'a>(
 
             };
             let qi = &_qi;
-            let mut select: Vec<_> = select.iter().map(|s| fmt_select_item(qi, s)).collect::<Result<Vec<_>>>()?;
+            let computed_columns = db_schema.get_object(schema, table).map(|o| &o.computed_columns).ok();
+            let mut select: Vec<_> = select
+                .iter()
+                .map(|s| match s {
+                    // computed column, the function is called with the current row
+                    Simple { field: Field { name, json_path }, alias, cast }
+                        if computed_columns.map(|c| c.contains_key(name)).unwrap_or(false) =>
+                    {
+                        let call = format!("{}({})", fmt_qi(&Qi(schema, name)), fmt_identity(qi.1));
+                        let field = match json_path {
+                            Some(_) => format!(fmt_field_format!(), call, "", "", fmt_json_path(json_path)),
+                            None => call,
+                        };
+                        let select_name = fmt_identity(&fmt_select_name(name, json_path, alias).unwrap_or(name.to_string()));
+                        Ok(sql(match cast {
+                            Some(cast) => format!("cast({} as {}) as {}", field, cast, select_name),
+                            None => format!("{} as {}", field, select_name),
+                        }))
+                    }
+                    s => fmt_select_item(qi, s),
+                })
+                .collect::<Result<Vec<_>>>()?;
             let (sub_selects, joins): (Vec<_>, Vec<_>) = q
                 .sub_selects
                 .iter()
@@ -359,6 +452,19 @@ pub fn fmt_query<'a>(
                 .into_iter()
                 .unzip();
             select.extend(sub_selects.into_iter());
+            // the rows of the to-one embeds joined laterally are grouped as a whole
+            let groupby_embeds = q.sub_selects.iter().filter_map(|SubSelect { alias, join, spread, .. }| match join {
+                Some(Parent(fk)) if !*spread => Some(alias.unwrap_or(fk.referenced_table.1)),
+                Some(Computed(r)) if r.to_one && !*spread => Some(alias.unwrap_or(r.function.1)),
+                _ => None,
+            });
+            let groupby_snippet = match fmt_groupby(qi, groupby)? {
+                g if g.is_empty() => g,
+                g => std::iter::once(g)
+                    .chain(groupby_embeds.map(|name| fmt_identity(&format!("{}_{}", qi.1, name))))
+                    .collect::<Vec<_>>()
+                    .join(", "),
+            };
 
             (
                 None,
@@ -389,13 +495,9 @@ pub fn fmt_query<'a>(
                     + " "
                     + joins.into_iter().flatten().collect::<Vec<_>>().join(" ")
                     + " "
-                    + if !where_.conditions.is_empty() {
-                        "where " + fmt_condition_tree(qi, where_)?
-                    } else {
-                        sql("")
-                    }
+                    + fmt_where(qi, where_, fmt_embed_conditions(db_schema, schema, q)?)?
                     + " "
-                    + (fmt_groupby(qi, groupby)?)
+                    + groupby_snippet
                     + (fmt_order(qi, order)?)
                     + " "
                     + fmt_limit(limit)
@@ -721,7 +823,7 @@ pub(super) use fmt_query;
 #[allow(unused_macros)]
 macro_rules! fmt_count_query {
     () => {
-        fn fmt_count_query<'a>(_db_schema: &'a DbSchema<'_>, schema: &'a str, wrapin_cte: Option<&'static str>, q: &'a Query) -> Result<Snippet<'a>> {
+        fn fmt_count_query<'a>(db_schema: &'a DbSchema<'_>, schema: &'a str, wrapin_cte: Option<&'static str>, q: &'a Query) -> Result<Snippet<'a>> {
             let query_snippet = match &q.node {
                 FunctionCall { .. } => sql(format!(" select 1 from {}", fmt_identity("subzero_source"))),
                 Select {
@@ -751,11 +853,7 @@ macro_rules! fmt_count_query {
                         + " "
                         // + joins.into_iter().flatten().collect::<Vec<_>>().join(" ")
                         + " "
-                        + if !where_.conditions.is_empty() {
-                            "where " + fmt_condition_tree(qi, where_)?
-                        } else {
-                            sql("")
-                        }
+                        + fmt_where(qi, where_, fmt_embed_conditions(db_schema, schema, q)?)?
                 }
                 Insert { .. } => sql(format!(" select 1 from {}", fmt_identity("subzero_source"))),
                 Update { .. } => sql(format!(" select 1 from {}", fmt_identity("subzero_source"))),
@@ -772,6 +870,53 @@ macro_rules! fmt_count_query {
 #[allow(unused_imports)]
 pub(super) use fmt_count_query;
 
+#[allow(unused_macros)]
+macro_rules! fmt_where {
+    () => {
+        fn fmt_where<'a, 'b>(qi: &'b Qi<'b>, where_: &'a ConditionTree<'a>, embed_conditions: Vec<Snippet<'a>>) -> Result<Snippet<'a>> {
+            let mut conditions = if where_.conditions.is_empty() {
+                vec![]
+            } else {
+                vec![fmt_condition_tree(qi, where_)?]
+            };
+            conditions.extend(embed_conditions);
+            Ok(if conditions.is_empty() {
+                sql("")
+            } else {
+                "where " + conditions.join(" and ")
+            })
+        }
+    };
+}
+#[allow(unused_imports)]
+pub(super) use fmt_where;
+
+#[allow(unused_macros)]
+macro_rules! fmt_embed_conditions {
+    () => {
+        // embeds with an inner or anti join restrict the parent rows through an exists check
+        fn fmt_embed_conditions<'a>(db_schema: &'a DbSchema<'_>, schema: &'a str, q: &'a Query) -> Result<Vec<Snippet<'a>>> {
+            q.sub_selects
+                .iter()
+                .filter(|s| s.join_type != JoinType::Left)
+                .map(
+                    |SubSelect {
+                         query, join, join_type, ..
+                     }| {
+                        Ok(match join_type {
+                            JoinType::Anti => sql("not exists ("),
+                            _ => sql("exists ("),
+                        } + fmt_query(db_schema, schema, true, None, query, join)?
+                            + ")")
+                    },
+                )
+                .collect()
+        }
+    };
+}
+#[allow(unused_imports)]
+pub(super) use fmt_embed_conditions;
+
 #[allow(unused_macros)]
 macro_rules! fmt_body {
     () => {
@@ -821,14 +966,9 @@ macro_rules! fmt_condition {
             Ok(match c {
                 Single { field, filter, negate } => {
                     let exp = match filter {
-                        Op(op, v) => {
-                            match SUPPORTED_OPERATORS.get(op) {
-                                // use operator expression if it is supported
-                                Some(_) => fmt_field(qi, field)? + " " + fmt_filter(filter)?,
-                                // otherwise use function call
-                                None => sql(*op) + "(" + fmt_field(qi, field)? + "," + param(v as &SqlParam) + ")",
-                            }
-                        }
+                        // operators without a direct equivalent are formatted by the dialect
+                        Op(op, _) | Fts(op, _, _) if !SUPPORTED_OPERATORS.contains(op) => fmt_filter_expression(fmt_field(qi, field)?, filter)?,
+                        Quant(..) => fmt_filter_expression(fmt_field(qi, field)?, filter)?,
                         _ => fmt_field(qi, field)? + " " + fmt_filter(filter)?,
                     };
 
@@ -891,6 +1031,21 @@ macro_rules! fmt_condition {
 #[allow(unused_imports)]
 pub(super) use fmt_condition;
 
+#[allow(unused_macros)]
+macro_rules! fmt_filter_expression {
+    () => {
+        fn fmt_filter_expression<'a>(fld: String, f: &'a Filter<'a>) -> Result<Snippet<'a>> {
+            Ok(match f {
+                // use function call
+                Op(o, v) => sql(*o) + "(" + fld + "," + param(v as &SqlParam) + ")",
+                _ => fld + " " + fmt_filter(f)?,
+            })
+        }
+    };
+}
+#[allow(unused_imports)]
+pub(super) use fmt_filter_expression;
+
 #[allow(unused_macros)]
 macro_rules! fmt_in_filter {
     ($p:ident) => {
@@ -909,6 +1064,14 @@ macro_rules! fmt_filter {
                     let vv: &SqlParam = v;
                     fmt_operator(o)? + param(vv)
                 }
+                Quant(o, q, l) => {
+                    let ll: &SqlParam = l;
+                    let q = match q {
+                        Quantifier::Any => "any",
+                        Quantifier::All => "all",
+                    };
+                    fmt_operator(o)? + q + "(" + param(ll) + ")"
+                }
                 In(l) => {
                     let ll: &SqlParam = l;
                     fmt_in_filter!(ll)
@@ -979,6 +1142,7 @@ macro_rules! fmt_function_call {
                     let (p1, p2) = (&parameters[0], &parameters[1]);
                     sql("(") + fmt_function_param(qi, p1)? + " / " + fmt_function_param(qi, p2)? + ")"
                 }
+                "count" if parameters.is_empty() => sql("count(*)"),
                 _ => {
                     sql(fn_name)
                         + "("
@@ -1001,9 +1165,9 @@ macro_rules! fmt_select_item_function {
     () => {
         fn fmt_select_item_function<'a, 'b>(
             qi: &'b Qi<'b>, fn_name: &'a str, parameters: &'a [FunctionParam<'a>], partitions: &'a [Field<'a>], orders: &'a [OrderTerm],
-            alias: &'a Option<&str>,
+            alias: &'a Option<&str>, cast: &'a Option<&str>,
         ) -> Result<Snippet<'a>> {
-            Ok(fmt_function_call(qi, fn_name, parameters)?
+            let call = fmt_function_call(qi, fn_name, parameters)?
                 + if partitions.is_empty() && orders.is_empty() {
                     sql("")
                 } else {
@@ -1014,14 +1178,13 @@ macro_rules! fmt_select_item_function {
                             sql("partition by ") + partitions.iter().map(|p| fmt_field(qi, p)).collect::<Result<Vec<_>>>()?.join(",")
                         }
                         + " "
-                        + if orders.is_empty() {
-                            "".to_string()
-                        } else {
-                            fmt_order(qi, orders)?
-                        }
+                        + if orders.is_empty() { sql("") } else { fmt_order(qi, orders)? }
                         + " )"
-                }
-                + fmt_as(fn_name, &None, alias))
+                };
+            Ok(match cast {
+                Some(c) => sql("cast(") + call + format!(" as {})", c) + fmt_as(fn_name, &None, &Some(alias.unwrap_or(fn_name))),
+                None => call + fmt_as(fn_name, &None, alias),
+            })
         }
     };
 }
@@ -1061,7 +1224,8 @@ macro_rules! fmt_select_item {
                     parameters,
                     partitions,
                     orders,
-                } => fmt_select_item_function(qi, *fn_name, parameters, partitions, orders, alias),
+                    cast,
+                } => fmt_select_item_function(qi, *fn_name, parameters, partitions, orders, alias, cast),
             }
         }
     };
@@ -1075,9 +1239,31 @@ macro_rules! fmt_sub_select_item {
         fn fmt_sub_select_item<'a, 'b>(
             db_schema: &'a DbSchema<'_>, schema: &'a str, qi: &'b Qi<'b>, i: &'a SubSelect,
         ) -> Result<(Snippet<'a>, Vec<Snippet<'a>>)> {
-            let SubSelect { query, alias, join, .. } = i;
+            let SubSelect {
+                query,
+                alias,
+                join,
+                spread,
+                ..
+            } = i;
             match join {
                 Some(j) => match j {
+                    Parent(ForeignKey {
+                        referenced_table: Qi(_, name),
+                        ..
+                    })
+                    | Child(ForeignKey { table: Qi(_, name), .. })
+                    | Computed(ComputedRelationship { function: Qi(_, name), .. })
+                        if *spread =>
+                    {
+                        // the columns of the embedded row are selected directly
+                        let local_table_name = format!("{}_{}", qi.1, alias.as_ref().unwrap_or(name));
+                        let subquery = fmt_query(db_schema, schema, true, None, query, join)?;
+                        Ok((
+                            sql(format!("{}.*", fmt_identity(&local_table_name))),
+                            vec!["left join lateral (" + subquery + ") as " + sql(fmt_identity(&local_table_name)) + " on true"],
+                        ))
+                    }
                     Parent(fk) => {
                         let alias_or_name = alias.as_ref().unwrap_or(&fk.referenced_table.1);
                         let local_table_name = format!("{}_{}", qi.1, alias_or_name);
@@ -1104,6 +1290,32 @@ macro_rules! fmt_sub_select_item {
                             vec![],
                         ))
                     }
+                    Computed(r) if r.to_one => {
+                        let alias_or_name = alias.as_ref().unwrap_or(&r.function.1);
+                        let local_table_name = format!("{}_{}", qi.1, alias_or_name);
+                        let subquery = fmt_query(db_schema, schema, true, None, query, join)?;
+
+                        Ok((
+                            sql(format!("row_to_json({}.*) as {}", fmt_identity(&local_table_name), fmt_identity(alias_or_name))),
+                            vec!["left join lateral (" + subquery + ") as " + sql(fmt_identity(&local_table_name)) + " on true"],
+                        ))
+                    }
+                    Computed(r) => {
+                        let alias_or_name = fmt_identity(alias.as_ref().unwrap_or(&r.function.1));
+                        let local_table_name = fmt_identity(r.function.1);
+                        let subquery = fmt_query(db_schema, schema, true, None, query, join)?;
+                        Ok((
+                            ("coalesce((select json_agg("
+                                + sql(local_table_name.clone())
+                                + ".*) from ("
+                                + subquery
+                                + ") as "
+                                + sql(local_table_name)
+                                + "), '[]') as "
+                                + sql(alias_or_name)),
+                            vec![],
+                        ))
+                    }
                     Many(_table, _fk1, fk2) => {
                         let alias_or_name = fmt_identity(alias.as_ref().unwrap_or(&fk2.referenced_table.1));
                         let local_table_name = fmt_identity(fk2.referenced_table.1);
@@ -1243,17 +1455,12 @@ pub(super) use fmt_function_param;
 #[allow(unused_macros)]
 macro_rules! fmt_order {
     () => {
-        fn fmt_order<'a>(qi: &'a Qi, o: &'a [OrderTerm<'a>]) -> Result<String> {
+        fn fmt_order<'a, 'b>(qi: &'b Qi<'b>, o: &'a [OrderTerm<'a>]) -> Result<Snippet<'a>> {
             Ok(if o.len() > 0 {
                 //format!("order by {}", o.iter().map(|t| fmt_order_term(qi, t)).collect::<Result<Vec<_>>>()?.join(", "))
-                String::from("order by ")
-                    + o.iter()
-                        .map(|t| fmt_order_term(qi, t))
-                        .collect::<Result<Vec<_>>>()?
-                        .join(", ")
-                        .as_str()
+                "order by " + o.iter().map(|t| fmt_order_term(qi, t)).collect::<Result<Vec<_>>>()?.join(", ")
             } else {
-                String::new()
+                sql("")
             })
         }
     };
@@ -1264,7 +1471,7 @@ pub(super) use fmt_order;
 #[allow(unused_macros)]
 macro_rules! fmt_order_term {
     () => {
-        fn fmt_order_term<'a>(_qi: &'a Qi, t: &'a OrderTerm<'a>) -> Result<String> {
+        fn fmt_order_term<'a, 'b>(qi: &'b Qi<'b>, t: &'a OrderTerm<'a>) -> Result<Snippet<'a>> {
             let direction = match &t.direction {
                 None => "",
                 Some(d) => match d {
@@ -1279,8 +1486,39 @@ macro_rules! fmt_order_term {
                     OrderNulls::NullsLast => "nulls last",
                 },
             };
-            //Ok(format!("{} {} {}", fmt_field(&Qi("".to_string(),"".to_string()), &t.term)?, direction, nulls))
-            Ok(vec![fmt_field(&Qi("", ""), &t.term)?.as_str(), direction, nulls].join(" "))
+            let term = match (&t.relation, &t.join) {
+                // the column of a to-one relation is selected with a scalar subquery
+                (Some(relation), Some(join)) => {
+                    let (from, columns) = match join {
+                        Parent(fk) => (fmt_qi(&fk.referenced_table), fk.referenced_columns.iter().zip(fk.columns.iter()).collect::<Vec<_>>()),
+                        Child(fk) => (fmt_qi(&fk.table), fk.columns.iter().zip(fk.referenced_columns.iter()).collect::<Vec<_>>()),
+                        Computed(r) if r.to_one => (format!("{}({})", fmt_qi(&r.function), fmt_identity(qi.1)), vec![]),
+                        _ => {
+                            return Err(Error::RelatedOrderNotToOne {
+                                origin: qi.1.to_string(),
+                                target: relation.to_string(),
+                            })
+                        }
+                    };
+                    let mut conditions = columns
+                        .iter()
+                        .map(|(c, pc)| sql(format!("{}.{} = {}.{}", fmt_identity(relation), fmt_identity(c), fmt_qi(qi), fmt_identity(pc))))
+                        .collect::<Vec<_>>();
+                    if let Some(policy) = &t.relation_policy {
+                        conditions.push(fmt_condition(&Qi("", relation), policy)?);
+                    }
+                    sql(format!("(select {} from {} as {}", fmt_field(&Qi("", relation), &t.term)?, from, fmt_identity(relation)))
+                        + if conditions.is_empty() {
+                            sql("")
+                        } else {
+                            " where " + conditions.join(" and ")
+                        }
+                        + ")"
+                }
+                //Ok(format!("{} {} {}", fmt_field(&Qi("".to_string(),"".to_string()), &t.term)?, direction, nulls))
+                _ => sql(fmt_field(&Qi("", ""), &t.term)?),
+            };
+            Ok(term + " " + direction + " " + nulls)
         }
     };
 }
@@ -1509,8 +1747,15 @@ macro_rules! body_snippet {
     (function_scalar) => { "coalesce((json_agg(_subzero_t.subzero_scalar)->0)::text, 'null')" };
     (function_scalar_array) => { "coalesce((json_agg(_subzero_t.subzero_scalar))::text, '[]')" };
     (function_any) => { "coalesce((json_agg(_subzero_t)->0)::text, 'null')" };
+    (function_scalar_text) => { "coalesce(string_agg(_subzero_t.subzero_scalar::text, ''), '')" };
+    // the body is returned as text so binary values are base64 encoded and decoded again before being sent to the client
+    (function_scalar_binary) => { "coalesce(encode(string_agg(_subzero_t.subzero_scalar, ''::bytea), 'base64'), '')" };
     (json_array) => { "coalesce(json_agg(_subzero_t), '[]')::character varying" };
     (json_object) => { "coalesce((json_agg(_subzero_t)->0)::text, 'null')" };
+    // ST_AsGeoJSON(record) uses the first geometry column as the feature geometry and the rest of the columns as properties
+    (geojson) => {
+        "json_build_object('type', 'FeatureCollection', 'features', coalesce(json_agg(ST_AsGeoJSON(_subzero_t)::json), '[]'))::character varying"
+    };
     (csv) => {
         r#"
             (SELECT coalesce(string_agg(a.k, ','), '')
@@ -22,12 +22,10 @@ use std::collections::HashMap;
 
 use super::base::{
     cast_select_item_format, fmt_condition, fmt_field, fmt_filter, fmt_identity, fmt_json_path, fmt_limit, fmt_logic_operator, fmt_offset,
-    fmt_operator, fmt_order, fmt_order_term, fmt_groupby, fmt_groupby_term, fmt_qi, fmt_select_item, fmt_select_name, return_representation,
-    simple_select_item_format, star_select_item_format, fmt_select_item_function, fmt_function_call, fmt_main_query,
-};
-use crate::api::{
-    Condition::*, Filter::*, Join::*, JsonOperand::*, JsonOperation::*, LogicOperator::*, QueryNode::*, SelectItem::*, *, ContentType::SingularJSON,
+    fmt_operator, fmt_order, fmt_groupby, fmt_groupby_term, fmt_qi, fmt_select_item, fmt_select_name, return_representation,
+    simple_select_item_format, star_select_item_format, fmt_select_item_function, fmt_function_call, fmt_main_query, fmt_quant_filter,
 };
+use crate::api::{Condition::*, ContentType::*, Filter::*, Join::*, JsonOperand::*, JsonOperation::*, LogicOperator::*, QueryNode::*, SelectItem::*, *};
 use crate::schema::DbSchema;
 use crate::dynamic_statement::{param, sql, JoinIterator, SqlSnippet, SqlSnippetChunk, generate_fn};
 use crate::error::{Result, *};
@@ -54,19 +52,26 @@ macro_rules! param_placeholder_format {
 generate_fn!(true, "String");
 
 fmt_main_query!();
+//fmt_explain!();
+// the plan output is returned as a single column named explain, there is no analyze in clickhouse
+fn fmt_explain<'a>(format: &PlanFormat, options: &[PlanOption]) -> Snippet<'a> {
+    let json = matches!(format, PlanFormat::Json) as u8;
+    let verbose = options.contains(&PlanOption::Verbose) as u8;
+    sql(format!("explain plan json={json}, actions={verbose}, indexes={verbose} "))
+}
 pub fn fmt_main_query_internal<'a>(
-    _db_schema: &DbSchema<'a>, schema: &'a str, method: &'a str, accept_content_type: &ContentType, query: &'a Query,
+    _db_schema: &DbSchema<'a>, schema: &'a str, method: &'a str, _accept_content_type: &ContentType, query: &'a Query,
     preferences: &'a Option<Preferences>, env: &'a HashMap<&'a str, &'a str>,
 ) -> Result<Snippet<'a>> {
-    let _count = match preferences {
+    // the total is read from rows_before_limit_at_least in the JSON output statistics
+    // which is exact only when exact_rows_before_limit is set
+    let exact_count = matches!(
+        preferences,
         Some(Preferences {
             count: Some(Count::ExactCount),
             ..
-        }) => Err(Error::InternalError {
-            message: "not implemented yet for clickhouse".to_string(),
-        })?, //true,
-        _ => false,
-    };
+        })
+    );
 
     let return_representation = return_representation(method, query, preferences);
     let has_payload_cte = matches!(query.node, Insert { .. } | Update { .. });
@@ -82,13 +87,10 @@ pub fn fmt_main_query_internal<'a>(
             &None,
         )?
         + "
-        format JSONEachRow
+        format JSON
         settings 
         "
-        + match accept_content_type {
-            SingularJSON => "",
-            _ => "output_format_json_array_of_rows=1,",
-        }
+        + if exact_count { "exact_rows_before_limit=1," } else { "" }
         + "
         join_use_nulls=1,
         output_format_json_named_tuples_as_objects=1
@@ -284,6 +286,33 @@ macro_rules! fmt_in_filter {
         "in " + param($p)
     };
 }
+fn fmt_operator_term<'a>(fld: String, o: &'a Operator<'a>, v: &'a SqlParam) -> Result<Snippet<'a>> {
+    Ok(match *o {
+        "match" => sql(format!("match({}, ", fld)) + param(v) + ")",
+        "imatch" => sql(format!("match({}, concat('(?i)', ", fld)) + param(v) + "))",
+        _ => fld + " " + fmt_operator(o)?.as_str() + param(v),
+    })
+}
+//fmt_filter_expression!();
+fn fmt_filter_expression<'a>(fld: String, f: &'a Filter<'a>) -> Result<Snippet<'a>> {
+    // array values are passed as json and converted to the type of the column
+    Ok(match f {
+        Op("isdistinct", v) => sql(format!("({} is null or {} != ", fld, fld)) + param(v as &SqlParam) + ")",
+        Op(o @ ("match" | "imatch"), v) => fmt_operator_term(fld, o, v)?,
+        Quant(o, q, ListVal(l, _)) => fmt_quant_filter!(fld, o, q, l),
+        Op("cs", v) => sql(format!("hasAll({}, JSONExtract(", fld)) + param(v as &SqlParam) + format!(", toTypeName({})))", fld),
+        Op("cd", v) => "hasAll(JSONExtract(" + param(v as &SqlParam) + format!(", toTypeName({})), {})", fld, fld),
+        Op("ov", v) => sql(format!("hasAny({}, JSONExtract(", fld)) + param(v as &SqlParam) + format!(", toTypeName({})))", fld),
+        // hasToken matches a single token, the language is ignored
+        Fts("fts", _, v) => sql(format!("hasToken({}, ", fld)) + param(v as &SqlParam) + ")",
+        Op(o, _) | Fts(o, _, _) => {
+            return Err(Error::UnsupportedFeature {
+                message: format!("the {} operator is not supported in clickhouse", o),
+            })
+        }
+        _ => fld + " " + fmt_filter(f)?,
+    })
+}
 fn fmt_env_var(e: &'_ EnvVar) -> String {
     match e {
         EnvVar { var, part: None } => format!("(select {} from env)", fmt_identity(var)),
@@ -312,7 +341,23 @@ fn fmt_function_param<'a, 'b>(qi: &'b Qi<'b>, p: &'a FunctionParam) -> Result<Sn
 }
 //fmt_sub_select_item!();
 fn fmt_sub_select_item<'a, 'b>(schema: &'a str, qi: &'b Qi<'b>, i: &'a SubSelect) -> Result<(Snippet<'a>, Vec<Snippet<'a>>)> {
-    let SubSelect { query, alias, join, .. } = i;
+    let SubSelect {
+        query,
+        alias,
+        join,
+        spread,
+        join_type,
+        ..
+    } = i;
+    let join_kind = match (join_type, join) {
+        (JoinType::Left, _) => "left join (",
+        (JoinType::Inner, Some(Parent(_) | Child(_))) => "inner join (",
+        _ => {
+            return Err(Error::UnsupportedFeature {
+                message: "filtering by the presence of this embed is not supported in clickhouse".to_string(),
+            })
+        }
+    };
     if let Some(j) = join {
         let subselect_columns = query
             .node
@@ -339,6 +384,36 @@ fn fmt_sub_select_item<'a, 'b>(schema: &'a str, qi: &'b Qi<'b>, i: &'a SubSelect
         );
 
         match j {
+            Parent(ForeignKey {
+                referenced_table: Qi(_, name),
+                ..
+            })
+            | Child(ForeignKey { table: Qi(_, name), .. })
+                if *spread =>
+            {
+                let _local_table_name = format!("{}_{}", qi.1, alias.as_ref().unwrap_or(name));
+                let local_table_name = _local_table_name.as_str();
+                let subquery = fmt_query(schema, true, None, query, join)?;
+                Ok((
+                    sql(subselect_columns
+                        .iter()
+                        .map(|i| format!("any(\"{local_table_name}\".\"{i}\") as {}", fmt_identity(i)))
+                        .collect::<Vec<_>>()
+                        .join(", ")),
+                    vec![
+                        join_kind
+                            + subquery
+                            + ") as "
+                            + sql(fmt_identity(local_table_name))
+                            + " on "
+                            + join_conditions
+                                .iter()
+                                .map(|c| fmt_condition(&Qi("", local_table_name), c))
+                                .collect::<Result<Vec<_>>>()?
+                                .join(join_separator),
+                    ],
+                ))
+            }
             Parent(fk) => {
                 let alias_or_name = alias.as_ref().unwrap_or(&fk.referenced_table.1);
                 let _local_table_name = format!("{}_{}", qi.1, alias_or_name);
@@ -369,7 +444,7 @@ fn fmt_sub_select_item<'a, 'b>(schema: &'a str, qi: &'b Qi<'b>, i: &'a SubSelect
                         + fmt_identity(alias_or_name),
                     //vec!["left join lateral (" + subquery + ") as " + sql(fmt_identity(&local_table_name)) + " on true"],
                     vec![
-                        "left join ("
+                        join_kind
                             + subquery
                             + ") as "
                             + sql(fmt_identity(local_table_name))
@@ -409,7 +484,7 @@ fn fmt_sub_select_item<'a, 'b>(schema: &'a str, qi: &'b Qi<'b>, i: &'a SubSelect
                         + ") as "
                         + fmt_identity(alias_or_name),
                     vec![
-                        "left join ("
+                        join_kind
                             + subquery
                             + ") as "
                             + sql(fmt_identity(local_table_name))
@@ -422,6 +497,9 @@ fn fmt_sub_select_item<'a, 'b>(schema: &'a str, qi: &'b Qi<'b>, i: &'a SubSelect
                     ],
                 ))
             }
+            Computed(_) => Err(Error::UnsupportedFeature {
+                message: "computed relationships are not supported in clickhouse".to_string(),
+            }),
             Many(_table, _fk1, fk2) => {
                 let alias_or_name = fmt_identity(alias.as_ref().unwrap_or(&fk2.referenced_table.1));
                 let local_table_name = fmt_identity(fk2.referenced_table.1);
@@ -449,7 +527,25 @@ fmt_identity!();
 fmt_qi!();
 fmt_field!();
 fmt_order!();
-fmt_order_term!();
+//fmt_order_term!();
+fn fmt_order_term<'a, 'b>(_qi: &'b Qi<'b>, t: &'a OrderTerm<'a>) -> Result<Snippet<'a>> {
+    if t.relation.is_some() {
+        return Err(Error::UnsupportedFeature {
+            message: "ordering by a related column is not supported in clickhouse".to_string(),
+        });
+    }
+    let direction = match &t.direction {
+        None => "",
+        Some(OrderDirection::Asc) => "asc",
+        Some(OrderDirection::Desc) => "desc",
+    };
+    let nulls = match &t.null_order {
+        None => "",
+        Some(OrderNulls::NullsFirst) => "nulls first",
+        Some(OrderNulls::NullsLast) => "nulls last",
+    };
+    Ok(sql([fmt_field(&Qi("", ""), &t.term)?.as_str(), direction, nulls].join(" ")))
+}
 fmt_groupby!();
 fmt_groupby_term!();
 //fmt_as!();
@@ -488,7 +584,6 @@ mod tests {
     use crate::dynamic_statement::{generate_fn, SqlSnippet, SqlSnippetChunk};
     use pretty_assertions::assert_eq;
     use regex::Regex;
-    use crate::api::ContentType::*;
     use super::*;
     use std::borrow::Cow;
     fn cow(s: &str) -> Cow<str> {
@@ -596,6 +691,8 @@ mod tests {
                     },
                     alias: Some(s("client")),
                     hint: None,
+                    spread: false,
+                    join_type: JoinType::Left,
                     join: Some(Parent(ForeignKey {
                         name: "client_id_fk",
                         table: Qi("default", "projects"),
@@ -671,6 +768,8 @@ mod tests {
                         sub_selects: vec![],
                     },
                     hint: None,
+                    spread: false,
+                    join_type: JoinType::Left,
                     alias: None,
                     join: Some(Child(ForeignKey {
                         name: "project_id_fk",
@@ -737,10 +836,9 @@ mod tests {
         let expected_main_query_str = format!(
             r#"with env as (select null)
         {expected_query_str}
-        format JSONEachRow
+        format JSON
         settings 
         
-        output_format_json_array_of_rows=1,
         join_use_nulls=1,
         output_format_json_named_tuples_as_objects=1
         
@@ -750,5 +848,15 @@ mod tests {
         let db_schema: DbSchema = serde_json::from_str("{\"use_internal_permissions\": false, \"schemas\":[]}").unwrap();
         let (main_query_str, _parameters, _) = generate(fmt_main_query(&db_schema, "default", &api_request, &HashMap::new()).unwrap());
         assert_eq!(re.replace_all(main_query_str.as_str(), " "), re.replace_all(expected_main_query_str.as_str(), " "));
+
+        let api_request = ApiRequest {
+            preferences: Some(Preferences {
+                count: Some(Count::ExactCount),
+                ..Default::default()
+            }),
+            ..api_request
+        };
+        let (main_query_str, _parameters, _) = generate(fmt_main_query(&db_schema, "default", &api_request, &HashMap::new()).unwrap());
+        assert!(main_query_str.contains("exact_rows_before_limit=1,"));
     }
 }
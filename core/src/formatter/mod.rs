@@ -75,6 +75,15 @@ impl<'a> ToParam for &'a str {
     }
 }
 
+impl<'a> ToParam for Cow<'a, str> {
+    fn to_param(&self) -> Param {
+        Param::Str(self.as_ref())
+    }
+    fn to_data_type(&self) -> &Option<Cow<str>> {
+        &Some(Cow::Borrowed("text"))
+    }
+}
+
 impl ToParam for String {
     fn to_param(&self) -> Param {
         Param::StrOwned(self)
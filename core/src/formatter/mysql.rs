@@ -15,9 +15,9 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program. If not, see <http://www.gnu.org/licenses/>.
 use super::base::{
-    fmt_as, fmt_condition, fmt_condition_tree, fmt_count_query, fmt_field, fmt_filter, fmt_in_filter, fmt_json_path, fmt_limit, fmt_logic_operator,
-    fmt_main_query, fmt_offset, fmt_operator, fmt_order, fmt_order_term, fmt_groupby, fmt_groupby_term, fmt_qi, fmt_select_item, fmt_select_name,
-    star_select_item_format, fmt_function_param, fmt_env_query, get_body_snippet, fmt_function_call,
+    fmt_as, fmt_condition, fmt_condition_tree, fmt_count_query, fmt_where, fmt_field, fmt_filter, fmt_in_filter, fmt_json_path, fmt_limit,
+    fmt_logic_operator, fmt_main_query, fmt_offset, fmt_operator, fmt_order, fmt_order_term, fmt_groupby, fmt_groupby_term, fmt_qi, fmt_select_name,
+    star_select_item_format, fmt_function_param, fmt_env_query, get_body_snippet, fmt_function_call, fmt_quant_filter,
 };
 use itertools::Itertools;
 pub use super::base::return_representation;
@@ -36,16 +36,6 @@ lazy_static! {
         .collect();
 }
 
-macro_rules! simple_select_item_format {
-    () => {
-        "'{select_name}', {field}{as:.0}"
-    };
-}
-macro_rules! cast_select_item_format {
-    () => {
-        "'{select_name}', cast({field} as {cast}){as:.0}"
-    };
-}
 macro_rules! fmt_field_format {
     () => {
         //"to_jsonb({}{}{}){}"
@@ -69,6 +59,9 @@ macro_rules! body_snippet {
     (csv) => {
         "''"
     }; // TODO!! unimplemented
+    (geojson) => {
+        "''"
+    }; // rejected in fmt_main_query_internal
     (function_scalar) => {
         "''"
     }; //TODO!! unimplemented
@@ -78,10 +71,25 @@ macro_rules! body_snippet {
     (function_any) => {
         "''"
     }; //TODO!! unimplemented
+    (function_scalar_text) => {
+        "''"
+    }; // rejected in fmt_main_query_internal
+    (function_scalar_binary) => {
+        "''"
+    }; // rejected in fmt_main_query_internal
 }
 
 generate_fn!();
 fmt_main_query!();
+//fmt_explain!();
+// analyze runs the query and reports the plan only in the tree format (json with analyze is rejected in the backend)
+fn fmt_explain<'a>(format: &PlanFormat, options: &[PlanOption]) -> Snippet<'a> {
+    match (format, options.contains(&PlanOption::Analyze)) {
+        (_, true) => sql("explain analyze "),
+        (PlanFormat::Json, false) => sql("explain format=json "),
+        (PlanFormat::Text, false) => sql("explain format=tree "),
+    }
+}
 pub fn fmt_main_query_internal<'a>(
     db_schema: &'a DbSchema<'_>, schema: &'a str, method: &'a str, accept_content_type: &ContentType, query: &'a Query,
     preferences: &'a Option<Preferences>, env: &'a HashMap<&'a str, &'a str>,
@@ -95,6 +103,19 @@ pub fn fmt_main_query_internal<'a>(
     );
     let check_constraints = matches!(query.node, Insert { .. } | Update { .. } | Select { check: Some(_), .. });
     let return_representation = return_representation(method, query, preferences);
+    match (return_representation, accept_content_type) {
+        (true, GeoJSON) => {
+            return Err(Error::UnsupportedFeature {
+                message: "GeoJSON output is not supported in mysql".to_string(),
+            })
+        }
+        (true, TextPlain | TextXML | OctetStream) => {
+            return Err(Error::UnsupportedFeature {
+                message: "raw output of functions is not supported in mysql".to_string(),
+            })
+        }
+        _ => {}
+    }
     let body_snippet = get_body_snippet!(return_representation, accept_content_type, query)?;
     let run_unwrapped_query = matches!(query.node, Insert { .. } | Update { .. } | Delete { .. });
     //let has_payload_cte = matches!(query.node, Insert { .. } | Update { .. });
@@ -327,11 +348,7 @@ pub fn fmt_query<'a>(
                     + " "
                     + joins.into_iter().flatten().collect::<Vec<_>>().join(" ")
                     + " "
-                    + if !where_.conditions.is_empty() {
-                        "where " + fmt_condition_tree(qi, where_)?
-                    } else {
-                        sql("")
-                    }
+                    + fmt_where(qi, where_, fmt_embed_conditions(db_schema, schema, q)?)?
                     + " "
                     + (fmt_groupby(qi, groupby)?)
                     + (fmt_order(qi, order)?)
@@ -644,6 +661,21 @@ pub fn fmt_query<'a>(
 }
 fmt_env_query!();
 fmt_count_query!();
+fmt_where!();
+//fmt_embed_conditions!();
+fn fmt_embed_conditions<'a>(db_schema: &DbSchema<'a>, schema: &'a str, q: &'a Query) -> Result<Vec<Snippet<'a>>> {
+    q.sub_selects
+        .iter()
+        .filter(|s| s.join_type != JoinType::Left)
+        .map(|SubSelect { query, join, join_type, .. }| {
+            Ok(match join_type {
+                JoinType::Anti => sql("not exists ("),
+                _ => sql("exists ("),
+            } + fmt_query(db_schema, schema, true, None, query, join, None)?
+                + ")")
+        })
+        .collect()
+}
 //fmt_body!();
 fn fmt_body<'a>(payload: &'a Payload, columns: &[&'a str]) -> Snippet<'a> {
     let payload_param: &SqlParam = payload;
@@ -678,15 +710,42 @@ macro_rules! fmt_in_filter {
     };
 }
 fmt_filter!();
+fn fmt_operator_term<'a>(fld: String, o: &'a Operator<'a>, v: &'a SqlParam) -> Result<Snippet<'a>> {
+    Ok(match *o {
+        "match" => sql(format!("regexp_like({}, ", fld)) + param(v) + ", 'c')",
+        "imatch" => sql(format!("regexp_like({}, ", fld)) + param(v) + ", 'i')",
+        _ => fld + " " + fmt_operator(o)?.as_str() + param(v),
+    })
+}
+//fmt_filter_expression!();
+fn fmt_filter_expression<'a>(fld: String, f: &'a Filter<'a>) -> Result<Snippet<'a>> {
+    Ok(match f {
+        Op("isdistinct", v) => sql(format!("not({} <=> ", fld)) + param(v as &SqlParam) + ")",
+        Op(o @ ("match" | "imatch"), v) => fmt_operator_term(fld, o, v)?,
+        Quant(o, q, ListVal(l, _)) => fmt_quant_filter!(fld, o, q, l),
+        Op("cs", v) => sql(format!("json_contains({}, ", fld)) + param(v as &SqlParam) + ")",
+        Op("cd", v) => "json_contains(" + param(v as &SqlParam) + ", " + fld + ")",
+        Op("ov", v) => sql(format!("json_overlaps({}, ", fld)) + param(v as &SqlParam) + ")",
+        // full text search needs a FULLTEXT index on the column, the language is ignored
+        Fts("fts", _, v) => sql(format!("match({}) against (", fld)) + param(v as &SqlParam) + " in boolean mode)",
+        Fts("plfts" | "wfts", _, v) => sql(format!("match({}) against (", fld)) + param(v as &SqlParam) + " in natural language mode)",
+        Fts("phfts", _, v) => sql(format!("match({}) against (concat('\"', ", fld)) + param(v as &SqlParam) + ", '\"') in boolean mode)",
+        Op(o, _) | Fts(o, _, _) => {
+            return Err(Error::UnsupportedFeature {
+                message: format!("the {} operator is not supported in mysql", o),
+            })
+        }
+        _ => fld + " " + fmt_filter(f)?,
+    })
+}
 fmt_select_name!();
 fmt_function_call!();
 //fmt_select_item_function!();
 fn fmt_select_item_function<'a, 'b>(
     qi: &'b Qi<'b>, fn_name: &'a str, parameters: &'a [FunctionParam<'a>], partitions: &'a [Field<'a>], orders: &'a [OrderTerm],
-    alias: &'a Option<&str>,
+    alias: &'a Option<&str>, cast: &'a Option<&str>,
 ) -> Result<Snippet<'a>> {
-    Ok(format!("'{}',", fmt_select_name(fn_name, &None, alias).unwrap_or_default().as_str())
-        + fmt_function_call(qi, fn_name, parameters)?
+    let call = fmt_function_call(qi, fn_name, parameters)?
         + if partitions.is_empty() && orders.is_empty() {
             sql("")
         } else {
@@ -697,26 +756,74 @@ fn fmt_select_item_function<'a, 'b>(
                     sql("partition by ") + partitions.iter().map(|p| fmt_field(qi, p)).collect::<Result<Vec<_>>>()?.join(",")
                 }
                 + " "
-                + if orders.is_empty() { "".to_string() } else { fmt_order(qi, orders)? }
+                + if orders.is_empty() { sql("") } else { fmt_order(qi, orders)? }
                 + " )"
+        };
+    Ok(format!("{},", fmt_string(&fmt_select_name(fn_name, &None, alias).unwrap_or_default()))
+        + match cast {
+            Some(c) => sql("cast(") + call + format!(" as {})", c),
+            None => call,
         })
 }
-fmt_select_item!();
+//fmt_select_item!();
+// every item is a key and a value of the json object built for the row
+fn fmt_select_item<'a, 'b>(qi: &'b Qi<'b>, i: &'a SelectItem) -> Result<Snippet<'a>> {
+    match i {
+        Star => Ok(sql(format!(star_select_item_format!(), fmt_qi(qi)))),
+        Simple {
+            field: field @ Field { name, json_path },
+            alias,
+            cast,
+        } => {
+            let select_name = fmt_string(&fmt_select_name(name, json_path, alias).unwrap_or_default());
+            Ok(sql(match cast {
+                Some(c) => format!("{}, cast({} as {})", select_name, fmt_field(qi, field)?, c),
+                None => format!("{}, {}", select_name, fmt_field(qi, field)?),
+            }))
+        }
+        Func {
+            alias,
+            fn_name,
+            parameters,
+            partitions,
+            orders,
+            cast,
+        } => fmt_select_item_function(qi, fn_name, parameters, partitions, orders, alias, cast),
+    }
+}
 fmt_function_param!();
 //fmt_sub_select_item!();
 fn fmt_sub_select_item<'a, 'b>(
     db_schema: &DbSchema<'a>, schema: &'a str, qi: &'b Qi<'b>, i: &'a SubSelect,
 ) -> Result<(Snippet<'a>, Vec<Snippet<'a>>)> {
-    let SubSelect { query, alias, join, .. } = i;
+    let SubSelect {
+        query, alias, join, spread, ..
+    } = i;
     match join {
         Some(j) => match j {
+            Parent(ForeignKey {
+                referenced_table: Qi(_, name),
+                ..
+            })
+            | Child(ForeignKey { table: Qi(_, name), .. })
+                if *spread =>
+            {
+                let local_table_name = format!("{}_{}", qi.1, alias.as_ref().unwrap_or(name));
+                let subquery = fmt_query(db_schema, schema, true, None, query, join, None)?;
+                let pairs = fmt_row_keys(query)?
+                    .iter()
+                    .map(|k| format!("{}, json_extract({}.row_, {})", fmt_string(k), fmt_identity(&local_table_name), fmt_string(&fmt_key_path(k))))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                Ok((sql(pairs), vec!["left join lateral (" + subquery + ") as " + sql(fmt_identity(&local_table_name)) + " on true"]))
+            }
             Parent(fk) => {
                 let alias_or_name = alias.as_ref().unwrap_or(&fk.referenced_table.1);
                 let local_table_name = format!("{}_{}", qi.1, alias_or_name);
                 let subquery = fmt_query(db_schema, schema, true, None, query, join, None)?;
 
                 Ok((
-                    sql(format!("'{}', {}.row_", alias_or_name, fmt_identity(&local_table_name))),
+                    sql(format!("{}, {}.row_", fmt_string(alias_or_name), fmt_identity(&local_table_name))),
                     vec!["left join lateral (" + subquery + ") as " + sql(fmt_identity(&local_table_name)) + " on true"],
                 ))
             }
@@ -725,7 +832,7 @@ fn fmt_sub_select_item<'a, 'b>(
                 let local_table_name = fmt_identity(fk.table.1);
                 let subquery = fmt_query(db_schema, schema, true, None, query, join, None)?;
                 Ok((
-                    (format!("'{alias_or_name}', coalesce((select json_arrayagg(")
+                    (format!("{}, coalesce((select json_arrayagg(", fmt_string(alias_or_name))
                         + sql(local_table_name.clone())
                         + ".row_) from ("
                         + subquery
@@ -735,12 +842,15 @@ fn fmt_sub_select_item<'a, 'b>(
                     vec![],
                 ))
             }
+            Computed(_) => Err(Error::UnsupportedFeature {
+                message: "computed relationships are not supported in mysql".to_string(),
+            }),
             Many(_table, _fk1, fk2) => {
                 let alias_or_name = alias.as_ref().unwrap_or(&fk2.referenced_table.1);
                 let local_table_name = fmt_identity(fk2.referenced_table.1);
                 let subquery = fmt_query(db_schema, schema, true, None, query, join, None)?;
                 Ok((
-                    (format!("'{alias_or_name}', coalesce((select json_arrayagg(")
+                    (format!("{}, coalesce((select json_arrayagg(", fmt_string(alias_or_name))
                         + sql(local_table_name.clone())
                         + ".row_) from ("
                         + subquery
@@ -754,6 +864,50 @@ fn fmt_sub_select_item<'a, 'b>(
         None => panic!("unable to format join query without matching relation"),
     }
 }
+// a string literal, the names of the selected columns can contain quotes
+fn fmt_string(s: &str) -> String {
+    format!("'{}'", s.replace('\\', "\\\\").replace('\'', "''"))
+}
+// the json path of a key of an object
+fn fmt_key_path(k: &str) -> String {
+    format!("$.\"{}\"", k.replace('\\', "\\\\").replace('"', "\\\""))
+}
+// the keys of the json object built as row_ for a query
+fn fmt_row_keys(query: &Query) -> Result<Vec<String>> {
+    let Query { node, sub_selects } = query;
+    let select = match node {
+        Select { select, .. } => select,
+        _ => return Ok(vec![]),
+    };
+    let mut keys = select
+        .iter()
+        .map(|i| match i {
+            Star => Err(Error::UnsupportedFeature {
+                message: "select * is not supported in spread embeds in mysql".to_string(),
+            }),
+            Simple {
+                field: Field { name, json_path },
+                alias,
+                ..
+            } => Ok(fmt_select_name(name, json_path, alias).unwrap_or_default()),
+            Func { fn_name, alias, .. } => Ok(fmt_select_name(fn_name, &None, alias).unwrap_or_default()),
+        })
+        .collect::<Result<Vec<_>>>()?;
+    for SubSelect {
+        query, alias, join, spread, ..
+    } in sub_selects
+    {
+        match join {
+            Some(_) if *spread => keys.extend(fmt_row_keys(query)?),
+            Some(Parent(fk)) => keys.push(alias.as_ref().unwrap_or(&fk.referenced_table.1).to_string()),
+            Some(Child(fk)) => keys.push(alias.as_ref().unwrap_or(&fk.table.1).to_string()),
+            Some(Many(_, _, fk2)) => keys.push(alias.as_ref().unwrap_or(&fk2.referenced_table.1).to_string()),
+            Some(Computed(r)) => keys.push(alias.as_ref().unwrap_or(&r.function.1).to_string()),
+            None => {}
+        }
+    }
+    Ok(keys)
+}
 fmt_operator!();
 fmt_logic_operator!();
 //fmt_identity!();
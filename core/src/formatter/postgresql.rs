@@ -19,7 +19,8 @@ use super::base::{
     fmt_env_var, fmt_identity, fmt_in_filter, fmt_json_operand, fmt_json_operation, fmt_json_path, fmt_limit, fmt_logic_operator, fmt_main_query,
     fmt_main_query_internal, fmt_offset, fmt_operator, fmt_order, fmt_order_term, fmt_groupby, fmt_groupby_term, fmt_qi, fmt_query, fmt_select_item,
     fmt_select_name, fmt_sub_select_item, return_representation, simple_select_item_format, star_select_item_format, fmt_function_param,
-    fmt_select_item_function, fmt_function_call, fmt_env_query, body_snippet, get_body_snippet,
+    fmt_select_item_function, fmt_function_call, fmt_env_query, body_snippet, get_body_snippet, fmt_filter_expression, fmt_where,
+    fmt_embed_conditions, fmt_explain,
 };
 use itertools::Itertools;
 use crate::schema::DbSchema;
@@ -32,11 +33,36 @@ use crate::error::{Result, Error};
 use super::{ToParam, Snippet, SqlParam};
 
 lazy_static! {
-    pub static ref SUPPORTED_OPERATORS: HashSet<&'static str> =
-        ["eq", "gte", "gt", "lte", "lt", "neq", "like", "ilike", "in", "is", "cs", "cd", "ov", "sl", "sr", "nxr", "nxl", "adj"]
-            .iter()
-            .copied()
-            .collect();
+    pub static ref SUPPORTED_OPERATORS: HashSet<&'static str> = [
+        "eq",
+        "gte",
+        "gt",
+        "lte",
+        "lt",
+        "neq",
+        "like",
+        "ilike",
+        "match",
+        "imatch",
+        "in",
+        "is",
+        "isdistinct",
+        "cs",
+        "cd",
+        "ov",
+        "sl",
+        "sr",
+        "nxr",
+        "nxl",
+        "adj",
+        "fts",
+        "plfts",
+        "phfts",
+        "wfts",
+    ]
+    .iter()
+    .copied()
+    .collect();
 }
 macro_rules! param_placeholder_format {
     () => {
@@ -47,12 +73,53 @@ macro_rules! param_placeholder_format {
 generate_fn!();
 fmt_main_query_internal!();
 fmt_main_query!();
+fmt_explain!();
+
+// every row of the result is returned separately (as json or a csv line) so the body can be streamed to the client
+// the csv header is prepended to the first row or returned alone when there are no rows
+pub fn fmt_stream_query<'a>(
+    db_schema: &'a DbSchema<'_>, schema: &'a str, request: &'a ApiRequest, env: &'a HashMap<&'a str, &'a str>,
+) -> Result<Snippet<'a>> {
+    let (row, no_rows) = match request.accept_content_type {
+        ApplicationJSON => ("row_to_json(_subzero_t)::text", ""),
+        TextCSV => (
+            r#"
+            case when row_number() over () = 1
+                then (select string_agg(k, ',') from json_object_keys(row_to_json(_subzero_t)) as k) || chr(10)
+                else ''
+            end
+            || substring(_subzero_t::text, 2, length(_subzero_t::text) - 2)
+            "#,
+            // the left join yields a row of nulls that still has the names of the columns
+            r#"
+            union all
+            select (select string_agg(k, ',') from json_object_keys(row_to_json(_subzero_h)) as k)
+            from ( select (_subzero_e).* from ( select null ) _subzero_d left join _subzero_query _subzero_e on true ) _subzero_h
+            where not exists ( select 1 from _subzero_query )"#,
+        ),
+        _ => {
+            return Err(Error::ContentTypeError {
+                message: "Only JSON and CSV responses can be streamed".to_string(),
+            })
+        }
+    };
+    Ok(sql("with")
+        + " env as materialized ("
+        + fmt_env_query(env)
+        + ")"
+        + " , "
+        + fmt_query(db_schema, schema, true, Some("_subzero_query"), &request.query, &None)?
+        + format!(" select {row} as row from ( select * from _subzero_query ) _subzero_t{no_rows}"))
+}
 fmt_query!();
 fmt_env_query!();
 fmt_count_query!();
+fmt_where!();
+fmt_embed_conditions!();
 fmt_body!();
 fmt_condition_tree!();
 fmt_condition!();
+fmt_filter_expression!();
 fmt_env_var!();
 fmt_filter!();
 fmt_select_name!();
@@ -237,6 +304,8 @@ mod tests {
                     },
                     alias: None,
                     hint: None,
+                    spread: false,
+                    join_type: JoinType::Left,
                     join: Some(Parent(ForeignKey {
                         name: "client_id_fk",
                         table: Qi("api", "projects"),
@@ -302,6 +371,8 @@ mod tests {
                         sub_selects: vec![],
                     },
                     hint: None,
+                    spread: false,
+                    join_type: JoinType::Left,
                     alias: None,
                     join: Some(Child(ForeignKey {
                         name: "project_id_fk",
@@ -469,6 +540,8 @@ mod tests {
                     },
                     alias: None,
                     hint: None,
+                    spread: false,
+                    join_type: JoinType::Left,
                     join: Some(Parent(ForeignKey {
                         name: "client_id_fk",
                         table: Qi("api", "projects"),
@@ -534,6 +607,8 @@ mod tests {
                         sub_selects: vec![],
                     },
                     hint: None,
+                    spread: false,
+                    join_type: JoinType::Left,
                     alias: None,
                     join: Some(Child(ForeignKey {
                         name: "project_id_fk",
@@ -590,6 +665,139 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_fmt_stream_query() {
+        let db_schema: DbSchema = serde_json::from_str(r#"{"schemas":[{"name":"api","objects":[{"kind":"table","name":"projects","columns":[{"name":"id","data_type":"int"}],"foreign_keys":[]}]}]}"#).unwrap();
+        let mut headers = HashMap::new();
+        headers.insert("accept", "text/csv");
+        let request = crate::parser::postgrest::parse(
+            "api",
+            "projects",
+            &db_schema,
+            "GET",
+            "/projects",
+            vec![("select", "id")],
+            None,
+            headers,
+            HashMap::new(),
+            None,
+        )
+        .unwrap();
+        let env = HashMap::new();
+        let (query_str, _, _) = generate(fmt_stream_query(&db_schema, "api", &request, &env).unwrap());
+        let re = Regex::new(r"\s+").unwrap();
+        assert_eq!(
+            re.replace_all(query_str.as_str(), " "),
+            re.replace_all(
+                r#"with env as materialized (select null) ,
+                _subzero_query as ( select "api"."projects"."id" from "api"."projects", env )
+                select
+                    case when row_number() over () = 1
+                        then (select string_agg(k, ',') from json_object_keys(row_to_json(_subzero_t)) as k) || chr(10)
+                        else ''
+                    end
+                    || substring(_subzero_t::text, 2, length(_subzero_t::text) - 2)
+                as row from ( select * from _subzero_query ) _subzero_t
+                union all
+                select (select string_agg(k, ',') from json_object_keys(row_to_json(_subzero_h)) as k)
+                from ( select (_subzero_e).* from ( select null ) _subzero_d left join _subzero_query _subzero_e on true ) _subzero_h
+                where not exists ( select 1 from _subzero_query )"#,
+                " "
+            )
+        );
+    }
+
+    #[test]
+    fn test_fmt_explain_query() {
+        let db_schema: DbSchema = serde_json::from_str(r#"{"schemas":[{"name":"api","objects":[{"kind":"table","name":"projects","columns":[{"name":"id","data_type":"int"}],"foreign_keys":[]}]}]}"#).unwrap();
+        let mut headers = HashMap::new();
+        headers.insert("accept", "application/vnd.pgrst.plan+json; options=analyze|verbose");
+        let request = crate::parser::postgrest::parse(
+            "api",
+            "projects",
+            &db_schema,
+            "GET",
+            "/projects",
+            vec![("select", "id")],
+            None,
+            headers,
+            HashMap::new(),
+            None,
+        )
+        .unwrap();
+        let env = HashMap::new();
+        let (query_str, _, _) = generate(fmt_main_query(&db_schema, "api", &request, &env).unwrap());
+        let plain_request = ApiRequest {
+            accept_content_type: ApplicationJSON,
+            ..request.clone()
+        };
+        let (plain_query_str, _, _) = generate(fmt_main_query(&db_schema, "api", &plain_request, &env).unwrap());
+        assert_eq!(query_str, format!("explain (format json, analyze, verbose) {plain_query_str}"));
+    }
+
+    #[test]
+    fn test_fmt_computed_query() {
+        let db_schema: DbSchema = serde_json::from_str(
+            r#"{
+                "schemas":[{
+                    "name":"api",
+                    "objects":[{
+                        "kind":"table",
+                        "name":"people",
+                        "columns":[
+                            { "name":"id", "data_type":"int", "primary_key":true },
+                            { "name":"first_name", "data_type":"text" }
+                        ],
+                        "foreign_keys":[],
+                        "computed_columns":[
+                            { "name":"full_name", "data_type":"text" }
+                        ],
+                        "computed_relationships":[{
+                            "function":["api","friends"],
+                            "table":["api","people"],
+                            "referenced_table":["api","people"],
+                            "to_one":false
+                        }]
+                    }]
+                }]
+            }"#,
+        )
+        .unwrap();
+        let request = crate::parser::postgrest::parse(
+            "api",
+            "people",
+            &db_schema,
+            "GET",
+            "/people",
+            vec![("select", "id,full_name,name:full_name::text,friends(id)")],
+            None,
+            HashMap::new(),
+            HashMap::new(),
+            None,
+        )
+        .unwrap();
+        let (query_str, _, _) = generate(fmt_query(&db_schema, "api", true, None, &request.query, &None).unwrap());
+        let re = Regex::new(r"\s+").unwrap();
+        assert_eq!(
+            re.replace_all(query_str.as_str(), " "),
+            re.replace_all(
+                r#"
+        select
+            "api"."people"."id",
+            "api"."full_name"("people") as "full_name",
+            cast("api"."full_name"("people") as text) as "name",
+            coalesce((select json_agg("friends".*) from (
+                select
+                    "friends"."id"
+                from "api"."friends"("people") as "friends"
+            ) as "friends"), '[]') as "friends"
+        from "api"."people"
+        "#,
+                " "
+            )
+        );
+    }
+
     #[test]
     fn test_fmt_condition_tree() {
         assert_eq!(
@@ -23,6 +23,7 @@ use super::base::{
     fmt_main_query,
     //fmt_query,
     fmt_count_query,
+    fmt_where,
     fmt_field,
     //fmt_env_var,
     fmt_filter,
@@ -49,8 +50,9 @@ use super::base::{
     //fmt_select_item_function,
     fmt_function_call,
     get_body_snippet,
+    fmt_quant_filter,
 };
-use crate::schema::DbSchema;
+use crate::schema::{DbSchema, Object, ObjectType};
 //use core::fmt;
 use std::collections::{HashMap, HashSet};
 pub use super::base::return_representation;
@@ -59,12 +61,15 @@ use crate::dynamic_statement::{param, sql, JoinIterator, SqlSnippet, SqlSnippetC
 use crate::error::{Result, Error};
 use super::{ToParam, Snippet, SqlParam};
 use std::borrow::Cow;
+use regex::{Captures, Regex};
 
 lazy_static! {
-    pub static ref SUPPORTED_OPERATORS: HashSet<&'static str> = ["eq", "gte", "gt", "lte", "lt", "neq", "like", "ilike", "in", "is"]
+    pub static ref SUPPORTED_OPERATORS: HashSet<&'static str> = ["eq", "gte", "gt", "lte", "lt", "neq", "like", "ilike", "in", "is", "isdistinct"]
         .iter()
         .copied()
         .collect();
+    // string literals and quoted identifiers are matched first so that :name inside them is left alone
+    static ref FUNCTION_BODY_PARAM: Regex = Regex::new(r#"'(?:[^']|'')*'|"(?:[^"]|"")*"|:(\w+)"#).expect("Invalid regex");
 }
 
 macro_rules! param_placeholder_format {
@@ -103,18 +108,32 @@ macro_rules! body_snippet {
     (csv) => {
         "''"
     }; // TODO!! unimplemented
-    (function_scalar) => {
+    (geojson) => {
         "''"
-    }; // unreachable
+    }; // rejected in fmt_main_query_internal
+    (function_scalar) => {
+        "coalesce(json_group_array(json(_subzero_t.row->'$.subzero_scalar'))->0, 'null')"
+    };
     (function_scalar_array) => {
-        "''"
-    }; // unreachable
+        "json_group_array(json(_subzero_t.row->'$.subzero_scalar'))"
+    };
     (function_any) => {
+        "coalesce(json_group_array(json(_subzero_t.row))->0, 'null')"
+    };
+    (function_scalar_text) => {
+        "coalesce(group_concat(_subzero_t.row->>'$.subzero_scalar', ''), '')"
+    };
+    (function_scalar_binary) => {
         "''"
-    }; // unreachable
+    }; // rejected in fmt_main_query_internal
 }
 
 fmt_main_query!();
+//fmt_explain!();
+// sqlite only describes the steps of the plan, the format is built from its rows and the options do not apply
+fn fmt_explain<'a>(_format: &PlanFormat, _options: &[PlanOption]) -> Snippet<'a> {
+    sql("explain query plan ")
+}
 pub fn fmt_main_query_internal<'a>(
     db_schema: &'a DbSchema<'_>, schema: &'a str, method: &'a str, accept_content_type: &ContentType, query: &'a Query,
     preferences: &'a Option<Preferences>, env: &'a HashMap<&'a str, &'a str>,
@@ -128,6 +147,19 @@ pub fn fmt_main_query_internal<'a>(
     );
 
     let return_representation = return_representation(method, query, preferences);
+    match (return_representation, accept_content_type) {
+        (true, GeoJSON) => {
+            return Err(Error::UnsupportedFeature {
+                message: "GeoJSON output is not supported in sqlite".to_string(),
+            })
+        }
+        (true, OctetStream) => {
+            return Err(Error::UnsupportedFeature {
+                message: "binary output of functions is not supported in sqlite".to_string(),
+            })
+        }
+        _ => {}
+    }
     let body_snippet = get_body_snippet!(return_representation, accept_content_type, query)?;
     let run_unwrapped_query = matches!(query.node, Insert { .. } | Update { .. } | Delete { .. });
     let has_payload_cte = matches!(query.node, Insert { .. } | Update { .. });
@@ -188,38 +220,101 @@ pub fn fmt_env_query<'a>(env: &'a HashMap<&'a str, &'a str>) -> Snippet<'a> {
         }
 }
 
+// the json_object projection needs the name of every column so 'select *' is expanded using the columns known from the schema
+fn star_columns<'a>(db_schema: &'a DbSchema<'_>, schema: &str, table: &str) -> Result<Vec<&'a str>> {
+    let object = db_schema
+        .schemas
+        .get(schema)
+        .and_then(|s| s.objects.get(table))
+        .ok_or_else(|| Error::UnknownRelation { relation: table.to_string() })?;
+    Ok(object.columns.keys().copied().collect())
+}
+
+fn local_column_names(select: &[SelectItem], star: impl Fn() -> Result<Vec<String>>) -> Result<Vec<(String, bool)>> {
+    let mut local_columns = vec![];
+    for i in select {
+        match i {
+            Star => local_columns.extend(star()?.into_iter().map(|c| (c, false))),
+            Simple {
+                field: Field { name, json_path },
+                alias,
+                ..
+            } => local_columns.push((fmt_select_name(name, json_path, alias).unwrap_or_default(), json_path.is_some())),
+            Func { alias, fn_name, .. } => local_columns.push((fmt_select_name(fn_name, &None, alias).unwrap_or_default(), false)),
+        }
+    }
+    Ok(local_columns)
+}
+
 //fmt_query!();
-pub fn get_query_select_column_names(query: &Query) -> Option<Vec<(String, bool)>> {
+pub fn get_query_select_column_names(db_schema: &DbSchema<'_>, schema: &str, query: &Query) -> Result<Option<Vec<(String, bool)>>> {
     match &query.node {
-        Select { select, .. } => {
+        Select {
+            select, from: (table, _), ..
+        } => {
             let related_columns = query
                 .sub_selects
                 .iter()
-                .map(|SubSelect { alias, join, .. }| match join {
-                    Some(j) => match j {
-                        Parent(fk) => (alias.as_ref().unwrap_or(&fk.referenced_table.1).to_string(), true),
-                        Child(fk) => (alias.as_ref().unwrap_or(&fk.table.1).to_string(), true),
-                        Many(_table, _fk1, fk2) => (alias.as_ref().unwrap_or(&fk2.referenced_table.1).to_string(), true),
+                .map(
+                    |SubSelect {
+                         query, alias, join, spread, ..
+                     }| match join {
+                        // a spread embed adds its own columns to the row
+                        Some(_) if *spread => Ok(get_query_select_column_names(db_schema, schema, query)?.unwrap_or_default()),
+                        Some(j) => Ok(vec![match j {
+                            Parent(fk) => (alias.as_ref().unwrap_or(&fk.referenced_table.1).to_string(), true),
+                            Child(fk) => (alias.as_ref().unwrap_or(&fk.table.1).to_string(), true),
+                            Many(_table, _fk1, fk2) => (alias.as_ref().unwrap_or(&fk2.referenced_table.1).to_string(), true),
+                            Computed(r) => (alias.as_ref().unwrap_or(&r.function.1).to_string(), true),
+                        }]),
+                        None => Ok(vec![("_unknown_".to_string(), false)]),
                     },
-                    None => ("_unknown_".to_string(), false),
-                })
-                .collect::<Vec<_>>();
-            let local_columns = select
+                )
+                .collect::<Result<Vec<_>>>()?
+                .into_iter()
+                .flatten();
+            let local_columns = local_column_names(select, || Ok(star_columns(db_schema, schema, table)?.into_iter().map(str::to_owned).collect()))?;
+            Ok(Some(local_columns.into_iter().chain(related_columns).collect::<Vec<_>>()))
+        }
+        FunctionCall {
+            fn_name, select, is_scalar, ..
+        } => Ok(Some(local_column_names(select, || match is_scalar {
+            true => Ok(vec!["subzero_scalar".to_owned()]),
+            false => Ok(star_columns(db_schema, fn_name.0, fn_name.1)?.into_iter().map(str::to_owned).collect()),
+        })?)),
+        _ => Ok(None),
+    }
+}
+
+// the function parameters are referenced in the body as :name
+fn fmt_function_body(body: &str, parameters: &[ProcParam]) -> String {
+    FUNCTION_BODY_PARAM
+        .replace_all(body, |c: &Captures| match c.get(1).and_then(|n| parameters.iter().find(|p| p.name == n.as_str())) {
+            Some(p) => format!("(select {} from subzero_args)", fmt_identity(p.name)),
+            None => c[0].to_string(),
+        })
+        .into_owned()
+}
+
+fn fmt_function_args<'a>(payload: &'a Payload, parameters: &[ProcParam]) -> Snippet<'a> {
+    let payload_param: &SqlParam = payload;
+    " subzero_payload as ( select "
+        + param(payload_param)
+        + " as json_data ),"
+        + " subzero_args as ( select "
+        + if parameters.is_empty() {
+            "null".to_string()
+        } else {
+            parameters
                 .iter()
-                .map(|i| match i {
-                    Star => ("*".to_owned(), false),
-                    Simple {
-                        field: Field { name, json_path },
-                        alias,
-                        ..
-                    } => (fmt_select_name(name, json_path, alias).unwrap_or_default(), json_path.is_some()),
-                    Func { alias, fn_name, .. } => (fmt_select_name(fn_name, &None, alias).unwrap_or_default(), false),
+                .map(|p| match p.type_ {
+                    "" => format!("json_extract(json_data, '$.{}') as {}", p.name, fmt_identity(p.name)),
+                    t => format!("cast(json_extract(json_data, '$.{}') as {}) as {}", p.name, t, fmt_identity(p.name)),
                 })
-                .collect::<Vec<_>>();
-            Some(local_columns.into_iter().chain(related_columns).collect::<Vec<_>>())
+                .collect::<Vec<_>>()
+                .join(",")
         }
-        _ => None,
-    }
+        + " from subzero_payload ),"
 }
 
 #[allow(clippy::type_complexity)]
@@ -229,8 +324,78 @@ pub fn fmt_query<'a>(
     let add_env_tbl_to_from = wrapin_cte.is_some();
 
     let (cte_snippet, query_snippet) = match &q.node {
-        FunctionCall {..} => {
-            return Err(Error::UnsupportedFeature { message: "function calls in sqlite not supported".to_string()})
+        // sqlite has no stored procedures, functions are either defined in the schema with a sql body
+        // or they are table-valued functions available on the connection (series, json_each, ...)
+        FunctionCall {
+            fn_name,
+            parameters,
+            payload,
+            is_scalar,
+            is_multiple_call,
+            select,
+            where_,
+            limit,
+            offset,
+            order,
+            ..
+        } => {
+            if *is_multiple_call || !q.sub_selects.is_empty() {
+                return Err(Error::UnsupportedFeature { message: "bulk calls and embedding are not supported for sqlite functions".to_string()})
+            }
+            let (declared_parameters, body) = match db_schema.schemas.get(fn_name.0).and_then(|s| s.objects.get(fn_name.1)) {
+                Some(Object { kind: ObjectType::Function { parameters, body, .. }, .. }) => (parameters.as_slice(), body),
+                _ => return Err(Error::UnknownRelation { relation: fn_name.1.to_string() }),
+            };
+            let parameters = match parameters {
+                CallParams::KeyParams(p) => p,
+                CallParams::OnePosParam(_) => {
+                    return Err(Error::UnsupportedFeature { message: "functions with a single unnamed parameter are not supported in sqlite".to_string()})
+                }
+            };
+            let source = match body {
+                Some(b) => fmt_function_body(b, declared_parameters),
+                None => format!(
+                    "select * from {}({})",
+                    fmt_identity(fn_name.1),
+                    parameters.iter().map(|p| format!("(select {} from subzero_args)", fmt_identity(p.name))).collect::<Vec<_>>().join(", ")
+                ),
+            };
+            let qi_subzero_source = &Qi("", "subzero_source");
+            let select: Vec<_> = select
+                .iter()
+                .map(|s| match (s, is_scalar) {
+                    (Star, true) => Ok(sql("subzero_source.subzero_scalar")),
+                    (Star, false) => Ok(sql(star_columns(db_schema, fn_name.0, fn_name.1)?
+                        .iter()
+                        .map(|c| format!("subzero_source.{}", fmt_identity(c)))
+                        .collect::<Vec<_>>()
+                        .join(", "))),
+                    (s, _) => fmt_select_item(qi_subzero_source, s),
+                })
+                .collect::<Result<Vec<_>>>()?;
+            (
+                Some(
+                    fmt_function_args(payload, if body.is_some() { declared_parameters } else { parameters })
+                        + if *is_scalar { " subzero_source(subzero_scalar) as ( " } else { " subzero_source as ( " }
+                        + source
+                        + " )"
+                ),
+                sql(" select ")
+                    + select.join(", ")
+                    + " from subzero_source"
+                    + if add_env_tbl_to_from { ", env " } else { " " }
+                    + if !where_.conditions.is_empty() {
+                        "where " + fmt_condition_tree(qi_subzero_source, where_)?
+                    } else {
+                        sql("")
+                    }
+                    + " "
+                    + fmt_order(qi_subzero_source, order)?
+                    + " "
+                    + fmt_limit(limit)
+                    + " "
+                    + fmt_offset(offset),
+            )
         }
         Select {
             select,
@@ -251,10 +416,17 @@ pub fn fmt_query<'a>(
                     format!("{} as {}", fmt_qi(&Qi(schema, table)), fmt_identity(a)),
                 ),
             };
-            if select.iter().any(|s| matches!( s, Star)) {
-                return Err(Error::UnsupportedFeature {message: "'select *' not supported, use explicit select parameters".to_string()})
-            }
-            let mut select: Vec<_> = select.iter().map(|s| fmt_select_item(&qi, s)).collect::<Result<Vec<_>>>()?;
+            let mut select: Vec<_> = select
+                .iter()
+                .map(|s| match s {
+                    Star => Ok(sql(star_columns(db_schema, schema, table)?
+                        .iter()
+                        .map(|c| format!("{}.{}", fmt_qi(&qi), fmt_identity(c)))
+                        .collect::<Vec<_>>()
+                        .join(", "))),
+                    s => fmt_select_item(&qi, s),
+                })
+                .collect::<Result<Vec<_>>>()?;
             let (sub_selects, joins): (Vec<_>, Vec<_>) = q
                 .sub_selects
                 .iter()
@@ -263,10 +435,9 @@ pub fn fmt_query<'a>(
                 .into_iter()
                 .unzip();
             select.extend(sub_selects);
+            let spread_columns = fmt_spread_columns(db_schema, schema, q)?;
 
-            (
-                None,
-                sql(" select ")
+            let query_snippet = sql(" select ")
                     //+ " json_object("
                     + select.join(", ")
                     //+ ") as row"
@@ -289,11 +460,7 @@ pub fn fmt_query<'a>(
                     + " "
                     + joins.into_iter().flatten().collect::<Vec<_>>().join(" ")
                     + " "
-                    + if !where_.conditions.is_empty() {
-                        "where " + fmt_condition_tree(&qi, where_)?
-                    } else {
-                        sql("")
-                    }
+                    + fmt_where(&qi, where_, fmt_embed_conditions(db_schema, schema, q)?)?
                     + " "
                     + fmt_groupby(&qi, groupby)?
                     + " "
@@ -301,7 +468,14 @@ pub fn fmt_query<'a>(
                     + " "
                     + fmt_limit(limit)
                     + " "
-                    + fmt_offset(offset),
+                    + fmt_offset(offset);
+            (
+                None,
+                if spread_columns.is_empty() {
+                    query_snippet
+                } else {
+                    sql(format!(" select _subzero_s.*, {} from (", spread_columns.join(", "))) + query_snippet + " ) as _subzero_s"
+                },
             )
         }
         Insert {
@@ -466,7 +640,7 @@ pub fn fmt_query<'a>(
             )
         }
     };
-    let select_column_names = get_query_select_column_names(q);
+    let select_column_names = get_query_select_column_names(db_schema, schema, q)?;
     Ok(match wrapin_cte {
         Some(cte_name) => match cte_snippet {
             Some(cte) => (" " + cte + " , " + format!("{cte_name} as ( ") + query_snippet + " )", select_column_names),
@@ -479,6 +653,22 @@ pub fn fmt_query<'a>(
     })
 }
 fmt_count_query!();
+fmt_where!();
+//fmt_embed_conditions!();
+fn fmt_embed_conditions<'a>(db_schema: &'a DbSchema<'_>, schema: &'a str, q: &'a Query) -> Result<Vec<Snippet<'a>>> {
+    q.sub_selects
+        .iter()
+        .filter(|s| s.join_type != JoinType::Left)
+        .map(|SubSelect { query, join, join_type, .. }| {
+            let (subquery, _) = fmt_query(db_schema, schema, true, None, query, join)?;
+            Ok(match join_type {
+                JoinType::Anti => sql("not exists ("),
+                _ => sql("exists ("),
+            } + subquery
+                + ")")
+        })
+        .collect()
+}
 //fmt_body!();
 #[rustfmt::skip]
 fn fmt_body<'a>(payload: &'a Payload, columns: &'a [&'a str]) -> Snippet<'a> {
@@ -513,6 +703,32 @@ fn fmt_env_var(e: &'_ EnvVar) -> String {
     }
 }
 fmt_filter!();
+fn fmt_operator_term<'a>(fld: String, o: &'a Operator<'a>, v: &'a SqlParam) -> Result<Snippet<'a>> {
+    Ok(match *o {
+        // regexp is not built in, it has to be registered on the connection as regexp(pattern, value)
+        "match" => fld + " regexp " + param(v),
+        "imatch" => fld + " regexp '(?i)' || " + param(v),
+        _ => fld + " " + fmt_operator(o)?.as_str() + param(v),
+    })
+}
+//fmt_filter_expression!();
+fn fmt_filter_expression<'a>(fld: String, f: &'a Filter<'a>) -> Result<Snippet<'a>> {
+    Ok(match f {
+        // the json containment and overlap operators call the cs/cd/ov functions registered on the connection
+        Op(o @ ("cs" | "cd" | "ov"), v) => sql(*o) + "(" + fld + "," + param(v as &SqlParam) + ")",
+        Op(o @ ("match" | "imatch"), v) => fmt_operator_term(fld, o, v)?,
+        Quant(o, q, ListVal(l, _)) => fmt_quant_filter!(fld, o, q, l),
+        // full text search works on FTS5 virtual tables, the language is ignored
+        Fts("fts" | "plfts" | "wfts", _, v) => fld + " match " + param(v as &SqlParam),
+        Fts("phfts", _, v) => fld + " match '\"' || " + param(v as &SqlParam) + " || '\"'",
+        Op(o, _) | Fts(o, _, _) => {
+            return Err(Error::UnsupportedFeature {
+                message: format!("the {} operator is not supported in sqlite", o),
+            })
+        }
+        _ => fld + " " + fmt_filter(f)?,
+    })
+}
 fmt_select_name!();
 fmt_function_call!();
 //fmt_select_item_function!();
@@ -521,11 +737,15 @@ fn fmt_select_function_call<'a>(
 ) -> Result<Snippet<'a>> {
     Ok(sql(fmt_identity(fn_name))
         + "("
-        + parameters
-            .iter()
-            .map(|p| fmt_function_param(qi, p))
-            .collect::<Result<Vec<_>>>()?
-            .join(",")
+        + if fn_name == "count" && parameters.is_empty() {
+            sql("*")
+        } else {
+            parameters
+                .iter()
+                .map(|p| fmt_function_param(qi, p))
+                .collect::<Result<Vec<_>>>()?
+                .join(",")
+        }
         + ")"
         + if partitions.is_empty() && orders.is_empty() {
             sql("")
@@ -537,24 +757,53 @@ fn fmt_select_function_call<'a>(
                     sql("partition by ") + partitions.iter().map(|p| fmt_field(qi, p)).collect::<Result<Vec<_>>>()?.join(",")
                 }
                 + " "
-                + if orders.is_empty() { "".to_string() } else { fmt_order(qi, orders)? }
+                + if orders.is_empty() { sql("") } else { fmt_order(qi, orders)? }
                 + " )"
         })
 }
 fn fmt_select_item_function<'a>(
     qi: &Qi, fn_name: &str, parameters: &'a [FunctionParam], partitions: &'a [Field], orders: &'a [OrderTerm], alias: &'a Option<&str>,
+    cast: &'a Option<&str>,
 ) -> Result<Snippet<'a>> {
-    Ok(fmt_select_function_call(qi, fn_name, parameters, partitions, orders, alias)?
-        + " as "
+    let call = fmt_select_function_call(qi, fn_name, parameters, partitions, orders, alias)?;
+    Ok(match cast {
+        Some(c) => sql("cast(") + call + format!(" as {})", c),
+        None => call,
+    } + " as "
         + fmt_select_name(fn_name, &None, alias).unwrap_or_default())
 }
 fmt_select_item!();
 fmt_function_param!();
 //fmt_sub_select_item!();
 fn fmt_sub_select_item<'a>(db_schema: &'a DbSchema<'_>, schema: &'a str, _qi: &Qi, i: &'a SubSelect) -> Result<(Snippet<'a>, Vec<Snippet<'a>>)> {
-    let SubSelect { query, alias, join, .. } = i;
+    let SubSelect {
+        query, alias, join, spread, ..
+    } = i;
     match join {
         Some(j) => match j {
+            Parent(ForeignKey {
+                referenced_table: Qi(_, name),
+                ..
+            })
+            | Child(ForeignKey { table: Qi(_, name), .. })
+                if *spread =>
+            {
+                // sqlite has no lateral joins so the embedded row is read once as a json object
+                // and its columns are extracted by the enclosing select (see fmt_spread_columns)
+                let local_table_name = fmt_identity(alias.as_ref().unwrap_or(name));
+                let (subquery, select_column_names) = fmt_query(db_schema, schema, true, None, query, join)?;
+                Ok((
+                    sql("( select json_object(")
+                        + fmt_json_object_pairs(&local_table_name, &select_column_names.unwrap_or_default())
+                        + ") from ("
+                        + subquery
+                        + " ) as "
+                        + local_table_name
+                        + " ) as "
+                        + fmt_spread_column(i),
+                    vec![],
+                ))
+            }
             Parent(fk) => {
                 let alias_or_name = format!("'{}'", alias.as_ref().unwrap_or(&fk.referenced_table.1));
                 let local_table_name = fmt_identity(fk.table.1);
@@ -566,11 +815,7 @@ fn fmt_sub_select_item<'a>(db_schema: &'a DbSchema<'_>, schema: &'a str, _qi: &Q
                     // + ")"
                     // + " as "
                     + " select json_object("
-                    + select_column_names.unwrap_or_default().iter().map(
-                        |(c,is_json)|
-                            if *is_json {format!("'{}', json({}.{})", c, &local_table_name, fmt_identity(c))}
-                            else{format!("'{}', {}.{}", c, &local_table_name, fmt_identity(c))}
-                        ).collect::<Vec<_>>().join(",")
+                    + fmt_json_object_pairs(&local_table_name, &select_column_names.unwrap_or_default())
                     + ")"
                     + " from ("
                     + subquery
@@ -591,11 +836,7 @@ fn fmt_sub_select_item<'a>(db_schema: &'a DbSchema<'_>, schema: &'a str, _qi: &Q
                         // + local_table_name.clone()
                         // + ".row))"
                         + " select json_group_array(json_object("
-                        + select_column_names.unwrap_or_default().iter().map(
-                            |(c,is_json)|
-                                if *is_json {format!("'{}', json({}.{})", c, &local_table_name, fmt_identity(c))}
-                                else{format!("'{}', {}.{}", c, &local_table_name, fmt_identity(c))}
-                            ).collect::<Vec<_>>().join(",")
+                        + fmt_json_object_pairs(&local_table_name, &select_column_names.unwrap_or_default())
                         + "))"
                         + " from ("
                         + subquery
@@ -607,6 +848,9 @@ fn fmt_sub_select_item<'a>(db_schema: &'a DbSchema<'_>, schema: &'a str, _qi: &Q
                     vec![],
                 ))
             }
+            Computed(_) => Err(Error::UnsupportedFeature {
+                message: "computed relationships are not supported in sqlite".to_string(),
+            }),
             Many(_table, _fk1, fk2) => {
                 let alias_or_name = fmt_identity(alias.as_ref().unwrap_or(&fk2.referenced_table.1));
                 let local_table_name = fmt_identity(fk2.referenced_table.1);
@@ -617,11 +861,7 @@ fn fmt_sub_select_item<'a>(db_schema: &'a DbSchema<'_>, schema: &'a str, _qi: &Q
                         // + local_table_name.clone()
                         // + ".row))"
                         + " select json_group_array(json_object("
-                        + select_column_names.unwrap_or_default().iter().map(
-                            |(c,is_json)|
-                                if *is_json {format!("'{}', json({}.{})", c, &local_table_name, fmt_identity(c))}
-                                else{format!("'{}', {}.{}", c, &local_table_name, fmt_identity(c))}
-                            ).collect::<Vec<_>>().join(",")
+                        + fmt_json_object_pairs(&local_table_name, &select_column_names.unwrap_or_default())
                         + "))"
                         + " from ("
                         + subquery
@@ -638,11 +878,59 @@ fn fmt_sub_select_item<'a>(db_schema: &'a DbSchema<'_>, schema: &'a str, _qi: &Q
     }
 }
 
+// the column holding the json object of a spread embed
+fn fmt_spread_column(SubSelect { alias, join, .. }: &SubSelect) -> String {
+    let name = match join {
+        Some(Parent(fk)) => fk.referenced_table.1,
+        Some(Child(fk)) => fk.table.1,
+        _ => "",
+    };
+    fmt_identity(&format!("_subzero_spread_{}", alias.unwrap_or(name)))
+}
+// the columns of the spread embeds extracted from their json objects
+fn fmt_spread_columns(db_schema: &DbSchema<'_>, schema: &str, q: &Query) -> Result<Vec<String>> {
+    Ok(q.sub_selects
+        .iter()
+        .filter(|s| s.spread && s.join.is_some())
+        .map(|s| {
+            let column = fmt_spread_column(s);
+            Ok(get_query_select_column_names(db_schema, schema, &s.query)?
+                .unwrap_or_default()
+                .iter()
+                .map(|(c, _)| format!("json_extract(_subzero_s.{}, {}) as {}", column, fmt_string(&fmt_key_path(c)), fmt_identity(c)))
+                .collect::<Vec<_>>())
+        })
+        .collect::<Result<Vec<_>>>()?
+        .concat())
+}
+// the keys and values of the json object of a row, the json columns are embedded as json and not as strings
+fn fmt_json_object_pairs(table: &str, columns: &[(String, bool)]) -> String {
+    columns
+        .iter()
+        .map(|(c, is_json)| {
+            if *is_json {
+                format!("{}, json({}.{})", fmt_string(c), table, fmt_identity(c))
+            } else {
+                format!("{}, {}.{}", fmt_string(c), table, fmt_identity(c))
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+// a string literal
+fn fmt_string(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "''"))
+}
+// the json path of a key of an object
+fn fmt_key_path(k: &str) -> String {
+    format!("$.\"{}\"", k.replace('\\', "\\\\").replace('"', "\\\""))
+}
 //fmt_operator!();
 fn fmt_operator<'a>(o: &'a Operator<'a>) -> Result<String> {
     match ALL_OPERATORS.get(o) {
         Some(&op) => match op {
             "ilike" => Ok(String::from("like") + " "),
+            "is distinct from" => Ok(String::from("is not") + " "),
             _ => Ok(String::from(op) + " "),
         },
         None => Err(Error::InternalError {
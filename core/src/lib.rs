@@ -25,6 +25,8 @@ pub mod dynamic_statement;
 pub mod error;
 pub mod formatter;
 pub mod license;
+pub mod openapi;
 pub mod parser;
 pub mod permissions;
+pub mod plan_cache;
 pub mod schema;
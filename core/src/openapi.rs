@@ -0,0 +1,449 @@
+// Copyright (c) 2022-2025 subZero Cloud S.R.L
+//
+// This file is part of subZero - The All-in-One library suite for internal tools development
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+use crate::api::{ALL_OPERATORS, ProcParam};
+use crate::error::*;
+use crate::schema::{Action, DbSchema, Object, ObjectType::*, ProcVolatility, Role};
+use serde_json::{json, Map, Value as JsonValue};
+use snafu::OptionExt;
+
+// the content type clients send in the Accept header to ask for the spec
+pub const OPENAPI_CONTENT_TYPE: &str = "application/openapi+json";
+
+// map a database type to a json schema type
+fn json_type(data_type: &str) -> JsonValue {
+    let t = data_type.to_lowercase();
+    let t = t.trim_start_matches("pg_catalog.");
+    let is_array = t.ends_with("[]") || t.starts_with('_') || t.starts_with("array(");
+    let json_type = match t.split(['(', ' ']).next().unwrap_or_default() {
+        _ if is_array => "array",
+        "smallint" | "integer" | "int" | "bigint" | "int2" | "int4" | "int8" | "serial" | "bigserial" | "smallserial" | "tinyint" | "mediumint"
+        | "int16" | "int32" | "int64" | "uint8" | "uint16" | "uint32" | "uint64" => "integer",
+        "numeric" | "decimal" | "real" | "double" | "float" | "float4" | "float8" | "float32" | "float64" => "number",
+        "boolean" | "bool" => "boolean",
+        "json" | "jsonb" => "object",
+        _ => "string",
+    };
+    json!({ "type": json_type, "format": data_type })
+}
+
+fn column_properties(object: &Object, columns: &[&str]) -> JsonValue {
+    let properties = object
+        .columns
+        .values()
+        .filter(|c| columns.contains(&c.name))
+        .map(|c| {
+            let mut p = json_type(c.data_type);
+            if c.primary_key {
+                p["description"] = json!("Note:\nThis is a Primary Key.<pk/>");
+            }
+            (c.name.to_string(), p)
+        })
+        .collect::<Map<_, _>>();
+    json!({ "type": "object", "properties": properties })
+}
+
+fn param_properties(parameters: &[ProcParam]) -> JsonValue {
+    let properties = parameters.iter().map(|p| (p.name.to_string(), json_type(p.type_))).collect::<Map<_, _>>();
+    let required = parameters.iter().filter(|p| p.required).map(|p| p.name).collect::<Vec<_>>();
+    json!({ "type": "object", "properties": properties, "required": required })
+}
+
+// columns the role can use for the action, all of them when no role is given (permissions are not checked)
+fn allowed_columns<'a>(db_schema: &'a DbSchema, schema_name: &str, object: &'a Object, role: Option<Role>, action: Action) -> Option<Vec<&'a str>> {
+    match role {
+        None => Some(object.columns.values().map(|c| c.name).collect()),
+        Some(r) => db_schema.get_columns_with_privileges(r, &action, schema_name, object.name).ok(),
+    }
+}
+
+fn has_privileges(db_schema: &DbSchema, schema_name: &str, object: &Object, role: Option<Role>, action: Action) -> bool {
+    match (role, action) {
+        (None, _) => true,
+        (Some(r), Action::Delete) => db_schema.has_delete_privileges(r, schema_name, object.name).is_ok(),
+        (Some(r), Action::Execute) => db_schema.has_execute_privileges(r, schema_name, object.name).is_ok(),
+        (Some(r), a) => db_schema.get_columns_with_privileges(r, &a, schema_name, object.name).is_ok(),
+    }
+}
+
+fn row_filters(object: &Object, columns: &[&str]) -> Vec<JsonValue> {
+    object
+        .columns
+        .values()
+        .filter(|c| columns.contains(&c.name))
+        .map(|c| {
+            json!({
+                "name": c.name,
+                "in": "query",
+                "required": false,
+                "schema": { "type": "string", "format": c.data_type },
+                "description": "Filter on the column using <operator>.<value>, see #/components/parameters/filter",
+            })
+        })
+        .collect()
+}
+
+fn ref_params(names: &[&str]) -> Vec<JsonValue> {
+    names
+        .iter()
+        .map(|name| json!({ "$ref": format!("#/components/parameters/{name}") }))
+        .collect()
+}
+
+fn rows_response(schema_ref: &str) -> JsonValue {
+    json!({
+        "200": {
+            "description": "OK",
+            "content": {
+                "application/json": { "schema": { "type": "array", "items": { "$ref": schema_ref } } },
+                "application/vnd.pgrst.object+json": { "schema": { "$ref": schema_ref } },
+                "text/csv": { "schema": { "type": "string" } },
+            }
+        },
+        "206": { "description": "Partial Content" },
+    })
+}
+
+fn body_content(schema: JsonValue) -> JsonValue {
+    json!({
+        "required": true,
+        "content": {
+            "application/json": { "schema": { "oneOf": [ schema.clone(), { "type": "array", "items": schema } ] } },
+            "text/csv": { "schema": { "type": "string" } },
+        }
+    })
+}
+
+fn common_parameters() -> JsonValue {
+    let mut operators = ALL_OPERATORS.keys().copied().chain(["in", "not"]).collect::<Vec<_>>();
+    operators.sort_unstable();
+    json!({
+        "select": {
+            "name": "select", "in": "query", "required": false, "schema": { "type": "string" },
+            "description": "Columns and embedded relations to return, e.g. id,name,relation(id,name)",
+        },
+        "order": {
+            "name": "order", "in": "query", "required": false, "schema": { "type": "string" },
+            "description": "Ordering terms, e.g. name.desc.nullslast,id",
+        },
+        "limit": {
+            "name": "limit", "in": "query", "required": false, "schema": { "type": "integer", "minimum": 0 },
+            "description": "Limit the number of rows returned",
+        },
+        "offset": {
+            "name": "offset", "in": "query", "required": false, "schema": { "type": "integer", "minimum": 0 },
+            "description": "Skip the given number of rows",
+        },
+        "on_conflict": {
+            "name": "on_conflict", "in": "query", "required": false, "schema": { "type": "string" },
+            "description": "Comma separated list of columns used for upserts",
+        },
+        "columns": {
+            "name": "columns", "in": "query", "required": false, "schema": { "type": "string" },
+            "description": "Comma separated list of columns to read from the payload",
+        },
+        "or": {
+            "name": "or", "in": "query", "required": false, "schema": { "type": "string" },
+            "description": "Logical disjunction of filters, e.g. (id.eq.1,name.eq.john)",
+        },
+        "and": {
+            "name": "and", "in": "query", "required": false, "schema": { "type": "string" },
+            "description": "Logical conjunction of filters, e.g. (id.gt.1,id.lt.10)",
+        },
+        "filter": {
+            "name": "filter", "in": "query", "required": false, "schema": { "type": "string" },
+            "description": format!("Column filter in the form <operator>.<value>, available operators: {}", operators.join(", ")),
+        },
+        "range": {
+            "name": "Range", "in": "header", "required": false, "schema": { "type": "string" },
+            "description": "Limiting and pagination, e.g. 0-24",
+        },
+        "rangeUnit": {
+            "name": "Range-Unit", "in": "header", "required": false, "schema": { "type": "string", "default": "items" },
+        },
+        "preferParams": {
+            "name": "Prefer", "in": "header", "required": false,
+            "schema": { "type": "string", "enum": ["params=single-object"] },
+        },
+        "preferReturn": {
+            "name": "Prefer", "in": "header", "required": false,
+            "schema": { "type": "string", "enum": ["return=representation", "return=minimal", "return=headers-only"] },
+        },
+        "preferCount": {
+            "name": "Prefer", "in": "header", "required": false,
+            "schema": { "type": "string", "enum": ["count=exact", "count=planned", "count=estimated"] },
+        },
+        "preferResolution": {
+            "name": "Prefer", "in": "header", "required": false,
+            "schema": { "type": "string", "enum": ["resolution=merge-duplicates", "resolution=ignore-duplicates"] },
+        },
+    })
+}
+
+// generate the OpenAPI 3.1 document describing the objects in the given schema
+// when a role is given, only the objects/columns the role has privileges for are included
+// the servers list is left out when the url the api is served from is not known
+pub fn generate(db_schema: &DbSchema, schema_name: &str, role: Option<Role>, server_url: Option<&str>) -> Result<JsonValue> {
+    let schema = db_schema.schemas.get(schema_name).context(UnacceptableSchemaSnafu {
+        schemas: vec![schema_name.to_owned()],
+    })?;
+    let mut paths = Map::new();
+    let mut schemas = Map::new();
+
+    for object in schema.objects.values() {
+        match &object.kind {
+            Table | View => {
+                let name = object.name;
+                let schema_ref = format!("#/components/schemas/{name}");
+                let mut path = Map::new();
+
+                if let Some(columns) = allowed_columns(db_schema, schema_name, object, role, Action::Select) {
+                    schemas.insert(name.to_string(), column_properties(object, &columns));
+                    let mut parameters = ref_params(&["select", "order", "limit", "offset", "or", "and", "range", "rangeUnit", "preferCount"]);
+                    parameters.extend(row_filters(object, &columns));
+                    path.insert(
+                        "get".to_string(),
+                        json!({ "tags": [name], "summary": format!("Read rows from {name}"), "parameters": parameters, "responses": rows_response(&schema_ref) }),
+                    );
+                }
+                if let Some(columns) = allowed_columns(db_schema, schema_name, object, role, Action::Insert) {
+                    path.insert(
+                        "post".to_string(),
+                        json!({
+                            "tags": [name],
+                            "summary": format!("Insert rows into {name}"),
+                            "parameters": ref_params(&["select", "columns", "on_conflict", "preferReturn", "preferResolution"]),
+                            "requestBody": body_content(column_properties(object, &columns)),
+                            "responses": { "201": { "description": "Created" } },
+                        }),
+                    );
+                }
+                let filter_columns = allowed_columns(db_schema, schema_name, object, role, Action::Select).unwrap_or_default();
+                if let Some(columns) = allowed_columns(db_schema, schema_name, object, role, Action::Update) {
+                    let mut parameters = ref_params(&["select", "columns", "preferReturn"]);
+                    parameters.extend(row_filters(object, &filter_columns));
+                    path.insert(
+                        "patch".to_string(),
+                        json!({
+                            "tags": [name],
+                            "summary": format!("Update rows in {name}"),
+                            "parameters": parameters,
+                            "requestBody": body_content(column_properties(object, &columns)),
+                            "responses": { "200": { "description": "OK" }, "204": { "description": "No Content" } },
+                        }),
+                    );
+                }
+                if has_privileges(db_schema, schema_name, object, role, Action::Delete) {
+                    let mut parameters = ref_params(&["select", "preferReturn"]);
+                    parameters.extend(row_filters(object, &filter_columns));
+                    path.insert(
+                        "delete".to_string(),
+                        json!({
+                            "tags": [name],
+                            "summary": format!("Delete rows from {name}"),
+                            "parameters": parameters,
+                            "responses": { "200": { "description": "OK" }, "204": { "description": "No Content" } },
+                        }),
+                    );
+                }
+                if !path.is_empty() {
+                    paths.insert(format!("/{name}"), JsonValue::Object(path));
+                }
+            }
+            Function { volatile, parameters, .. } => {
+                if !has_privileges(db_schema, schema_name, object, role, Action::Execute) {
+                    continue;
+                }
+                let name = object.name;
+                let mut path = Map::new();
+                let responses = json!({ "200": { "description": "OK", "content": { "application/json": { "schema": {} } } } });
+                // only stable/immutable functions can be called with GET
+                if *volatile != ProcVolatility::Volatile {
+                    let mut query_parameters = ref_params(&["select", "order", "limit", "offset"]);
+                    query_parameters.extend(
+                        parameters
+                            .iter()
+                            .map(|p| json!({ "name": p.name, "in": "query", "required": p.required, "schema": json_type(p.type_) })),
+                    );
+                    path.insert(
+                        "get".to_string(),
+                        json!({ "tags": ["(rpc) ".to_string() + name], "summary": format!("Call {name}"), "parameters": query_parameters, "responses": responses }),
+                    );
+                }
+                path.insert(
+                    "post".to_string(),
+                    json!({
+                        "tags": ["(rpc) ".to_string() + name],
+                        "summary": format!("Call {name}"),
+                        "parameters": ref_params(&["select", "order", "limit", "offset", "preferParams"]),
+                        "requestBody": { "required": true, "content": { "application/json": { "schema": param_properties(parameters) } } },
+                        "responses": responses,
+                    }),
+                );
+                paths.insert(format!("/rpc/{name}"), JsonValue::Object(path));
+            }
+        }
+    }
+
+    let mut spec = json!({
+        "openapi": "3.1.0",
+        "info": {
+            "title": format!("{schema_name} API"),
+            "description": "API generated from the database schema",
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "paths": paths,
+        "components": {
+            "schemas": schemas,
+            "parameters": common_parameters(),
+        },
+    });
+    if let Some(url) = server_url {
+        spec["servers"] = json!([{ "url": url }]);
+    }
+    Ok(spec)
+}
+
+// whether the Accept header asks for the OpenAPI document
+pub fn accepts_openapi(accept: Option<&str>) -> bool {
+    match accept {
+        None => true,
+        Some(a) => a
+            .split(',')
+            .map(|t| t.split(';').next().unwrap_or_default().trim())
+            .any(|t| matches!(t, OPENAPI_CONTENT_TYPE | "application/json" | "*/*")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    static JSON_SCHEMA: &str = r#"
+        {
+            "use_internal_permissions": true,
+            "schemas":[
+                {
+                    "name":"api",
+                    "objects":[
+                        {
+                            "kind":"function",
+                            "name":"myfunction",
+                            "volatile":"s",
+                            "composite":false,
+                            "setof":false,
+                            "return_type":"int4",
+                            "return_type_schema":"pg_catalog",
+                            "parameters":[
+                                { "name":"id", "type":"integer", "required":true, "variadic":false }
+                            ],
+                            "permissions":[
+                                { "role":"admin", "grant":["execute"] }
+                            ]
+                        },
+                        {
+                            "kind":"table",
+                            "name":"tasks",
+                            "columns":[
+                                { "name":"id", "data_type":"int", "primary_key":true },
+                                { "name":"name", "data_type":"text" },
+                                { "name":"secret", "data_type":"text" }
+                            ],
+                            "foreign_keys":[],
+                            "permissions":[
+                                { "role":"admin", "grant":["all"] },
+                                { "role":"user", "grant":["select"], "columns":["id","name"] }
+                            ]
+                        }
+                    ]
+                }
+            ]
+        }
+    "#;
+
+    #[test]
+    fn json_types() {
+        assert_eq!(json_type("int4")["type"], "integer");
+        assert_eq!(json_type("double precision")["type"], "number");
+        assert_eq!(json_type("text[]")["type"], "array");
+        assert_eq!(json_type("jsonb")["type"], "object");
+        assert_eq!(json_type("timestamp with time zone")["type"], "string");
+        assert_eq!(json_type("timestamp with time zone")["format"], "timestamp with time zone");
+    }
+
+    #[test]
+    fn generate_without_role() {
+        let db_schema: DbSchema = serde_json::from_str(JSON_SCHEMA).unwrap();
+        let spec = generate(&db_schema, "api", None, Some("http://localhost/")).unwrap();
+        assert_eq!(spec["openapi"], "3.1.0");
+        assert_eq!(spec["servers"][0]["url"], "http://localhost/");
+        let tasks = spec["paths"]["/tasks"].as_object().unwrap();
+        // the key order depends on whether serde_json preserves the insertion order
+        let mut methods = tasks.keys().collect::<Vec<_>>();
+        methods.sort();
+        assert_eq!(methods, vec!["delete", "get", "patch", "post"]);
+        assert_eq!(
+            spec["components"]["schemas"]["tasks"]["properties"]
+                .as_object()
+                .unwrap()
+                .keys()
+                .collect::<Vec<_>>(),
+            vec!["id", "name", "secret"]
+        );
+        let rpc = spec["paths"]["/rpc/myfunction"].as_object().unwrap();
+        assert_eq!(rpc.keys().collect::<Vec<_>>(), vec!["get", "post"]);
+        assert_eq!(
+            rpc["post"]["requestBody"]["content"]["application/json"]["schema"],
+            json!({ "type": "object", "properties": { "id": { "type": "integer", "format": "integer" } }, "required": ["id"] })
+        );
+    }
+
+    #[test]
+    fn generate_with_role() {
+        let db_schema: DbSchema = serde_json::from_str(JSON_SCHEMA).unwrap();
+        let spec = generate(&db_schema, "api", Some("user"), None).unwrap();
+        assert!(spec.get("servers").is_none());
+        let paths = spec["paths"].as_object().unwrap();
+        assert_eq!(paths.keys().collect::<Vec<_>>(), vec!["/tasks"]);
+        assert_eq!(paths["/tasks"].as_object().unwrap().keys().collect::<Vec<_>>(), vec!["get"]);
+        assert_eq!(
+            spec["components"]["schemas"]["tasks"]["properties"]
+                .as_object()
+                .unwrap()
+                .keys()
+                .collect::<Vec<_>>(),
+            vec!["id", "name"]
+        );
+
+        let spec = generate(&db_schema, "api", Some("anonymous"), None).unwrap();
+        assert_eq!(spec["paths"], json!({}));
+    }
+
+    #[test]
+    fn generate_unknown_schema() {
+        let db_schema: DbSchema = serde_json::from_str(JSON_SCHEMA).unwrap();
+        assert!(matches!(generate(&db_schema, "other", None, None), Err(Error::UnacceptableSchema { .. })));
+    }
+
+    #[test]
+    fn accept_header() {
+        assert!(accepts_openapi(None));
+        assert!(accepts_openapi(Some("application/openapi+json")));
+        assert!(accepts_openapi(Some("text/html, */*;q=0.8")));
+        assert!(!accepts_openapi(Some("text/html")));
+    }
+}
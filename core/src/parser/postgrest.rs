@@ -31,7 +31,7 @@ use nom::{
     Err,
     error::{ParseError, context, ErrorKind, VerboseErrorKind, VerboseError},
     //error::convert_error,
-    combinator::{peek, recognize, eof, map, map_res, map_opt, opt, value},
+    combinator::{peek, recognize, eof, map, map_res, opt, value},
     sequence::{delimited, terminated, preceded, tuple},
     bytes::complete::{tag, is_not, is_a, take},
     character::complete::{multispace0, char, alpha1, digit1, one_of},
@@ -246,6 +246,16 @@ fn get_payload<'a>(content_type: ContentType, _body: &'a str, columns_param: Opt
             body.push(']');
             Ok((headers, Cow::Owned(body)))
         }
+        (t @ (GeoJSON | TextPlain | TextXML | OctetStream | Plan(..)), _) => Err(Error::ContentTypeError {
+            message: format!(
+                "{} is only supported as a response format",
+                AVAILABLE_MEDIA_TYPES
+                    .iter()
+                    .find(|(_, c, _)| *c == t)
+                    .map(|(m, ..)| *m)
+                    .unwrap_or_default()
+            ),
+        }),
         (Other(t), _) => Err(Error::ContentTypeError {
             message: format!("None of these Content-Types are available: {t}"),
         }),
@@ -275,7 +285,6 @@ pub fn parse<'a>(
     //let mut
     let mut fn_arguments = vec![];
     let accept_content_type = match headers.get("accept") {
-        //TODO!!! accept header can have multiple content types
         Some(&accept_header) => {
             // let (act, _) = content_type()
             //     .message("failed to parse accept header")
@@ -283,8 +292,14 @@ pub fn parse<'a>(
             //     .map_err(|_| Error::ContentTypeError {
             //         message: format!("None of these Content-Types are available: {}", accept_header),
             //     })?;
-            let (_, act) = context("failed to parse accept header", content_type)(accept_header).map_err(|_| Error::ContentTypeError {
-                message: format!("None of these Content-Types are available: {accept_header}"),
+            let (_, act) = context("failed to parse accept header", accept)(accept_header).map_err(|_| Error::NotAcceptable {
+                accept: accept_header.to_string(),
+                // the plan is not listed, it is available only when enabled in the configuration
+                available: AVAILABLE_MEDIA_TYPES
+                    .iter()
+                    .filter(|(_, t, _)| !matches!(t, Plan(..)))
+                    .map(|(t, ..)| t.to_string())
+                    .collect(),
             })?;
             Ok(act)
         }
@@ -311,7 +326,10 @@ pub fn parse<'a>(
             //     .message("failed to parse Prefer header ")
             //     .easy_parse(pref)
             //     .map_err(to_app_error(pref))?;
-            let (_, p) = context("failed to parse Prefer header", preferences)(pref).map_err(|e| to_app_error(pref, e))?;
+            let (_, (p, invalid)) = context("failed to parse Prefer header", preferences)(pref).map_err(|e| to_app_error(pref, e))?;
+            if p.handling == Some(Handling::Strict) && !invalid.is_empty() {
+                return Err(Error::InvalidPreferences { invalid: invalid.join(", ") });
+            }
             Ok(Some(p))
         }
         None => Ok(None),
@@ -464,6 +482,9 @@ pub fn parse<'a>(
                 representation: Some(Representation::Full),
                 resolution: None,
                 count: None,
+                transaction: None,
+                handling: None,
+                max_affected: None,
             },
         });
     }
@@ -478,6 +499,22 @@ pub fn parse<'a>(
     let (node_select, sub_selects) = split_select(select_items);
     let mut query = match (method, &root_obj.kind, body) {
         (method, Function { return_type, parameters, .. }, _body) => {
+            if matches!(accept_content_type, TextPlain | TextXML | OctetStream) {
+                let raw_type = match return_type {
+                    One(Scalar(t)) | SetOf(Scalar(t)) => raw_media_type(t),
+                    _ => None,
+                };
+                if raw_type.as_ref() != Some(&accept_content_type) {
+                    return Err(Error::NotAcceptable {
+                        accept: headers.get("accept").unwrap_or(&"").to_string(),
+                        available: AVAILABLE_MEDIA_TYPES
+                            .iter()
+                            .filter(|(_, t, _)| !matches!(t, TextPlain | TextXML | OctetStream | Plan(..)) || Some(t) == raw_type.as_ref())
+                            .map(|(t, ..)| t.to_string())
+                            .collect(),
+                    });
+                }
+            }
             let parameters_map = parameters.iter().map(|p| (p.name, p)).collect::<HashMap<_, _>>();
             let required_params: HashSet<&str> = HashSet::from_iter(parameters.iter().filter(|p| p.required).map(|p| p.name));
             let all_params: HashSet<&str> = HashSet::from_iter(parameters.iter().map(|p| p.name));
@@ -610,7 +647,7 @@ pub fn parse<'a>(
                     //CallParams::KeyParams(vec![]),
                     payload,
                     //parameter_values,
-                    is_scalar: matches!(return_type, One(Scalar) | SetOf(Scalar)),
+                    is_scalar: matches!(return_type, One(Scalar(_)) | SetOf(Scalar(_))),
                     returns_single: match return_type {
                         One(_) => true,
                         SetOf(_) => false,
@@ -868,6 +905,10 @@ pub fn parse<'a>(
         }
         Ok(())
     })?;
+    add_order_join_info(&mut query, schema, db_schema)?;
+
+    // group by the non aggregated columns when aggregates are selected
+    add_aggregate_groupby(&mut query);
 
     // enforce max rows limit for each node
     enforce_max_rows(&mut query, max_rows);
@@ -889,6 +930,42 @@ pub fn parse<'a>(
     })
 }
 
+// add the plain columns of a select containing aggregates to its group by
+// (along with the local columns used to join the embedded resources)
+fn add_aggregate_groupby(query: &mut Query<'_>) {
+    let Query { node, sub_selects } = query;
+    if let Select { select, groupby, .. } = node {
+        let has_aggregates = select.iter().any(|s| {
+            matches!(s, Func { fn_name, partitions, orders, .. }
+                if partitions.is_empty() && orders.is_empty() && AGGREGATE_FUNCTIONS.contains(fn_name))
+        });
+        if has_aggregates {
+            let join_columns = sub_selects.iter().flat_map(|SubSelect { join, .. }| match join {
+                Some(Parent(fk)) => fk.columns.clone(),
+                Some(Child(fk)) => fk.referenced_columns.clone(),
+                Some(Many(_, fk, _)) => fk.referenced_columns.clone(),
+                _ => vec![],
+            });
+            let fields = select
+                .iter()
+                .filter_map(|s| match s {
+                    Simple { field, .. } => Some(field.clone()),
+                    _ => None,
+                })
+                .chain(join_columns.map(|name| Field { name, json_path: None }))
+                .collect::<Vec<_>>();
+            for field in fields {
+                if !groupby.iter().any(|GroupByTerm(f)| f == &field) {
+                    groupby.push(GroupByTerm(field));
+                }
+            }
+        }
+    }
+    for SubSelect { query: q, .. } in sub_selects.iter_mut() {
+        add_aggregate_groupby(q);
+    }
+}
+
 // enforce max rows
 fn enforce_max_rows<'a>(query: &mut Query<'a>, max_rows: Option<&'a str>) {
     if let Some(max_str) = max_rows {
@@ -1120,26 +1197,56 @@ fn select_item(i: &str) -> Parsed<SelectKind> {
             function_call,
             opt(tuple((tag("-p"), delimited(char('('), separated_list1(ws(char(',')), field), char(')'))))),
             opt(tuple((tag("-o"), delimited(char('('), separated_list1(ws(char(',')), order_term), char(')'))))),
+            opt(cast),
         )),
-        |(alias, (fn_name, parameters), partitions, orders)| {
+        |(alias, (fn_name, parameters), partitions, orders, cast)| {
             Item(Func {
                 alias,
                 fn_name,
                 parameters,
                 partitions: partitions.map(|(_, p)| p).unwrap_or_default(),
                 orders: orders.map(|(_, o)| o).unwrap_or_default(),
+                cast,
+            })
+        },
+    );
+    // column.sum() style aggregates and count()
+    let aggregate = map(
+        tuple((
+            opt(alias),
+            alt((
+                map(tag("count()"), |_| ("count", vec![])),
+                map(tuple((field, dot, aggregate_name, tag("()"))), |(field, _, fn_name, _)| (fn_name, vec![FunctionParam::Fld(field)])),
+            )),
+            opt(cast),
+        )),
+        |(alias, (fn_name, parameters), cast)| {
+            Item(Func {
+                alias,
+                fn_name,
+                parameters,
+                partitions: vec![],
+                orders: vec![],
+                cast,
             })
         },
     );
     let sub_select = map(
         tuple((
+            opt(tag("...")),
             opt(alias),
             ws(field_name),
             opt(map(tuple((one_of("!."), ws(field_name))), |(_, hint)| hint)),
+            opt(map(tuple((char('!'), ws(alt((tag("inner"), tag("left")))))), |(_, join_type)| join_type)),
             delimited(char('('), separated_list1(ws(char(',')), select_item), char(')')),
         )),
-        |(alias, from, hint, select)| {
+        |(spread, alias, from, hint, join_type, select)| {
             let (sel, sub_sel) = split_select(select);
+            // the join type can take the place of the hint (table!inner(...))
+            let (hint, join_type) = match (hint, join_type) {
+                (Some(t @ ("inner" | "left")), None) => (None, Some(t)),
+                (h, t) => (h, t),
+            };
             Sub(Box::new(SubSelect {
                 query: Query {
                     node: Select {
@@ -1162,10 +1269,16 @@ fn select_item(i: &str) -> Parsed<SelectKind> {
                 alias,
                 hint,
                 join: None,
+                spread: spread.is_some(),
+                join_type: if join_type == Some("inner") { JoinType::Inner } else { JoinType::Left },
             }))
         },
     );
-    alt((function, sub_select, column, star))(i)
+    alt((function, aggregate, sub_select, column, star))(i)
+}
+
+fn aggregate_name(i: &str) -> Parsed<&str> {
+    alt((tag("avg"), tag("count"), tag("max"), tag("min"), tag("sum")))(i)
 }
 
 fn single_value<'a>(data_type: &Option<&'a str>, i: &'a str) -> Parsed<'a, SingleVal<'a>> {
@@ -1206,17 +1319,19 @@ fn logic_single_value<'a>(data_type: &'a Option<&'a str>, i: &'a str) -> Parsed<
     Ok((input, v))
 }
 
-fn list_value<'a>(data_type: &Option<&'a str>, i: &'a str) -> Parsed<'a, ListVal<'a>> {
+// the values of the in operator are wrapped in () and the values of the quantified operators in {}
+fn list_value<'a>(delimiters: (char, char), data_type: &Option<&'a str>, i: &'a str) -> Parsed<'a, ListVal<'a>> {
+    let (open, close) = delimiters;
     let dt = data_type.map(|v| Cow::Owned(format!("Array({v})"))); //TODO!!! this is hardcoded for clickhouse
-    let (input, list) = delimited(ws(char('(')), separated_list0(ws(char(',')), list_element), ws(char(')')))(i)?;
+    let (input, list) = delimited(ws(char(open)), separated_list0(ws(char(',')), |ii| list_element(close, ii)), ws(char(close)))(i)?;
     Ok((input, ListVal(list, dt)))
 }
 
-fn list_element(i: &str) -> Parsed<Cow<str>> {
+fn list_element(close: char, i: &str) -> Parsed<Cow<str>> {
     alt((
         //terminated(quoted_value, peek(none_of(",)"))),
         quoted_value_escaped,
-        map(is_not(",)"), Cow::Borrowed),
+        map(is_not(&[',', close][..]), Cow::Borrowed),
     ))(i)
 }
 
@@ -1234,6 +1349,16 @@ fn fts_operator(i: &str) -> Parsed<&str> {
     })(i)
 }
 
+fn quantified_operator(i: &str) -> Parsed<(&str, Quantifier)> {
+    map_res(
+        tuple((alpha1, delimited(char('('), alt((value(Quantifier::Any, tag("any")), value(Quantifier::All, tag("all")))), char(')')))),
+        |(o, q): (&str, Quantifier)| match QUANTIFIABLE_OPERATORS.contains(&o) {
+            true => Ok((o, q)),
+            false => Err(Err::Error(("unknown quantifiable operator", ErrorKind::Fail))),
+        },
+    )(i)
+}
+
 fn negatable_filter<'a>(data_type: &Option<&'a str>, i: &'a str) -> Parsed<'a, (bool, Filter<'a>)> {
     map(tuple((opt(tag("not.")), apply(data_type, filter))), |(n, f)| (n.is_some(), f))(i)
 }
@@ -1253,7 +1378,16 @@ fn filter_common<'a, 'b>(
             },
             _ => Filter::Op(o, SingleVal(v, dt)),
         }),
-        map(tuple((tag("in"), char('.'), apply(data_type, list_value))), |(_, _, ListVal(v, dt))| Filter::In(ListVal(v, dt))),
+        map(tuple((tag("in"), char('.'), apply(data_type, |dt, ii| list_value(('(', ')'), dt, ii)))), |(_, _, ListVal(v, dt))| {
+            Filter::In(ListVal(v, dt))
+        }),
+        map(
+            tuple((quantified_operator, char('.'), apply(data_type, |dt, ii| list_value(('{', '}'), dt, ii)))),
+            |((o, q), _, ListVal(v, dt))| match o {
+                "like" | "ilike" => Filter::Quant(o, q, ListVal(v.into_iter().map(|e| Cow::Owned(e.replace('*', "%"))).collect(), dt)),
+                _ => Filter::Quant(o, q, ListVal(v, dt)),
+            },
+        ),
         map(
             tuple((
                 fts_operator,
@@ -1301,11 +1435,21 @@ fn order(i: &str) -> Parsed<Vec<OrderTerm>> {
 fn order_term(i: &str) -> Parsed<OrderTerm> {
     map(
         tuple((
-            field,
+            alt((
+                map(tuple((field_name, delimited(char('('), ws(field), char(')')))), |(relation, term)| (Some(relation), term)),
+                map(field, |term| (None, term)),
+            )),
             opt(preceded(dot, alt((value(OrderDirection::Asc, tag("asc")), value(OrderDirection::Desc, tag("desc")))))),
             opt(preceded(dot, alt((value(OrderNulls::NullsFirst, tag("nullsfirst")), value(OrderNulls::NullsLast, tag("nullslast")))))),
         )),
-        |(term, direction, null_order)| OrderTerm { term, direction, null_order },
+        |((relation, term), direction, null_order)| OrderTerm {
+            term,
+            relation,
+            join: None,
+            relation_policy: None,
+            direction,
+            null_order,
+        },
     )(i)
 }
 
@@ -1344,52 +1488,129 @@ fn content_type(i: &str) -> Parsed<ContentType> {
     )(i)
 }
 
-fn preferences(i: &str) -> Parsed<Preferences> {
-    map_opt(
-        separated_list1(
-            tag(","),
-            map_res(is_not(","), |t: &str| {
-                let tt = t.trim().split('=').map(|s| s.trim()).collect::<Vec<_>>();
-                match tt.as_slice() {
-                    ["resolution", s] => Ok(("resolution", *s)),
-                    ["return", s] => Ok(("return", *s)),
-                    ["count", s] => Ok(("count", *s)),
-                    _ => Err("unknown preference"),
-                }
-            }),
-        ),
-        |v: Vec<(&str, _)>| {
-            let m = v.into_iter().collect::<HashMap<_, _>>();
-            Some(Preferences {
-                resolution: match m.get("resolution") {
-                    Some(r) => match *r {
-                        "merge-duplicates" => Some(Resolution::MergeDuplicates),
-                        "ignore-duplicates" => Some(Resolution::IgnoreDuplicates),
-                        _ => None,
-                    },
-                    None => None,
-                },
-                representation: match m.get("return") {
-                    Some(r) => match *r {
-                        "representation" => Some(Representation::Full),
-                        "minimal" => Some(Representation::None),
-                        "headers-only" => Some(Representation::HeadersOnly),
-                        _ => None,
-                    },
-                    None => None,
-                },
-                count: match m.get("count") {
-                    Some(r) => match *r {
-                        "exact" => Some(Count::ExactCount),
-                        "planned" => Some(Count::PlannedCount),
-                        "estimated" => Some(Count::EstimatedCount),
-                        _ => None,
-                    },
-                    None => None,
-                },
+// media types available for the response, in the order they are picked when the client has no preference
+// the vendor specific types are not matched by wildcards, they have to be requested explicitly
+pub const AVAILABLE_MEDIA_TYPES: &[(&str, ContentType, bool)] = &[
+    ("application/json", ApplicationJSON, true),
+    ("application/vnd.pgrst.object+json", SingularJSON, false),
+    ("application/vnd.pgrst.object", SingularJSON, false),
+    ("application/geo+json", GeoJSON, false),
+    ("text/csv", TextCSV, true),
+    ("text/plain", TextPlain, false),
+    ("text/xml", TextXML, false),
+    ("application/octet-stream", OctetStream, false),
+    ("application/vnd.pgrst.plan+json", Plan(PlanFormat::Json, Vec::new()), false),
+    ("application/vnd.pgrst.plan+text", Plan(PlanFormat::Text, Vec::new()), false),
+    ("application/vnd.pgrst.plan", Plan(PlanFormat::Text, Vec::new()), false),
+];
+
+// media types that return the raw value of a scalar function, keyed by the type the function returns
+fn raw_media_type(scalar_type: &str) -> Option<ContentType> {
+    match scalar_type {
+        "text" => Some(TextPlain),
+        "xml" => Some(TextXML),
+        "bytea" => Some(OctetStream),
+        _ => None,
+    }
+}
+
+// media range from the accept header as (type, subtype, q)
+fn media_range(t: &str) -> (&str, &str, f32) {
+    let mut parts = t.split(';').map(str::trim);
+    let (mtype, subtype) = parts.next().unwrap_or_default().split_once('/').unwrap_or((t.trim(), ""));
+    let q = parts
+        .filter_map(|p| p.split_once('='))
+        .find(|(k, _)| k.trim().eq_ignore_ascii_case("q"))
+        .and_then(|(_, v)| v.trim().parse::<f32>().ok())
+        .map(|q| q.clamp(0.0, 1.0))
+        .unwrap_or(1.0);
+    (mtype, subtype, q)
+}
+
+// content negotiation (RFC 9110 section 12.5.1)
+// each available media type gets the q value of the most specific media range matching it
+// and the one with the highest q value wins, ties are broken by specificity and then by the order in the header
+fn accept(i: &str) -> Parsed<ContentType> {
+    map_res(separated_list1(tag(","), map(is_not(","), media_range)), |ranges: Vec<(&str, &str, f32)>| {
+        AVAILABLE_MEDIA_TYPES
+            .iter()
+            .filter_map(|(media_type, content_type, wildcard)| {
+                let (mtype, subtype) = media_type.split_once('/').unwrap_or_default();
+                ranges
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(position, (t, s, q))| {
+                        let specificity = match (*t, *s) {
+                            (t, s) if t.eq_ignore_ascii_case(mtype) && s.eq_ignore_ascii_case(subtype) => 2,
+                            (t, "*") if *wildcard && t.eq_ignore_ascii_case(mtype) => 1,
+                            ("*", "*") if *wildcard => 0,
+                            _ => return None,
+                        };
+                        Some((specificity, *q, position))
+                    })
+                    .max_by_key(|(specificity, _, position)| (*specificity, std::cmp::Reverse(*position)))
+                    .map(|(specificity, q, position)| (content_type, q, specificity, position))
             })
-        },
-    )(i)
+            .filter(|(_, q, ..)| *q > 0.0)
+            .fold(None, |best: Option<(&ContentType, f32, i32, usize)>, candidate| match best {
+                Some(b) if (b.1, b.2, std::cmp::Reverse(b.3)) >= (candidate.1, candidate.2, std::cmp::Reverse(candidate.3)) => Some(b),
+                _ => Some(candidate),
+            })
+            .ok_or("no acceptable media type")
+            .and_then(|(content_type, .., position)| match content_type {
+                Plan(format, _) => Ok(Plan(format.clone(), plan_options(i.split(',').nth(position).unwrap_or_default())?)),
+                t => Ok(t.clone()),
+            })
+    })(i)
+}
+
+// the options parameter of the plan media type, e.g. application/vnd.pgrst.plan+json; options=analyze|buffers
+fn plan_options(media_range: &str) -> Result<Vec<PlanOption>, &'static str> {
+    media_range
+        .split(';')
+        .skip(1)
+        .filter_map(|p| p.split_once('='))
+        .filter(|(k, _)| k.trim().eq_ignore_ascii_case("options"))
+        .flat_map(|(_, v)| v.trim().trim_matches('"').split('|'))
+        .map(|o| match o.trim() {
+            "analyze" => Ok(PlanOption::Analyze),
+            "verbose" => Ok(PlanOption::Verbose),
+            "settings" => Ok(PlanOption::Settings),
+            "buffers" => Ok(PlanOption::Buffers),
+            "wal" => Ok(PlanOption::Wal),
+            _ => Err("unknown plan option"),
+        })
+        .collect()
+}
+
+// unknown preferences and unknown values are returned separately so they can be ignored (handling=lenient)
+// or rejected (handling=strict), when a preference is repeated the last value wins
+// missing=default is not implemented (the introspection has no column defaults) so it is treated as unknown
+fn preferences(i: &str) -> Parsed<(Preferences, Vec<&str>)> {
+    map(separated_list1(tag(","), map(is_not(","), str::trim)), |v: Vec<&str>| {
+        let mut p = Preferences::default();
+        let mut invalid = vec![];
+        for t in v {
+            let pair = t.split_once('=').map(|(k, v)| (k.trim(), v.trim()));
+            match pair {
+                Some(("resolution", "merge-duplicates")) => p.resolution = Some(Resolution::MergeDuplicates),
+                Some(("resolution", "ignore-duplicates")) => p.resolution = Some(Resolution::IgnoreDuplicates),
+                Some(("return", "representation")) => p.representation = Some(Representation::Full),
+                Some(("return", "minimal")) => p.representation = Some(Representation::None),
+                Some(("return", "headers-only")) => p.representation = Some(Representation::HeadersOnly),
+                Some(("count", "exact")) => p.count = Some(Count::ExactCount),
+                Some(("count", "planned")) => p.count = Some(Count::PlannedCount),
+                Some(("count", "estimated")) => p.count = Some(Count::EstimatedCount),
+                Some(("tx", "commit")) => p.transaction = Some(Transaction::Commit),
+                Some(("tx", "rollback")) => p.transaction = Some(Transaction::Rollback),
+                Some(("handling", "strict")) => p.handling = Some(Handling::Strict),
+                Some(("handling", "lenient")) => p.handling = Some(Handling::Lenient),
+                Some(("max-affected", n)) if n.parse::<u64>().is_ok() => p.max_affected = n.parse().ok(),
+                _ => invalid.push(t),
+            }
+        }
+        (p, invalid)
+    })(i)
 }
 
 fn logic_condition<'a, 'b>(n: Option<&'b bool>, lo: Option<&'b LogicOperator>, i: &'a str) -> Parsed<'a, Condition<'a>> {
@@ -1450,11 +1671,30 @@ fn split_select(select: Vec<SelectKind>) -> (Vec<SelectItem>, Vec<SubSelect>) {
     (sel, sub_sel)
 }
 
+// a child is at most one row when the foreign key columns cover its primary key
+fn is_to_one(db_schema: &DbSchema, join: &Join) -> bool {
+    match join {
+        Parent(_) => true,
+        Child(fk) => {
+            let pk = db_schema
+                .schemas
+                .get(fk.table.0)
+                .and_then(|s| s.objects.get(fk.table.1))
+                .map(|o| o.columns.iter().filter(|(_, c)| c.primary_key).map(|(n, _)| *n).collect::<Vec<_>>())
+                .unwrap_or_default();
+            !pk.is_empty() && pk.iter().all(|c| fk.columns.contains(c))
+        }
+        Many(..) => false,
+        Computed(r) => r.to_one,
+    }
+}
+
 fn is_self_join(join: &Join) -> bool {
     match join {
         Parent(fk) => fk.table == fk.referenced_table,
         Many(_, _, _) => false,
         Child(fk) => fk.table == fk.referenced_table,
+        Computed(_) => false,
     }
 }
 
@@ -1481,7 +1721,12 @@ fn add_join_info<'a, 'b>(query: &'b mut Query<'a>, schema: &'a str, db_schema: &
     };
 
     for SubSelect {
-        query: q, join, hint, alias, ..
+        query: q,
+        join,
+        hint,
+        alias,
+        spread,
+        ..
     } in query.sub_selects.iter_mut()
     {
         if let Select {
@@ -1497,6 +1742,12 @@ fn add_join_info<'a, 'b>(query: &'b mut Query<'a>, schema: &'a str, db_schema: &
                 });
             }
             let new_join: Join<'a> = db_schema.get_join(schema, parent_table, child_table, hint)?;
+            if *spread && !is_to_one(db_schema, &new_join) {
+                return Err(Error::SpreadNotToOne {
+                    origin: parent_table.to_string(),
+                    target: child_table.to_string(),
+                });
+            }
             if is_self_join(&new_join) {
                 *table_alias = Some(ALIAS_SUFFIXES[depth as usize]);
             }
@@ -1507,6 +1758,14 @@ fn add_join_info<'a, 'b>(query: &'b mut Query<'a>, schema: &'a str, db_schema: &
                     }
                     *child_table = fk.referenced_table.1;
                 }
+                // the function is called in the from clause with the parent row as argument
+                Computed(r) => {
+                    if alias.is_none() {
+                        *alias = Some(child_table);
+                    }
+                    *table_alias = Some(r.function.1);
+                    *child_table = r.referenced_table.1;
+                }
                 _ => {}
             }
             *join = Some(new_join);
@@ -1521,6 +1780,40 @@ fn add_join_info<'a, 'b>(query: &'b mut Query<'a>, schema: &'a str, db_schema: &
     Ok(())
 }
 
+// resolve the relations used in the order terms (order=relation(column))
+fn add_order_join_info<'a>(query: &mut Query<'a>, schema: &'a str, db_schema: &'a DbSchema<'a>) -> Result<()> {
+    let Query { node, sub_selects } = query;
+    if let Select { from: (table, _), order, .. } = node {
+        let computed_columns = db_schema.get_object(schema, table).map(|o| &o.computed_columns).ok();
+        for OrderTerm { term, relation, join, .. } in order.iter_mut() {
+            // the computed columns are not output columns the select can be ordered by
+            if relation.is_none() && computed_columns.map(|c| c.contains_key(term.name)).unwrap_or(false) {
+                return Err(Error::UnsupportedFeature {
+                    message: format!("ordering by the computed column '{}' is not supported", term.name),
+                });
+            }
+            if let Some(relation) = relation {
+                // the relation can be the alias of an embed
+                let new_join = match sub_selects.iter().find(|s| s.alias == Some(*relation)).and_then(|s| s.join.clone()) {
+                    Some(j) => j,
+                    None => db_schema.get_join(schema, table, relation, &None)?,
+                };
+                if !is_to_one(db_schema, &new_join) {
+                    return Err(Error::RelatedOrderNotToOne {
+                        origin: table.to_string(),
+                        target: relation.to_string(),
+                    });
+                }
+                *join = Some(new_join);
+            }
+        }
+    }
+    for SubSelect { query: q, .. } in sub_selects.iter_mut() {
+        add_order_join_info(q, schema, db_schema)?;
+    }
+    Ok(())
+}
+
 fn insert_join_conditions<'a, 'b>(query: &'b mut Query<'a>, schema: &'a str) -> Result<()> {
     let subzero_source = "subzero_source";
 
@@ -1612,6 +1905,7 @@ fn insert_join_conditions<'a, 'b>(query: &'b mut Query<'a>, schema: &'a str) ->
                         )
                         .collect()
                 }
+                Computed(_) => vec![],
             };
             q.insert_conditions(conditions)?;
             insert_join_conditions(q, schema)?;
@@ -1687,6 +1981,7 @@ fn get_returning<'a>(selects: &[SelectItem<'a>], sub_selects: &[SubSelect<'a>])
                     f.extend(fk2.referenced_columns.iter());
                     Ok(f)
                 }
+                Computed(_) => Ok(vec![STAR]),
             },
             x => Err(Error::NoRelBetween {
                 origin: "table".to_string(),
@@ -1919,6 +2214,38 @@ pub mod tests {
         let body = r#"{"id":"10"}"#;
         let b = parse("api", "myfunction", &db_schema, "POST", "dummy", vec![], Some(body), empty_hashmap.clone(), empty_hashmap.clone(), None);
         assert_eq!(b.unwrap(), api_request);
+
+        // unknown preferences are ignored unless handling=strict is requested
+        let headers = HashMap::from([("prefer", "timezone=UTC")]);
+        let d = parse("api", "myfunction", &db_schema, "GET", "dummy", vec![("id", "10")], None, headers, empty_hashmap.clone(), None);
+        assert!(d.is_ok());
+        let headers = HashMap::from([("prefer", "handling=strict, timezone=UTC")]);
+        let d = parse("api", "myfunction", &db_schema, "GET", "dummy", vec![("id", "10")], None, headers, empty_hashmap.clone(), None);
+        assert_eq!(
+            d.map_err(|e| e.json_body()).unwrap_err(),
+            AppError::InvalidPreferences {
+                invalid: "timezone=UTC".to_string()
+            }
+            .json_body()
+        );
+
+        // raw output is only available for functions returning text, xml or bytea
+        let headers = HashMap::from([("accept", "text/plain")]);
+        let c = parse("api", "myfunction", &db_schema, "GET", "dummy", vec![("id", "10")], None, headers, empty_hashmap.clone(), None);
+        assert_eq!(
+            c.map_err(|e| e.json_body()).unwrap_err(),
+            AppError::NotAcceptable {
+                accept: "text/plain".to_string(),
+                available: vec![
+                    "application/json".to_string(),
+                    "application/vnd.pgrst.object+json".to_string(),
+                    "application/vnd.pgrst.object".to_string(),
+                    "application/geo+json".to_string(),
+                    "text/csv".to_string(),
+                ],
+            }
+            .json_body()
+        );
     }
 
     #[test]
@@ -1967,6 +2294,8 @@ pub mod tests {
                 },
                 alias: None,
                 hint: None,
+                spread: false,
+                join_type: JoinType::Left,
                 join: None,
             }],
         };
@@ -2022,6 +2351,8 @@ pub mod tests {
                     },
                     alias: None,
                     hint: None,
+                    spread: false,
+                    join_type: JoinType::Left,
                     join: None
                 }]
             }
@@ -2159,6 +2490,8 @@ pub mod tests {
                             },
                             alias: None,
                             hint: None,
+                            spread: false,
+                            join_type: JoinType::Left,
                             join: Some(Parent(ForeignKey {
                                 name: "client_id_fk",
                                 table: Qi("api", "projects"),
@@ -2223,6 +2556,8 @@ pub mod tests {
                                 }
                             },
                             hint: None,
+                            spread: false,
+                            join_type: JoinType::Left,
                             alias: None,
                             join: Some(Child(ForeignKey {
                                 name: "project_id_fk",
@@ -2307,7 +2642,10 @@ pub mod tests {
                 preferences: Some(Preferences {
                     representation: Some(Representation::Full),
                     resolution: None,
-                    count: None
+                    count: None,
+                    transaction: None,
+                    handling: None,
+                    max_affected: None
                 }),
                 path: "dummy",
                 method: "POST",
@@ -2364,7 +2702,10 @@ pub mod tests {
                 preferences: Some(Preferences {
                     representation: Some(Representation::Full),
                     resolution: None,
-                    count: None
+                    count: None,
+                    transaction: None,
+                    handling: None,
+                    max_affected: None
                 }),
                 path: "dummy",
                 method: "POST",
@@ -2534,7 +2875,10 @@ pub mod tests {
                 preferences: Some(Preferences {
                     representation: Some(Representation::Full),
                     resolution: None,
-                    count: None
+                    count: None,
+                    transaction: None,
+                    handling: None,
+                    max_affected: None
                 }),
                 path: "dummy",
                 method: "POST",
@@ -2592,7 +2936,10 @@ pub mod tests {
                 preferences: Some(Preferences {
                     representation: Some(Representation::Full),
                     resolution: None,
-                    count: None
+                    count: None,
+                    transaction: None,
+                    handling: None,
+                    max_affected: None
                 }),
                 path: "dummy",
                 method: "POST",
@@ -2640,6 +2987,8 @@ pub mod tests {
                                 }
                             },
                             hint: None,
+                            spread: false,
+                            join_type: JoinType::Left,
                             alias: None,
                             join: Some(Child(ForeignKey {
                                 name: "project_id_fk",
@@ -2684,6 +3033,8 @@ pub mod tests {
                             },
                             alias: None,
                             hint: None,
+                            spread: false,
+                            join_type: JoinType::Left,
                             join: Some(Parent(ForeignKey {
                                 name: "client_id_fk",
                                 table: Qi("api", "projects"),
@@ -2873,20 +3224,72 @@ pub mod tests {
             preferences("return=minimal , resolution = merge-duplicates, count=planned, count=exact"),
             Ok((
                 "",
-                Preferences {
-                    representation: Some(Representation::None),
-                    resolution: Some(Resolution::MergeDuplicates),
-                    count: Some(Count::ExactCount)
-                },
+                (
+                    Preferences {
+                        representation: Some(Representation::None),
+                        resolution: Some(Resolution::MergeDuplicates),
+                        count: Some(Count::ExactCount),
+                        transaction: None,
+                        handling: None,
+                        max_affected: None
+                    },
+                    vec![]
+                )
+            ))
+        );
+        assert_eq!(
+            preferences("tx=rollback, return=representation, handling=lenient, timezone=UTC, count=none, max-affected=10, missing=default"),
+            Ok((
+                "",
+                (
+                    Preferences {
+                        representation: Some(Representation::Full),
+                        resolution: None,
+                        count: None,
+                        transaction: Some(Transaction::Rollback),
+                        handling: Some(Handling::Lenient),
+                        max_affected: Some(10)
+                    },
+                    vec!["timezone=UTC", "count=none", "missing=default"]
+                )
             ))
         );
     }
 
+    #[test]
+    fn parse_accept() {
+        assert_eq!(accept("*/*"), Ok(("", ApplicationJSON)));
+        assert_eq!(accept("text/csv, application/json"), Ok(("", TextCSV)));
+        assert_eq!(accept("text/csv;q=0.5, application/json"), Ok(("", ApplicationJSON)));
+        assert_eq!(accept("text/*;q=0.9, application/xml"), Ok(("", TextCSV)));
+        assert_eq!(accept("application/*"), Ok(("", ApplicationJSON)));
+        assert_eq!(accept("application/vnd.pgrst.object+json;q=0.8, */*;q=0.1"), Ok(("", SingularJSON)));
+        assert_eq!(accept("*/*, application/json;q=0"), Ok(("", TextCSV)));
+        assert_eq!(accept("text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8"), Ok(("", ApplicationJSON)));
+        assert_eq!(accept("application/geo+json, application/json;q=0.5"), Ok(("", GeoJSON)));
+        assert_eq!(accept("application/*;q=0.5, application/geo+json;q=0.4"), Ok(("", ApplicationJSON)));
+        assert!(accept("text/html, application/xml").is_err());
+        assert!(accept("application/json;q=0").is_err());
+        assert_eq!(accept("application/vnd.pgrst.plan"), Ok(("", Plan(PlanFormat::Text, vec![]))));
+        assert_eq!(
+            accept(r#"application/vnd.pgrst.plan+json; for="application/json"; options=analyze|buffers"#),
+            Ok(("", Plan(PlanFormat::Json, vec![PlanOption::Analyze, PlanOption::Buffers])))
+        );
+        assert_eq!(accept("*/*, application/vnd.pgrst.plan+text;q=0.5"), Ok(("", ApplicationJSON)));
+        assert!(accept("application/vnd.pgrst.plan+text; options=fast").is_err());
+    }
+
     #[test]
     fn parse_filter() {
         assert_eq!(filter(&None, "gte.5"), Ok(("", Filter::Op("gte", SingleVal(cow("5"), None)))));
         assert_eq!(filter(&None, "in.(1,2,3)"), Ok(("", Filter::In(ListVal(["1", "2", "3"].map(cow).to_vec(), None)))));
         assert_eq!(filter(&None, "fts.word"), Ok(("", Filter::Fts("fts", None, SingleVal(cow("word"), None)))));
+        assert_eq!(
+            filter(&None, "like(any).{a*,\"b,*\"}"),
+            Ok(("", Filter::Quant("like", Quantifier::Any, ListVal(vec![cow("a%"), cow("b,%")], None))))
+        );
+        assert_eq!(filter(&None, "gt(all).{1,2}"), Ok(("", Filter::Quant("gt", Quantifier::All, ListVal(vec![cow("1"), cow("2")], None)))));
+        assert!(filter(&None, "neq(any).{1,2}").is_err());
     }
 
     #[test]
@@ -3033,20 +3436,24 @@ pub mod tests {
 
     #[test]
     fn parse_list_element() {
-        assert_eq!(list_element("any 123 value"), Ok(("", cow("any 123 value"))));
-        assert_eq!(list_element("any123value,another"), Ok((",another", cow("any123value"))));
-        assert_eq!(list_element("any123value)"), Ok((")", cow("any123value"))));
-        assert_eq!(list_element("\"any123value,)\",another"), Ok((",another", cow("any123value,)"))));
+        assert_eq!(list_element(')', "any 123 value"), Ok(("", cow("any 123 value"))));
+        assert_eq!(list_element(')', "any123value,another"), Ok((",another", cow("any123value"))));
+        assert_eq!(list_element(')', "any123value)"), Ok((")", cow("any123value"))));
+        assert_eq!(list_element(')', "\"any123value,)\",another"), Ok((",another", cow("any123value,)"))));
     }
 
     #[test]
     fn parse_list_value() {
-        assert_eq!(list_value(&None, "()"), Ok(("", ListVal(vec![], None))));
-        assert_eq!(list_value(&None, "(any 123 value)"), Ok(("", ListVal(vec![cow("any 123 value")], None))));
-        assert_eq!(list_value(&None, "(any123value,another)"), Ok(("", ListVal(vec![cow("any123value"), cow("another")], None))));
-        assert_eq!(list_value(&None, "(\"any123 value\", another)"), Ok(("", ListVal(vec![cow("any123 value"), cow("another")], None))));
-        assert_eq!(list_value(&None, "(\"any123 value\", 123)"), Ok(("", ListVal(vec![cow("any123 value"), cow("123")], None))));
-        assert_eq!(list_value(&None, "(\"Double\\\"Quote\\\"McGraw\\\"\")"), Ok(("", ListVal(vec![cow("Double\"Quote\"McGraw\"")], None))));
+        assert_eq!(list_value(('(', ')'), &None, "()"), Ok(("", ListVal(vec![], None))));
+        assert_eq!(list_value(('(', ')'), &None, "(any 123 value)"), Ok(("", ListVal(vec![cow("any 123 value")], None))));
+        assert_eq!(list_value(('(', ')'), &None, "(any123value,another)"), Ok(("", ListVal(vec![cow("any123value"), cow("another")], None))));
+        assert_eq!(list_value(('(', ')'), &None, "(\"any123 value\", another)"), Ok(("", ListVal(vec![cow("any123 value"), cow("another")], None))));
+        assert_eq!(list_value(('(', ')'), &None, "(\"any123 value\", 123)"), Ok(("", ListVal(vec![cow("any123 value"), cow("123")], None))));
+        assert_eq!(
+            list_value(('(', ')'), &None, "(\"Double\\\"Quote\\\"McGraw\\\"\")"),
+            Ok(("", ListVal(vec![cow("Double\"Quote\"McGraw\"")], None)))
+        );
+        assert_eq!(list_value(('{', '}'), &None, "{a,\"b}\"}"), Ok(("", ListVal(vec![cow("a"), cow("b}")], None))));
     }
 
     #[test]
@@ -3078,6 +3485,23 @@ pub mod tests {
 
     #[test]
     fn parse_order() {
+        assert_eq!(
+            order("relation(field).desc"),
+            Ok((
+                "",
+                vec![OrderTerm {
+                    term: Field {
+                        name: "field",
+                        json_path: None
+                    },
+                    relation: Some("relation"),
+                    join: None,
+                    relation_policy: None,
+                    direction: Some(OrderDirection::Desc),
+                    null_order: None
+                },]
+            ))
+        );
         assert_eq!(
             order("field"),
             Ok((
@@ -3087,6 +3511,9 @@ pub mod tests {
                         name: "field",
                         json_path: None
                     },
+                    relation: None,
+                    join: None,
+                    relation_policy: None,
                     direction: None,
                     null_order: None
                 },]
@@ -3101,6 +3528,9 @@ pub mod tests {
                         name: "field",
                         json_path: None
                     },
+                    relation: None,
+                    join: None,
+                    relation_policy: None,
                     direction: Some(OrderDirection::Asc),
                     null_order: None
                 },]
@@ -3115,6 +3545,9 @@ pub mod tests {
                         name: "field",
                         json_path: None
                     },
+                    relation: None,
+                    join: None,
+                    relation_policy: None,
                     direction: Some(OrderDirection::Desc),
                     null_order: None
                 },]
@@ -3129,6 +3562,9 @@ pub mod tests {
                         name: "field",
                         json_path: None
                     },
+                    relation: None,
+                    join: None,
+                    relation_policy: None,
                     direction: Some(OrderDirection::Desc),
                     null_order: Some(OrderNulls::NullsFirst)
                 },]
@@ -3143,6 +3579,9 @@ pub mod tests {
                         name: "field",
                         json_path: None
                     },
+                    relation: None,
+                    join: None,
+                    relation_policy: None,
                     direction: Some(OrderDirection::Desc),
                     null_order: Some(OrderNulls::NullsLast)
                 },]
@@ -3157,6 +3596,9 @@ pub mod tests {
                         name: "field",
                         json_path: None
                     },
+                    relation: None,
+                    join: None,
+                    relation_policy: None,
                     direction: None,
                     null_order: Some(OrderNulls::NullsLast)
                 },]
@@ -3172,6 +3614,9 @@ pub mod tests {
                             name: "field",
                             json_path: None
                         },
+                        relation: None,
+                        join: None,
+                        relation_policy: None,
                         direction: None,
                         null_order: None
                     },
@@ -3180,6 +3625,9 @@ pub mod tests {
                             name: "field",
                             json_path: None
                         },
+                        relation: None,
+                        join: None,
+                        relation_policy: None,
                         direction: Some(OrderDirection::Asc),
                         null_order: None
                     },
@@ -3188,6 +3636,9 @@ pub mod tests {
                             name: "field",
                             json_path: None
                         },
+                        relation: None,
+                        join: None,
+                        relation_policy: None,
                         direction: Some(OrderDirection::Desc),
                         null_order: Some(OrderNulls::NullsLast)
                     },
@@ -3300,9 +3751,13 @@ pub mod tests {
                             name: "city",
                             json_path: None
                         },
+                        relation: None,
+                        join: None,
+                        relation_policy: None,
                         direction: Some(OrderDirection::Desc),
                         null_order: None,
                     }],
+                    cast: None,
                 }),
             ))
         );
@@ -3322,6 +3777,38 @@ pub mod tests {
                     ],
                     partitions: vec![],
                     orders: vec![],
+                    cast: None,
+                }),
+            ))
+        );
+        assert_eq!(
+            select_item("total:amount.sum()::int"),
+            Ok((
+                "",
+                Item(Func {
+                    alias: Some("total"),
+                    fn_name: "sum",
+                    parameters: vec![FunctionParam::Fld(Field {
+                        name: "amount",
+                        json_path: None
+                    })],
+                    partitions: vec![],
+                    orders: vec![],
+                    cast: Some("int"),
+                }),
+            ))
+        );
+        assert_eq!(
+            select_item("count()"),
+            Ok((
+                "",
+                Item(Func {
+                    alias: None,
+                    fn_name: "count",
+                    parameters: vec![],
+                    partitions: vec![],
+                    orders: vec![],
+                    cast: None,
                 }),
             ))
         );
@@ -3436,6 +3923,8 @@ pub mod tests {
                     },
                     alias: None,
                     hint: Some("hint"),
+                    spread: false,
+                    join_type: JoinType::Left,
                     join: None
                 })),
             ))
@@ -3491,6 +3980,84 @@ pub mod tests {
                     },
                     alias: None,
                     hint: Some("hint"),
+                    spread: false,
+                    join_type: JoinType::Left,
+                    join: None
+                })),
+            ))
+        );
+
+        assert_eq!(
+            select_item("...table(column0)"),
+            Ok((
+                "",
+                Sub(Box::new(SubSelect {
+                    query: Query {
+                        sub_selects: vec![],
+                        node: Select {
+                            check: None,
+                            order: vec![],
+                            groupby: vec![],
+                            limit: None,
+                            offset: None,
+                            select: vec![Simple {
+                                field: Field {
+                                    name: "column0",
+                                    json_path: None
+                                },
+                                alias: None,
+                                cast: None
+                            },],
+                            from: ("table", None),
+                            join_tables: vec![],
+                            where_: ConditionTree {
+                                operator: And,
+                                conditions: vec![]
+                            }
+                        }
+                    },
+                    alias: None,
+                    hint: None,
+                    spread: true,
+                    join_type: JoinType::Left,
+                    join: None
+                })),
+            ))
+        );
+
+        assert_eq!(
+            select_item("table!inner(column0)"),
+            Ok((
+                "",
+                Sub(Box::new(SubSelect {
+                    query: Query {
+                        sub_selects: vec![],
+                        node: Select {
+                            check: None,
+                            order: vec![],
+                            groupby: vec![],
+                            limit: None,
+                            offset: None,
+                            select: vec![Simple {
+                                field: Field {
+                                    name: "column0",
+                                    json_path: None
+                                },
+                                alias: None,
+                                cast: None
+                            },],
+                            from: ("table", None),
+                            join_tables: vec![],
+                            where_: ConditionTree {
+                                operator: And,
+                                conditions: vec![]
+                            }
+                        }
+                    },
+                    alias: None,
+                    hint: None,
+                    spread: false,
+                    join_type: JoinType::Inner,
                     join: None
                 })),
             ))
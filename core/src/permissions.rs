@@ -19,6 +19,7 @@ use std::collections::HashSet;
 use crate::api::ConditionTree;
 use crate::api::{
     ApiRequest, FunctionParam, Query, SubSelect, QueryNode::*, SelectItem::Func, SelectItem, Qi, Condition, LogicOperator::*, ColumnName, Field,
+    OrderTerm, Join::*,
 };
 use crate::error::*;
 use crate::schema::{Role, DbSchema, ColumnPermissions::*, ColumnPermissions, Action, Policy, Object};
@@ -53,6 +54,18 @@ fn get_select_columns<'a>(select: &[SelectItem<'a>]) -> ColumnPermissions<'a> {
     })
 }
 
+// the tables and columns of the to-one relations used in order=relation(column)
+fn get_order_relations<'a>(order: &[OrderTerm<'a>]) -> Vec<(&'a str, &'a str)> {
+    order
+        .iter()
+        .filter_map(|t| match &t.join {
+            Some(Parent(fk)) => Some((fk.referenced_table.1, t.term.name)),
+            Some(Child(fk)) => Some((fk.table.1, t.term.name)),
+            _ => None,
+        })
+        .collect()
+}
+
 fn get_policies_for_relation<'a>(
     object: &'a Object, action: Action, role: Role<'a>, permissive_policies: &mut Vec<&'a Policy<'a>>, restrictive_policies: &mut Vec<&'a Policy<'a>>,
 ) {
@@ -570,6 +583,19 @@ pub fn insert_policy_conditions<'d: 'a, 'a>(db_schema: &'d DbSchema, current_sch
 
     let (security_quals, with_check_options) = get_row_security_policies(rel, role, action, apply_select_policies, has_on_conflict_update);
 
+    // the related columns used for ordering are read in a subquery that also needs the policies of the related table
+    if let Select { order, .. } = &mut query.node {
+        for term in order.iter_mut() {
+            let table = match &term.join {
+                Some(Parent(fk)) => fk.referenced_table.1,
+                Some(Child(fk)) => fk.table.1,
+                _ => continue,
+            };
+            let related = schema.objects.get(table).context(UnknownRelationSnafu { relation: table.to_owned() })?;
+            term.relation_policy = get_row_security_policies(related, role, Action::Select, false, false).0;
+        }
+    }
+
     match query.node {
         Select {
             where_: ConditionTree { ref mut conditions, .. },
@@ -663,6 +689,13 @@ pub fn check_privileges<'a>(db_schema: &'a DbSchema<'a>, current_schema: &'a str
             };
             let columns = get_select_columns(select);
             db_schema.has_select_privileges(user, current_schema, origin, &columns)?;
+
+            // the columns of the related tables used for ordering are also read
+            if let Select { order, .. } = n {
+                for (table, column) in get_order_relations(order) {
+                    db_schema.has_select_privileges(user, current_schema, table, &Specific(vec![column]))?;
+                }
+            }
         }
         Ok(())
     } else {
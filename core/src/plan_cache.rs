@@ -0,0 +1,473 @@
+// Copyright (c) 2022-2025 subZero Cloud S.R.L
+//
+// This file is part of subZero - The All-in-One library suite for internal tools development
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+// cache of the statements generated for requests that have the same shape
+// requests that differ only in the literal values (filter values, limits, payload, env) reuse the statement text
+// and the order of its parameters, only the new values are bound
+use crate::api::{ApiRequest, Condition, ConditionTree, Filter, FunctionParam, Query, QueryNode::*, SelectItem, SingleVal, SubSelect};
+use crate::error::Result;
+use crate::formatter::{Param, SqlParam};
+use itertools::Itertools;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc, RwLock,
+};
+
+struct Plan {
+    statement: Arc<str>,
+    // for each parameter of the statement, the position of its value in the request values
+    layout: Vec<usize>,
+    value_count: usize,
+    // the request values that were not bound as parameters, they are part of the statement text
+    inlined: Vec<(usize, String)>,
+}
+
+struct Entry {
+    plan: Arc<Plan>,
+    last_used: AtomicU64,
+}
+
+pub struct PlanCache {
+    capacity: usize,
+    // advanced on every use of a plan, the plan used the longest time ago is evicted when the cache is full
+    clock: AtomicU64,
+    plans: RwLock<HashMap<String, Entry>>,
+}
+
+impl PlanCache {
+    // a capacity of 0 disables the cache
+    pub fn new(capacity: usize) -> Self {
+        PlanCache {
+            capacity,
+            clock: AtomicU64::new(0),
+            plans: RwLock::new(HashMap::new()),
+        }
+    }
+
+    fn tick(&self) -> u64 {
+        self.clock.fetch_add(1, Ordering::Relaxed)
+    }
+
+    // the plans depend on the schema (permissions, relations) so they are dropped when it is reloaded
+    pub fn clear(&self) {
+        self.plans.write().unwrap().clear();
+    }
+
+    // returns the statement and the parameters for the request, generate is called only when the request shape is not cached
+    pub fn statement<'a, F>(
+        &self, request: &'a ApiRequest<'a>, env: &'a HashMap<&'a str, &'a str>, generate: F,
+    ) -> Result<(Arc<str>, Vec<&'a SqlParam<'a>>)>
+    where
+        F: FnOnce() -> Result<(String, Vec<&'a SqlParam<'a>>)>,
+    {
+        if self.capacity == 0 {
+            let (statement, parameters) = generate()?;
+            return Ok((statement.into(), parameters));
+        }
+
+        let (shape, values) = request_shape(request, env);
+        let plan = self.plans.read().unwrap().get(&shape).map(|e| {
+            e.last_used.store(self.tick(), Ordering::Relaxed);
+            e.plan.clone()
+        });
+        if let Some(plan) = plan {
+            if plan.value_count == values.len() && plan.inlined.iter().all(|(i, v)| value_text(values[*i]) == *v) {
+                return Ok((plan.statement.clone(), plan.layout.iter().map(|i| values[*i]).collect()));
+            }
+        }
+
+        let (statement, parameters) = generate()?;
+        let statement: Arc<str> = statement.into();
+        // parameters that can not be traced back to exactly one request value make the statement not reusable
+        let layout = parameters
+            .iter()
+            .map(|p| match values.iter().positions(|v| same_value(*v, *p)).collect::<Vec<_>>()[..] {
+                [i] => Some(i),
+                _ => None,
+            })
+            .collect::<Option<Vec<_>>>();
+        if let Some(layout) = layout {
+            let inlined = (0..values.len())
+                .filter(|i| !layout.contains(i))
+                .map(|i| (i, value_text(values[i])))
+                .collect();
+            let mut plans = self.plans.write().unwrap();
+            if plans.len() >= self.capacity && !plans.contains_key(&shape) {
+                let least_recently_used = plans
+                    .iter()
+                    .min_by_key(|(_, e)| e.last_used.load(Ordering::Relaxed))
+                    .map(|(k, _)| k.clone());
+                if let Some(k) = least_recently_used {
+                    plans.remove(&k);
+                }
+            }
+            plans.insert(
+                shape,
+                Entry {
+                    plan: Arc::new(Plan {
+                        statement: statement.clone(),
+                        layout,
+                        value_count: values.len(),
+                        inlined,
+                    }),
+                    last_used: AtomicU64::new(self.tick()),
+                },
+            );
+        }
+        Ok((statement, parameters))
+    }
+}
+
+fn same_value(a: &SqlParam, b: &SqlParam) -> bool {
+    match (a.to_param(), b.to_param()) {
+        (Param::SV(x), Param::SV(y)) => std::ptr::eq(x, y),
+        (Param::LV(x), Param::LV(y)) => std::ptr::eq(x, y),
+        (Param::PL(x), Param::PL(y)) => std::ptr::eq(x, y),
+        (Param::Str(x), Param::Str(y)) => std::ptr::eq(x, y),
+        (Param::StrOwned(x), Param::StrOwned(y)) => std::ptr::eq(x, y),
+        _ => false,
+    }
+}
+
+fn value_text(v: &SqlParam) -> String {
+    format!("{:?}", v.to_param())
+}
+
+// everything about the request that ends up in the statement text, the values are replaced with ?
+// and collected in the order they are encountered
+fn request_shape<'a>(request: &'a ApiRequest<'a>, env: &'a HashMap<&'a str, &'a str>) -> (String, Vec<&'a SqlParam<'a>>) {
+    let mut shape = format!("{} {} {:?} {:?} env(", request.method, request.schema_name, request.accept_content_type, request.preferences);
+    let mut values = vec![];
+    for (k, v) in env.iter().sorted_by_key(|x| x.0) {
+        value(&mut shape, &mut values, k, v as &SqlParam);
+    }
+    shape.push(')');
+    query_shape(&mut shape, &mut values, &request.query);
+    (shape, values)
+}
+
+fn value<'a>(shape: &mut String, values: &mut Vec<&'a SqlParam<'a>>, name: &str, v: &'a SqlParam<'a>) {
+    let _ = write!(shape, " {name}=?");
+    values.push(v);
+}
+
+fn optional_value<'a>(shape: &mut String, values: &mut Vec<&'a SqlParam<'a>>, name: &str, v: &'a Option<SingleVal<'a>>) {
+    if let Some(v) = v {
+        value(shape, values, name, v);
+    }
+}
+
+fn query_shape<'a>(shape: &mut String, values: &mut Vec<&'a SqlParam<'a>>, query: &'a Query<'a>) {
+    match &query.node {
+        FunctionCall {
+            fn_name,
+            parameters,
+            payload,
+            return_table_type,
+            is_scalar,
+            returns_single,
+            is_multiple_call,
+            returning,
+            select,
+            where_,
+            limit,
+            offset,
+            order,
+        } => {
+            let _ = write!(
+                shape,
+                " call {fn_name:?} {parameters:?} {return_table_type:?} {is_scalar} {returns_single} {is_multiple_call} {returning:?} {order:?}"
+            );
+            value(shape, values, "payload", payload);
+            select_shape(shape, values, select);
+            tree_shape(shape, values, where_);
+            optional_value(shape, values, "limit", limit);
+            optional_value(shape, values, "offset", offset);
+        }
+        Select {
+            select,
+            from,
+            join_tables,
+            where_,
+            check,
+            limit,
+            offset,
+            order,
+            groupby,
+        } => {
+            let _ = write!(shape, " select {from:?} {join_tables:?} {order:?} {groupby:?}");
+            select_shape(shape, values, select);
+            tree_shape(shape, values, where_);
+            if let Some(check) = check {
+                shape.push_str(" check");
+                tree_shape(shape, values, check);
+            }
+            optional_value(shape, values, "limit", limit);
+            optional_value(shape, values, "offset", offset);
+        }
+        Insert {
+            into,
+            columns,
+            payload,
+            check,
+            where_,
+            returning,
+            select,
+            on_conflict,
+        } => {
+            let _ = write!(shape, " insert {into:?} {columns:?} {returning:?} {on_conflict:?}");
+            value(shape, values, "payload", payload);
+            select_shape(shape, values, select);
+            tree_shape(shape, values, where_);
+            tree_shape(shape, values, check);
+        }
+        Delete {
+            from,
+            where_,
+            returning,
+            select,
+        } => {
+            let _ = write!(shape, " delete {from:?} {returning:?}");
+            select_shape(shape, values, select);
+            tree_shape(shape, values, where_);
+        }
+        Update {
+            table,
+            columns,
+            payload,
+            check,
+            where_,
+            returning,
+            select,
+        } => {
+            let _ = write!(shape, " update {table:?} {columns:?} {returning:?}");
+            value(shape, values, "payload", payload);
+            select_shape(shape, values, select);
+            tree_shape(shape, values, where_);
+            tree_shape(shape, values, check);
+        }
+    }
+    for SubSelect {
+        query,
+        alias,
+        hint,
+        join,
+        spread,
+        join_type,
+    } in &query.sub_selects
+    {
+        let _ = write!(shape, " sub({alias:?} {hint:?} {join:?} {spread} {join_type:?}");
+        query_shape(shape, values, query);
+        shape.push(')');
+    }
+}
+
+fn select_shape<'a>(shape: &mut String, values: &mut Vec<&'a SqlParam<'a>>, select: &'a [SelectItem<'a>]) {
+    for item in select {
+        match item {
+            SelectItem::Func {
+                fn_name,
+                parameters,
+                partitions,
+                orders,
+                alias,
+                cast,
+            } => {
+                let _ = write!(shape, " fn({fn_name} {partitions:?} {orders:?} {alias:?} {cast:?}");
+                function_params_shape(shape, values, parameters);
+                shape.push(')');
+            }
+            i => {
+                let _ = write!(shape, " {i:?}");
+            }
+        }
+    }
+}
+
+fn function_params_shape<'a>(shape: &mut String, values: &mut Vec<&'a SqlParam<'a>>, parameters: &'a [FunctionParam<'a>]) {
+    for p in parameters {
+        match p {
+            FunctionParam::Val(v, cast) => {
+                value(shape, values, "val", v);
+                let _ = write!(shape, "{cast:?}");
+            }
+            FunctionParam::Func { fn_name, parameters } => {
+                let _ = write!(shape, " fn({fn_name}");
+                function_params_shape(shape, values, parameters);
+                shape.push(')');
+            }
+            f => {
+                let _ = write!(shape, " {f:?}");
+            }
+        }
+    }
+}
+
+fn tree_shape<'a>(shape: &mut String, values: &mut Vec<&'a SqlParam<'a>>, tree: &'a ConditionTree<'a>) {
+    let _ = write!(shape, " {:?}(", tree.operator);
+    for c in &tree.conditions {
+        match c {
+            Condition::Group { negate, tree } => {
+                let _ = write!(shape, " {negate}");
+                tree_shape(shape, values, tree);
+            }
+            Condition::Single { field, filter, negate } => {
+                let _ = write!(shape, " {field:?} {negate}");
+                match filter {
+                    Filter::Op(o, v) => value(shape, values, o, v),
+                    Filter::Quant(o, q, v) => value(shape, values, &format!("{o} {q:?}"), v),
+                    Filter::In(v) => value(shape, values, "in", v),
+                    Filter::Fts(o, l, v) => {
+                        optional_value(shape, values, "language", l);
+                        value(shape, values, o, v);
+                    }
+                    f => {
+                        let _ = write!(shape, " {f:?}");
+                    }
+                }
+            }
+            c => {
+                let _ = write!(shape, " {c:?}");
+            }
+        }
+    }
+    shape.push(')');
+}
+
+#[cfg(all(test, feature = "postgresql"))]
+mod tests {
+    use super::*;
+    use crate::formatter::postgresql::{fmt_main_query, generate};
+    use crate::parser::postgrest::parse;
+    use crate::schema::DbSchema;
+    use pretty_assertions::assert_eq;
+
+    fn db_schema() -> DbSchema<'static> {
+        serde_json::from_str(
+            r#"{
+                "schemas":[{
+                    "name":"api",
+                    "objects":[{
+                        "kind":"table",
+                        "name":"projects",
+                        "columns":[
+                            { "name":"id", "data_type":"int", "primary_key":true },
+                            { "name":"name", "data_type":"text" }
+                        ],
+                        "foreign_keys":[]
+                    }]
+                }]
+            }"#,
+        )
+        .unwrap()
+    }
+
+    fn statement(
+        cache: &PlanCache, db_schema: &DbSchema, get: Vec<(&str, &str)>, env: &HashMap<&str, &str>, generated: &mut bool,
+    ) -> (Arc<str>, Vec<String>) {
+        let request = parse("api", "projects", db_schema, "GET", "/projects", get, None, HashMap::new(), HashMap::new(), None).unwrap();
+        let (statement, parameters) = cache
+            .statement(&request, env, || {
+                *generated = true;
+                let (s, p, _) = generate(fmt_main_query(db_schema, "api", &request, env)?);
+                Ok((s, p))
+            })
+            .unwrap();
+        (statement, parameters.iter().map(|p| value_text(*p)).collect())
+    }
+
+    #[test]
+    fn test_reuse_statement() {
+        let db_schema = db_schema();
+        let cache = PlanCache::new(10);
+        let env = HashMap::from([("role", "anonymous")]);
+        let mut generated = false;
+
+        let (s1, p1) = statement(&cache, &db_schema, vec![("id", "eq.1"), ("select", "id,name")], &env, &mut generated);
+        assert!(generated);
+        generated = false;
+        let (s2, p2) = statement(&cache, &db_schema, vec![("id", "eq.2"), ("select", "id,name")], &env, &mut generated);
+        assert!(!generated);
+        assert!(Arc::ptr_eq(&s1, &s2));
+        assert_eq!(p1.len(), p2.len());
+        assert!(p1.iter().any(|p| p.contains("\"1\"")));
+        assert!(p2.iter().any(|p| p.contains("\"2\"")));
+        assert!(!p2.iter().any(|p| p.contains("\"1\"")));
+
+        // a different user is only a different value of the env
+        let env2 = HashMap::from([("role", "webuser")]);
+        let (s3, p3) = statement(&cache, &db_schema, vec![("id", "eq.3"), ("select", "id,name")], &env2, &mut generated);
+        assert!(!generated);
+        assert!(Arc::ptr_eq(&s1, &s3));
+        assert!(p3.iter().any(|p| p.contains("webuser")));
+    }
+
+    #[test]
+    fn test_different_shapes() {
+        let db_schema = db_schema();
+        let cache = PlanCache::new(10);
+        let env = HashMap::from([("role", "anonymous")]);
+        let mut generated = false;
+
+        statement(&cache, &db_schema, vec![("id", "eq.1")], &env, &mut generated);
+        generated = false;
+        statement(&cache, &db_schema, vec![("id", "gt.1")], &env, &mut generated);
+        assert!(generated);
+        generated = false;
+        statement(&cache, &db_schema, vec![("id", "gt.1"), ("select", "id")], &env, &mut generated);
+        assert!(generated);
+        generated = false;
+        statement(&cache, &db_schema, vec![("name", "is.null")], &env, &mut generated);
+        assert!(generated);
+        generated = false;
+        statement(&cache, &db_schema, vec![("name", "is.true")], &env, &mut generated);
+        assert!(generated);
+    }
+
+    #[test]
+    fn test_evict_least_recently_used() {
+        let db_schema = db_schema();
+        let cache = PlanCache::new(2);
+        let env = HashMap::new();
+        let mut generated = false;
+
+        statement(&cache, &db_schema, vec![("id", "eq.1")], &env, &mut generated);
+        statement(&cache, &db_schema, vec![("name", "eq.a")], &env, &mut generated);
+        statement(&cache, &db_schema, vec![("id", "eq.2")], &env, &mut generated);
+        // the name filter is the least recently used plan when the third shape is added
+        statement(&cache, &db_schema, vec![("id", "gt.1")], &env, &mut generated);
+        generated = false;
+        statement(&cache, &db_schema, vec![("id", "eq.3")], &env, &mut generated);
+        assert!(!generated);
+        statement(&cache, &db_schema, vec![("name", "eq.b")], &env, &mut generated);
+        assert!(generated);
+    }
+
+    #[test]
+    fn test_disabled_cache() {
+        let db_schema = db_schema();
+        let cache = PlanCache::new(0);
+        let env = HashMap::new();
+        let mut generated = false;
+
+        statement(&cache, &db_schema, vec![("id", "eq.1")], &env, &mut generated);
+        generated = false;
+        statement(&cache, &db_schema, vec![("id", "eq.2")], &env, &mut generated);
+        assert!(generated);
+    }
+}
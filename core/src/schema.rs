@@ -14,12 +14,13 @@
 //
 // You should have received a copy of the GNU Affero General Public License
 // along with this program. If not, see <http://www.gnu.org/licenses/>.
-use crate::api::{ForeignKey, Join, Join::*, ProcParam, Qi, Condition};
+use crate::api::{ComputedRelationship, ForeignKey, Join, Join::*, ProcParam, Qi, Condition};
 use crate::error::*;
 use serde::{Deserialize, Deserializer, Serialize};
 use serde_json::Value as JsonValue;
 use snafu::OptionExt;
 use snafu::ResultExt;
+use std::borrow::Cow;
 use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::iter::FromIterator;
 use log::debug;
@@ -121,6 +122,14 @@ impl<'a> DbSchema<'a> {
 
         let origin_table = schema.objects.get(origin).context(UnknownRelationSnafu { relation: origin.to_owned() })?;
 
+        // the target is a function returning rows related to the origin
+        // people?select=friends(*)
+        if hint.is_none() {
+            if let Some(r) = origin_table.computed_relationships.iter().find(|r| r.function.1 == target) {
+                return Ok(Computed(r.clone()));
+            }
+        }
+
         match origin_table
             .foreign_keys
             .iter()
@@ -438,6 +447,11 @@ pub struct Object<'a> {
     pub columns: BTreeMap<&'a str, Column<'a>>,
     #[serde(borrow)]
     pub foreign_keys: Vec<ForeignKey<'a>>,
+    // functions taking a row of this table as their only argument
+    #[serde(borrow, default)]
+    pub computed_columns: BTreeMap<&'a str, Column<'a>>,
+    #[serde(borrow, default)]
+    pub computed_relationships: Vec<ComputedRelationship<'a>>,
     #[serde(borrow)]
     pub permissions: Permissions<'a>,
 }
@@ -451,6 +465,10 @@ struct ObjectDef<'a> {
     pub columns: BTreeMap<&'a str, Column<'a>>,
     #[serde(borrow, deserialize_with = "deserialize_foreign_keys", default)]
     pub foreign_keys: Vec<ForeignKey<'a>>,
+    #[serde(borrow, deserialize_with = "deserialize_columns", default)]
+    pub computed_columns: BTreeMap<&'a str, Column<'a>>,
+    #[serde(borrow, default)]
+    pub computed_relationships: Vec<ComputedRelationship<'a>>,
 
     #[serde(borrow, deserialize_with = "deserialize_permissions", default)]
     pub permissions: Permissions<'a>,
@@ -472,6 +490,8 @@ struct ObjectDef<'a> {
     pub return_type_schema: &'a str,
     #[serde(borrow, default, deserialize_with = "deserialize_vec_procparam")]
     parameters: Vec<ProcParam<'a>>,
+    #[serde(borrow, default)]
+    pub body: Option<Cow<'a, str>>,
 }
 
 #[derive(Deserialize)]
@@ -500,7 +520,7 @@ pub enum ProcReturnType<'a> {
 
 #[derive(Debug, PartialEq, Eq, Clone, Deserialize, Serialize)]
 pub enum PgType<'a> {
-    Scalar,
+    Scalar(&'a str),
     #[serde(borrow)]
     Composite(Qi<'a>),
 }
@@ -520,6 +540,9 @@ pub enum ObjectType<'a> {
         return_type: ProcReturnType<'a>,
         #[serde(deserialize_with = "deserialize_vec_procparam")]
         parameters: Vec<ProcParam<'a>>,
+        // sql used as the function implementation for databases without stored procedures (sqlite)
+        #[serde(borrow, default)]
+        body: Option<Cow<'a, str>>,
     },
 }
 
@@ -792,15 +815,18 @@ where
                         },
                         return_type: match (o.setof, o.composite) {
                             (true, true) => ProcReturnType::SetOf(PgType::Composite(Qi(o.return_type_schema, o.return_type))),
-                            (true, false) => ProcReturnType::SetOf(PgType::Scalar),
+                            (true, false) => ProcReturnType::SetOf(PgType::Scalar(o.return_type)),
                             (false, true) => ProcReturnType::One(PgType::Composite(Qi(o.return_type_schema, o.return_type))),
-                            (false, false) => ProcReturnType::One(PgType::Scalar),
+                            (false, false) => ProcReturnType::One(PgType::Scalar(o.return_type)),
                         },
                         parameters: o.parameters,
+                        body: o.body,
                     },
                     name: o.name,
                     columns: o.columns,
                     foreign_keys: o.foreign_keys,
+                    computed_columns: o.computed_columns,
+                    computed_relationships: o.computed_relationships,
                     permissions: o.permissions,
                 },
                 "view" => Object {
@@ -808,6 +834,8 @@ where
                     name: o.name,
                     columns: o.columns,
                     foreign_keys: o.foreign_keys,
+                    computed_columns: o.computed_columns,
+                    computed_relationships: o.computed_relationships,
                     permissions: o.permissions,
                 },
                 _ => Object {
@@ -815,6 +843,8 @@ where
                     name: o.name,
                     columns: o.columns,
                     foreign_keys: o.foreign_keys,
+                    computed_columns: o.computed_columns,
+                    computed_relationships: o.computed_relationships,
                     permissions: o.permissions,
                 },
             },
@@ -989,7 +1019,9 @@ pub fn include_files(template: String) -> String {
                 //TODO!!! this allows including any file, should this be restricted in some way?
                 let contents = fs::read_to_string(Path::new(file_name)).unwrap_or_else(|_| String::from(default_val));
                 debug!("contents for {} {}", file_name, contents);
-                format!("'{}'", contents)
+                // the contents end up in a single quoted sql string literal and doubling the quote is the
+                // standard escape understood by all the supported databases (files without quotes are included unchanged)
+                format!("'{}'", contents.replace('\'', "''"))
             }
         })
         .collect::<Vec<_>>()
@@ -1029,6 +1061,12 @@ mod tests {
         let result = include_files(template);
         println!("{result}");
         // assert!(false)
+
+        // the contents are placed in a sql string literal so quotes are doubled
+        fs::write("include_files_quotes_test.json", r#"[{"body":"select 'a'"}]"#).unwrap();
+        let result = include_files("select '[]'--include_files_quotes_test.json".to_owned());
+        fs::remove_file("include_files_quotes_test.json").unwrap();
+        assert_eq!(result, r#"select '[{"body":"select ''a''"}]'"#);
     }
 
     #[test]
@@ -1045,17 +1083,20 @@ mod tests {
                             Object {
                                 kind: Function {
                                     volatile: ProcVolatility::Volatile,
-                                    return_type: ProcReturnType::SetOf(PgType::Scalar),
+                                    return_type: ProcReturnType::SetOf(PgType::Scalar("int4")),
                                     parameters: vec![ProcParam {
                                         name: "a",
                                         type_: "integer",
                                         required: true,
                                         variadic: false,
                                     }],
+                                    body: None,
                                 },
                                 name: "myfunction",
                                 columns: [].iter().cloned().collect(),
                                 foreign_keys: [].to_vec(),
+                                computed_columns: BTreeMap::new(),
+                                computed_relationships: vec![],
                                 permissions: Permissions::default(),
                             },
                         ),
@@ -1093,6 +1134,8 @@ mod tests {
                                     referenced_columns: vec!["id"],
                                 }]
                                 .to_vec(),
+                                computed_columns: BTreeMap::new(),
+                                computed_relationships: vec![],
                                 permissions: Permissions::default(),
                             },
                         ),
@@ -1113,6 +1156,8 @@ mod tests {
                                 .cloned()
                                 .collect(),
                                 foreign_keys: [].to_vec(),
+                                computed_columns: BTreeMap::new(),
+                                computed_relationships: vec![],
                                 permissions: Permissions {
                                     grants: [(("role", Select), Specific(vec!["id", "name"]))].iter().cloned().collect(),
                                     policies: vec![(
@@ -1457,6 +1502,14 @@ mod tests {
                                                 "referenced_table":["api","projects"],
                                                 "referenced_columns": ["id"]
                                             }
+                                        ],
+                                        "computed_relationships":[
+                                            {
+                                                "function":["api","sibling_tasks"],
+                                                "table":["api","tasks"],
+                                                "referenced_table":["api","tasks"],
+                                                "to_one":false
+                                            }
                                         ]
                                     },
                                     {
@@ -1514,6 +1567,17 @@ mod tests {
                 referenced_columns: vec!["id"],
             }))
         );
+        assert_eq!(
+            db_schema
+                .get_join(&s("api"), &s("tasks"), &s("sibling_tasks"), &None)
+                .map_err(|e| format!("{e}")),
+            Ok(Computed(ComputedRelationship {
+                function: Qi("api", "sibling_tasks"),
+                table: Qi("api", "tasks"),
+                referenced_table: Qi("api", "tasks"),
+                to_one: false,
+            }))
+        );
         assert_eq!(
             db_schema
                 .get_join(&s("api"), &s("clients"), &s("projects"), &None)
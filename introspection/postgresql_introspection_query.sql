@@ -225,9 +225,12 @@ functions as (
      or coalesce(proargmodes::text[] && '{t,b,o}', false)
     ) as composite,
     p.provolatile as volatile,
-    p.provariadic > 0 as variadic
+    p.provariadic > 0 as variadic,
+    -- the table whose row type is the single argument (computed columns and relationships)
+    arg_t.typrelid as table_arg_oid
   from pg_proc p
   left join arguments a on a.oid = p.oid
+  left join pg_type arg_t on p.pronargs = 1 and arg_t.oid = p.proargtypes[0] and arg_t.typrelid <> 0
   join pg_namespace pn on pn.oid = p.pronamespace
   join base_types bt on bt.oid = p.prorettype
   join pg_type t on t.oid = bt.base
@@ -861,6 +864,29 @@ json_schema as (
                             and r.table_schema = any($1)
                             and r.foreign_table_schema = any($1)
                         ) as foreign_keys), '[]'),
+                        'computed_columns', coalesce((select json_agg(computed_columns.*) from (
+                            select
+                                f.function_name as name,
+                                f.return_type as data_type
+                            from functions f
+                            where
+                            f.table_arg_oid = t.table_oid
+                            and f.function_schema = t.table_schema
+                            and not f.setof
+                            and not f.composite
+                        ) as computed_columns), '[]'),
+                        'computed_relationships', coalesce((select json_agg(computed_relationships.*) from (
+                            select
+                                array[f.function_schema, f.function_name] as function,
+                                array[t.table_schema, t.table_name] as "table",
+                                array[f.return_type_schema, f.return_type] as referenced_table,
+                                not f.setof as to_one
+                            from functions f
+                            where
+                            f.table_arg_oid = t.table_oid
+                            and f.function_schema = t.table_schema
+                            and f.return_type_oid is not null
+                        ) as computed_relationships), '[]'),
                         'permissions', coalesce((select json_agg(permissions.*) from (
                             select
                                 p.name,
@@ -81,6 +81,30 @@ permissions as (
     from json_each(
         '[]'--permissions.json
     )
+),
+
+-- sqlite has no stored procedures, functions are declared as objects with a sql body
+-- or refer to table-valued functions available on the connection
+functions as (
+    select
+        json_set(
+            f.value,
+            '$.kind', 'function',
+            '$.permissions', (
+                select json_group_array(json(p.row)) from (
+                    select json_object(
+                        'name', pp.name,
+                        'restrictive', coalesce(pp.restrictive,0),
+                        'role', pp.role,
+                        'grant', json(pp.grant)
+                    ) as row from permissions pp
+                    where pp.table_schema = 'public' and pp.table_name = json_extract(f.value, '$.name')
+                ) p
+            )
+        ) as row
+    from json_each(
+        '[]'--functions.json
+    ) f
 )
 
 select json_object(
@@ -133,6 +157,9 @@ select json_object(
                     )
                 ) as row from tables t
                 where t.table_schema = schema_name
+                union all
+                select row from functions
+                where schema_name = 'public'
             ) o
         )
     ) as row from schemas
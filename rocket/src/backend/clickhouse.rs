@@ -26,28 +26,76 @@ use tokio::time::Duration;
 use crate::error::{Result, *};
 use crate::config::{VhostConfig, SchemaStructure::*};
 use subzero_core::{
-    api::{ApiRequest, ApiResponse, SingleVal, Payload, ListVal},
+    api::{
+        ApiRequest, ApiResponse, SingleVal, Payload, ListVal, Preferences,
+        ContentType::{SingularJSON, Plan},
+        QueryNode::Select,
+    },
     error::{Error as CoreError, JsonDeserializeSnafu, JsonSerializeSnafu},
     schema::{DbSchema, replace_json_str},
     formatter::{
-        Param::*,
+        Param::*, SqlParam,
         clickhouse::{fmt_main_query, generate},
     },
 };
 use std::collections::HashMap;
+use std::sync::Arc;
 //use std::panic::resume_unwind;
 use async_trait::async_trait;
 // use http::Error as HttpError;
 // use log::{debug};
-use super::{Backend, DbSchemaWrap, include_files};
+use super::{Backend, DbSchemaWrap, include_files, DbSchemaCache};
 
 use std::fs;
 use std::path::Path;
-use serde_json::Value as JsonValue;
+use serde::Deserialize;
+use serde_json::{Value as JsonValue, value::RawValue};
 use http::Method;
 use base64::{Engine as _, engine::general_purpose};
 
-type HttpClient = (Url, Uri, Client);
+type HttpClient = (Url, Uri, Client, Transport);
+
+// how the parameters of the main query are formatted and how the response is transferred
+// both send the query and its parameters in a multipart form body
+// the response is the JSON output format in both cases, only its transfer is compressed
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Transport {
+    // the parameters as plain strings
+    Form,
+    // the parameters in the text form of their ClickHouse types and a compressed response
+    Compressed,
+}
+// the db_uri schemes selecting the compressed transport and the http schemes they stand for
+// (clickhouse+compressed is an alias of clickhouse+native, both use the http interface)
+const COMPRESSED_TRANSPORT_SCHEMES: [(&str, &str); 4] = [
+    ("clickhouse+native+https://", "https://"),
+    ("clickhouse+native://", "http://"),
+    ("clickhouse+compressed+https://", "https://"),
+    ("clickhouse+compressed://", "http://"),
+];
+
+fn transport_uri(uri: &str) -> (String, Transport) {
+    COMPRESSED_TRANSPORT_SCHEMES
+        .iter()
+        .find_map(|(scheme, http)| uri.strip_prefix(scheme).map(|rest| (format!("{http}{rest}"), Transport::Compressed)))
+        .unwrap_or((uri.to_string(), Transport::Form))
+}
+
+// the parts of the ClickHouse JSON output format that are used to build the response
+#[derive(Deserialize)]
+struct JsonOutput<'a> {
+    #[serde(borrow)]
+    data: Vec<&'a RawValue>,
+    rows: u64,
+    rows_before_limit_at_least: Option<u64>,
+}
+
+// a row of the explain output
+#[derive(Deserialize)]
+struct PlanLine {
+    explain: String,
+}
+
 type Pool = managed::Pool<Manager>;
 struct Manager {
     uri: String,
@@ -62,11 +110,14 @@ impl managed::Manager for Manager {
     type Error = reqwest::Error;
 
     async fn create(&self) -> Result<HttpClient, reqwest::Error> {
+        let (uri, transport) = transport_uri(&self.uri);
         let client = Client::builder()
             .tcp_keepalive(Some(TCP_KEEPALIVE))
             .pool_idle_timeout(Some(POOL_IDLE_TIMEOUT))
+            .zstd(transport == Transport::Compressed)
+            .gzip(transport == Transport::Compressed)
             .build()?;
-        Ok((self.uri.parse::<Url>().unwrap(), self.uri.parse::<Uri>().unwrap(), client))
+        Ok((uri.parse::<Url>().unwrap(), uri.parse::<Uri>().unwrap(), client, transport))
     }
 
     async fn recycle(&self, _: &mut HttpClient, _: &managed::Metrics) -> managed::RecycleResult<reqwest::Error> {
@@ -77,40 +128,41 @@ impl managed::Manager for Manager {
 async fn execute<'a>(
     schema: &DbSchema<'a>, pool: &Pool, _authenticated: bool, request: &ApiRequest<'_>, env: &HashMap<&str, &str>, _config: &VhostConfig,
 ) -> Result<ApiResponse> {
+    if matches!(request.accept_content_type, Plan(..)) && !matches!(request.query.node, Select { .. }) {
+        return Err(to_core_error(CoreError::UnsupportedFeature {
+            message: "The execution plan is only available for read requests".to_string(),
+        }));
+    }
     let o = pool.get().await.unwrap(); //.context(ClickhouseDbPoolError)?;
     let uri = &o.0;
     let base_url = &o.1;
     let client = &o.2;
     let (main_statement, main_parameters, _) = generate(fmt_main_query(schema, request.schema_name, request, env).context(CoreSnafu)?);
     debug!("main_statement {}", main_statement);
-    let mut parameters = vec![("query".to_string(), main_statement)];
+    let transport = o.3;
+    let mut parameters = vec![];
     for (k, v) in main_parameters.iter().enumerate() {
-        let p = match v.to_param() {
-            SV(SingleVal(v, _)) => v.to_string(),
-            LV(ListVal(v, _)) => format!("[{}]", v.join(",")),
-            PL(Payload(v, _)) => v.to_string(),
-            StrOwned(v) => v.clone(),
-            Str(v) => v.to_string(),
+        let p = match (transport, v.to_param()) {
+            (Transport::Compressed, _) => fmt_typed_param(*v),
+            (Transport::Form, SV(SingleVal(v, _))) => v.to_string(),
+            (Transport::Form, LV(ListVal(v, _))) => format!("[{}]", v.join(",")),
+            (Transport::Form, PL(Payload(v, _))) => v.to_string(),
+            (Transport::Form, StrOwned(v)) => v.clone(),
+            (Transport::Form, Str(v)) => v.to_string(),
         };
         parameters.push((format!("param_p{}", k + 1), p));
     }
     debug!("parameters {:?}", parameters);
 
-    let form = parameters.into_iter().fold(reqwest::multipart::Form::new(), |form, (k, v)| {
-        form.text(k, v)
-    });
-    // let formdata = FormData {
-    //     fields: parameters,
-    //     files: vec![],
-    // };
-    // let mut http_body: Vec<u8> = Vec::new();
-    // let boundary = generate_boundary();
-    // write_formdata(&mut http_body, &boundary, &formdata).expect("write_formdata error");
-
-    let mut http_request = client
-        .post(base_url.to_string())
-        .multipart(form);
-    //.body(Body::from(http_body)).context(HttpRequestError)?;
+    // the parameters (also the payload of mutations) go in the body, only the settings go in the url
+    let form = std::iter::once(("query".to_string(), main_statement))
+        .chain(parameters)
+        .fold(reqwest::multipart::Form::new(), |form, (k, v)| form.text(k, v));
+    let mut http_request = client.post(base_url.to_string());
+    if transport == Transport::Compressed {
+        http_request = http_request.query(&[("enable_http_compression", "1")]);
+    }
+    let mut http_request = http_request.multipart(form);
     if uri.username() != "" {
         http_request = http_request.header(
             reqwest::header::AUTHORIZATION,
@@ -120,43 +172,61 @@ async fn execute<'a>(
 
     //let http_request = http_request.body(http_body);
     let http_response = http_request.send().await.context(ReqwestSnafu)?;
-    let page_total = {
-        let headers = http_response.headers();
-        debug!("headers {:?}", headers);
-        match headers.get("x-clickhouse-summary") {
-        Some(s) => match serde_json::from_str::<JsonValue>(s.to_str().unwrap_or("")) {
-            Ok(v) => {
-                debug!("read_rows {:?}", v["read_rows"].as_str());
-                v["read_rows"].as_str().unwrap_or("0").parse().unwrap_or(0)
-            }
-            Err(_) => 0,
-        },
-        None => 0,
-    }};
     let status = http_response.status();
     let body = http_response.text().await.context(ReqwestSnafu)?;
     debug!("status {:?}", status);
-    //debug!("headers {:?}", headers);
-    // let bytes = hyper::body::to_bytes(body).await.context(HyperSnafu)?;
-    // let body = String::from_utf8(bytes.to_vec()).unwrap_or_default();
-    
-    debug!("page_total {:?}", page_total);
+    if !status.is_success() {
+        return Err(Error::Internal { message: body });
+    }
+
+    // the main query uses the JSON output format so the rows and the statistics come in the same response
+    let output: JsonOutput = serde_json::from_str(&body).context(JsonDeserializeSnafu).context(CoreSnafu)?;
+    if let Plan(..) = request.accept_content_type {
+        let lines = output
+            .data
+            .iter()
+            .map(|r| serde_json::from_str::<PlanLine>(r.get()).map(|l| l.explain))
+            .collect::<Result<Vec<_>, _>>()
+            .context(JsonDeserializeSnafu)
+            .context(CoreSnafu)?;
+        return Ok(ApiResponse {
+            page_total: 0,
+            total_result_set: None,
+            top_level_offset: 0,
+            response_headers: None,
+            response_status: None,
+            body: lines.join("\n"),
+        });
+    }
+    let page_total = output.rows;
+    let top_level_offset = match &request.query.node {
+        Select { offset: Some(SingleVal(o, _)), .. } => o.parse().unwrap_or(0),
+        _ => 0,
+    };
+    let total_result_set = match &request.preferences {
+        Some(Preferences { count: Some(_), .. }) => Some(output.rows_before_limit_at_least.unwrap_or(top_level_offset + page_total)),
+        _ => None,
+    };
+    debug!("page_total {:?} total_result_set {:?}", page_total, total_result_set);
+    let body = match request.accept_content_type {
+        SingularJSON => output.data.first().map(|r| r.get().to_string()).unwrap_or_default(),
+        _ => format!("[{}]", output.data.iter().map(|r| r.get()).collect::<Vec<_>>().join(",")),
+    };
     let api_response = ApiResponse {
         page_total,
-        total_result_set: None,
-        top_level_offset: 0,
+        total_result_set,
+        top_level_offset,
         response_headers: None,
         response_status: None,
         body,
     };
 
-    // if request.accept_content_type == SingularJSON && api_response.page_total != 1 {
-    //     //transaction.rollback().await.context(PgDbError { authenticated })?;
-    //     return Err(Error::SingularityError {
-    //         count: api_response.page_total,
-    //         content_type: "application/vnd.pgrst.object+json".to_string(),
-    //     });
-    // }
+    if request.accept_content_type == SingularJSON && api_response.page_total != 1 {
+        return Err(to_core_error(CoreError::SingularityError {
+            count: api_response.page_total,
+            content_type: "application/vnd.pgrst.object+json".to_string(),
+        }));
+    }
 
     if request.method == Method::PUT && api_response.page_total != 1 {
         // Makes sure the querystring pk matches the payload pk
@@ -176,96 +246,158 @@ async fn execute<'a>(
     Ok(api_response)
 }
 
-pub struct ClickhouseBackend {
-    config: VhostConfig,
-    pool: Pool,
-    db_schema: DbSchemaWrap,
+// formats a parameter in the text form ClickHouse parses for the type of its placeholder
+fn fmt_typed_param(p: &SqlParam) -> String {
+    match p.to_param() {
+        SV(SingleVal(v, _)) => escape_param(v),
+        LV(ListVal(v, t)) => {
+            let quote = !t.as_deref().map(is_numeric_type).unwrap_or(false);
+            let elements = v
+                .iter()
+                .map(|e| if quote { format!("'{}'", escape_param(e).replace('\'', "\\'")) } else { e.to_string() })
+                .collect::<Vec<_>>();
+            format!("[{}]", elements.join(","))
+        }
+        PL(Payload(v, _)) => escape_param(v),
+        StrOwned(v) => escape_param(v),
+        Str(v) => escape_param(v),
+    }
 }
 
-#[async_trait]
-impl Backend for ClickhouseBackend {
-    async fn init(_vhost: String, config: VhostConfig) -> Result<Self> {
-        //setup db connection
-        let mgr = Manager { uri: config.db_uri.clone() };
-        let pool = Pool::builder(mgr).max_size(config.db_pool).build().unwrap();
-        //read db schema
-        let db_schema: DbSchemaWrap = match config.db_schema_structure.clone() {
-            SqlFile(f) => match fs::read_to_string(
-                vec![&f, &format!("clickhouse_{f}")]
-                    .into_iter()
-                    .find(|f| Path::new(f).exists())
-                    .unwrap_or(&f),
-            ) {
-                Ok(q) => {
-                    let o = pool.get().await.context(ClickhouseDbPoolSnafu)?;
-                    let uri = &o.0;
-                    let base_url = &o.1;
-                    let client = &o.2;
-                    let query = include_files(q);
-                    let parameters = vec![
-                            ("param_p1".to_owned(), format!("['{}']", config.db_schemas.join("','"))),
-                            ("query".to_owned(), query),
-                    ];
-                    let form = parameters.into_iter().fold(reqwest::multipart::Form::new(), |form, (k, v)| {
-                        form.text(k, v)
-                    });
-                    let mut http_request = client
-                        .post(base_url.to_string())
-                        .multipart(form);
-                    if uri.username() != "" {
-                        http_request = http_request.header(
-                            reqwest::header::AUTHORIZATION,
-                            format!(
-                                "Basic {}",
-                                general_purpose::STANDARD_NO_PAD.encode(format!("{}:{}", uri.username(), uri.password().unwrap_or_default()))
-                            ),
-                        );
-                    }
+fn escape_param(v: &str) -> String {
+    v.replace('\\', "\\\\").replace('\t', "\\t").replace('\n', "\\n")
+}
+
+// values of numeric types (also inside arrays) are not quoted
+fn is_numeric_type(t: &str) -> bool {
+    let mut t = t;
+    while let Some(inner) = ["Array(", "Nullable(", "LowCardinality("].iter().find_map(|w| t.strip_prefix(w)) {
+        t = inner;
+    }
+    !t.starts_with("Interval") && ["Int", "UInt", "Float", "Decimal", "Bool"].iter().any(|n| t.starts_with(n))
+}
 
-                    let http_response = http_request.send().await.context(ReqwestSnafu)?;
-                    let s = http_response.text().await.context(ReqwestSnafu)?;
-                    //println!("s: {}", s);
-                    let mut v: JsonValue = serde_json::from_str(&s).context(JsonDeserializeSnafu).context(CoreSnafu)?;
-                    replace_json_str(&mut v).context(CoreSnafu)?;
-                    let s = serde_json::to_string_pretty(&v).context(JsonSerializeSnafu).context(CoreSnafu)?;
+async fn load_schema(pool: &Pool, config: &VhostConfig) -> Result<DbSchemaWrap> {
+    let db_schema: DbSchemaWrap = match config.db_schema_structure.clone() {
+        SqlFile(f) => match fs::read_to_string(
+            vec![&f, &format!("clickhouse_{f}")]
+                .into_iter()
+                .find(|f| Path::new(f).exists())
+                .unwrap_or(&f),
+        ) {
+            Ok(q) => {
+                let o = pool.get().await.context(ClickhouseDbPoolSnafu)?;
+                let uri = &o.0;
+                let base_url = &o.1;
+                let client = &o.2;
+                let query = include_files(q);
+                let parameters = vec![
+                        ("param_p1".to_owned(), format!("['{}']", config.db_schemas.join("','"))),
+                        ("query".to_owned(), query),
+                ];
+                let form = parameters.into_iter().fold(reqwest::multipart::Form::new(), |form, (k, v)| {
+                    form.text(k, v)
+                });
+                let mut http_request = client
+                    .post(base_url.to_string())
+                    .multipart(form);
+                if uri.username() != "" {
+                    http_request = http_request.header(
+                        reqwest::header::AUTHORIZATION,
+                        format!(
+                            "Basic {}",
+                            general_purpose::STANDARD_NO_PAD.encode(format!("{}:{}", uri.username(), uri.password().unwrap_or_default()))
+                        ),
+                    );
+                }
 
-                    Ok(DbSchemaWrap::new(s, |s| {
-                        serde_json::from_str::<DbSchema>(s.as_str())
-                            .context(JsonDeserializeSnafu)
-                            .context(CoreSnafu)
-                    }))
-                },
-                Err(e) => Err(e).context(ReadFileSnafu { path: f }),
-            },
-            JsonFile(f) => match fs::read_to_string(&f) {
-                Ok(s) => Ok(DbSchemaWrap::new(s, |s| {
+                let http_response = http_request.send().await.context(ReqwestSnafu)?;
+                let s = http_response.text().await.context(ReqwestSnafu)?;
+                //println!("s: {}", s);
+                let mut v: JsonValue = serde_json::from_str(&s).context(JsonDeserializeSnafu).context(CoreSnafu)?;
+                replace_json_str(&mut v).context(CoreSnafu)?;
+                let s = serde_json::to_string_pretty(&v).context(JsonSerializeSnafu).context(CoreSnafu)?;
+
+                Ok(DbSchemaWrap::new(s, |s| {
                     serde_json::from_str::<DbSchema>(s.as_str())
                         .context(JsonDeserializeSnafu)
                         .context(CoreSnafu)
-                })),
-                Err(e) => Err(e).context(ReadFileSnafu { path: f }),
+                }))
             },
-            JsonString(s) => Ok(DbSchemaWrap::new(s, |s| {
+            Err(e) => Err(e).context(ReadFileSnafu { path: f }),
+        },
+        JsonFile(f) => match fs::read_to_string(&f) {
+            Ok(s) => Ok(DbSchemaWrap::new(s, |s| {
                 serde_json::from_str::<DbSchema>(s.as_str())
                     .context(JsonDeserializeSnafu)
                     .context(CoreSnafu)
             })),
-        }?;
+            Err(e) => Err(e).context(ReadFileSnafu { path: f }),
+        },
+        JsonString(s) => Ok(DbSchemaWrap::new(s, |s| {
+            serde_json::from_str::<DbSchema>(s.as_str())
+                .context(JsonDeserializeSnafu)
+                .context(CoreSnafu)
+        })),
+    }?;
+    db_schema.validate()
+}
 
-        if let Err(e) = db_schema.with_schema(|s| s.as_ref()) {
-            let message = format!("Backend init failed: {e}");
-            return Err(crate::Error::Internal { message });
-        }
+pub struct ClickhouseBackend {
+    config: VhostConfig,
+    pool: Pool,
+    db_schema: DbSchemaCache,
+}
+
+#[async_trait]
+impl Backend for ClickhouseBackend {
+    async fn init(_vhost: String, config: VhostConfig) -> Result<Self> {
+        //setup db connection
+        let mgr = Manager { uri: config.db_uri.clone() };
+        let pool = Pool::builder(mgr).max_size(config.db_pool).build().unwrap();
+        //read db schema
+        let db_schema = DbSchemaCache::new(load_schema(&pool, &config).await?);
 
         Ok(ClickhouseBackend { config, pool, db_schema })
     }
-    async fn execute(&self, authenticated: bool, request: &ApiRequest, env: &HashMap<&str, &str>) -> Result<ApiResponse> {
-        execute(self.db_schema(), &self.pool, authenticated, request, env, &self.config).await
+    async fn execute(&self, db_schema: &DbSchema, authenticated: bool, request: &ApiRequest, env: &HashMap<&str, &str>) -> Result<ApiResponse> {
+        execute(db_schema, &self.pool, authenticated, request, env, &self.config).await
     }
-    fn db_schema(&self) -> &DbSchema {
-        self.db_schema.borrow_schema().as_ref().unwrap()
+    fn db_schema(&self) -> Arc<DbSchemaWrap> {
+        self.db_schema.get()
+    }
+    async fn reload_schema(&self) -> Result<()> {
+        self.db_schema.set(load_schema(&self.pool, &self.config).await?);
+        Ok(())
     }
     fn config(&self) -> &VhostConfig {
         &self.config
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::borrow::Cow;
+
+    #[test]
+    fn typed_params() {
+        let s = SingleVal(Cow::Borrowed("a\\b\tc"), Some(Cow::Borrowed("String")));
+        assert_eq!(fmt_typed_param(&s), "a\\\\b\\tc");
+        let l = ListVal(vec![Cow::Borrowed("1"), Cow::Borrowed("2")], Some(Cow::Borrowed("Array(Int32)")));
+        assert_eq!(fmt_typed_param(&l), "[1,2]");
+        let l = ListVal(vec![Cow::Borrowed("o'k"), Cow::Borrowed("b")], Some(Cow::Borrowed("Array(Nullable(String))")));
+        assert_eq!(fmt_typed_param(&l), "['o\\'k','b']");
+        assert!(!is_numeric_type("Array(IntervalDay)"));
+        assert!(is_numeric_type("LowCardinality(Nullable(UInt8))"));
+    }
+
+    #[test]
+    fn transport_schemes() {
+        assert_eq!(transport_uri("http://default@localhost:8123/"), ("http://default@localhost:8123/".to_string(), Transport::Form));
+        assert_eq!(transport_uri("clickhouse+native://localhost:8123/"), ("http://localhost:8123/".to_string(), Transport::Compressed));
+        assert_eq!(transport_uri("clickhouse+native+https://localhost:8443/"), ("https://localhost:8443/".to_string(), Transport::Compressed));
+        assert_eq!(transport_uri("clickhouse+compressed://localhost:8123/"), ("http://localhost:8123/".to_string(), Transport::Compressed));
+        assert_eq!(transport_uri("clickhouse+compressed+https://localhost:8443/"), ("https://localhost:8443/".to_string(), Transport::Compressed));
+    }
+}
@@ -15,16 +15,19 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program. If not, see <http://www.gnu.org/licenses/>.
 use subzero_core::{
-    api::{ApiRequest, ApiResponse},
+    api::{ApiRequest, ApiResponse, ContentType::*, Preferences, QueryNode::*, Transaction},
     schema::DbSchema,
 };
 
 pub use subzero_core::schema::include_files;
 use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
 use crate::error::Result;
 use crate::config::VhostConfig;
 use async_trait::async_trait;
 use ouroboros::self_referencing;
+use rocket::futures::Stream;
+use std::pin::Pin;
 #[cfg(feature = "clickhouse")]
 pub mod clickhouse;
 #[cfg(feature = "mysql")]
@@ -39,11 +42,36 @@ pub trait Backend {
     async fn init(vhost: String, config: VhostConfig) -> Result<Self>
     where
         Self: Sized;
-    async fn execute(&self, authenticated: bool, request: &ApiRequest, env: &HashMap<&str, &str>) -> Result<ApiResponse>;
-    fn db_schema(&self) -> &DbSchema;
+    async fn execute(&self, db_schema: &DbSchema, authenticated: bool, request: &ApiRequest, env: &HashMap<&str, &str>) -> Result<ApiResponse>;
+    // executes a read request returning the body in chunks as they are read from the database
+    // backends that can not stream return None and the request is executed with execute
+    async fn execute_stream(
+        &self, _db_schema: &DbSchema, _authenticated: bool, _request: &ApiRequest, _env: &HashMap<&str, &str>,
+    ) -> Result<Option<BodyStream>> {
+        Ok(None)
+    }
+    fn db_schema(&self) -> Arc<DbSchemaWrap>;
+    async fn reload_schema(&self) -> Result<()>;
     fn config(&self) -> &VhostConfig;
 }
 
+// chunks of a response body sent to the client while the rest of the rows are still being read
+pub type BodyStream = Pin<Box<dyn Stream<Item = Vec<u8>> + Send>>;
+
+pub enum ResponseBody {
+    Text(String),
+    Stream(BodyStream),
+}
+
+// the status and headers are sent before the rows are read so only plain reads without counts are streamed
+pub fn stream_response(config: &VhostConfig, request: &ApiRequest) -> bool {
+    config.db_stream_responses
+        && request.method == "GET"
+        && matches!(request.query.node, Select { .. })
+        && matches!(request.accept_content_type, ApplicationJSON | TextCSV)
+        && !matches!(request.preferences, Some(Preferences { count: Some(_), .. }))
+}
+
 #[self_referencing]
 pub struct DbSchemaWrap {
     schema_string: String,
@@ -51,3 +79,57 @@ pub struct DbSchemaWrap {
     #[borrows(schema_string)]
     schema: Result<DbSchema<'this>>,
 }
+
+impl DbSchemaWrap {
+    pub fn schema(&self) -> &DbSchema {
+        self.borrow_schema().as_ref().unwrap()
+    }
+
+    // make sure the schema was parsed successfully before it's used
+    pub fn validate(self) -> Result<Self> {
+        if let Err(e) = self.with_schema(|s| s.as_ref()) {
+            let message = format!("Loading the db schema failed: {e}");
+            return Err(crate::Error::Internal { message });
+        }
+        Ok(self)
+    }
+}
+
+// the Prefer: tx header overrides the db_tx_rollback setting for a single request (only kept when db_tx_allow_override is set)
+pub fn rollback_transaction(config: &VhostConfig, request: &ApiRequest) -> bool {
+    match &request.preferences {
+        Some(Preferences {
+            transaction: Some(Transaction::Rollback),
+            ..
+        }) => true,
+        Some(Preferences {
+            transaction: Some(Transaction::Commit),
+            ..
+        }) => false,
+        _ => config.db_tx_rollback,
+    }
+}
+
+// Prefer: max-affected limits the number of rows an update or delete is allowed to change (only kept with handling=strict)
+pub fn exceeds_max_affected(request: &ApiRequest, affected: u64) -> bool {
+    match (&request.query.node, &request.preferences) {
+        (Update { .. } | Delete { .. }, Some(Preferences { max_affected: Some(max), .. })) => affected > *max,
+        _ => false,
+    }
+}
+
+// holds the current db schema and allows it to be replaced while the server is running
+// requests keep a reference to the schema they started with so a reload does not affect them
+pub struct DbSchemaCache(RwLock<Arc<DbSchemaWrap>>);
+
+impl DbSchemaCache {
+    pub fn new(db_schema: DbSchemaWrap) -> Self {
+        DbSchemaCache(RwLock::new(Arc::new(db_schema)))
+    }
+    pub fn get(&self) -> Arc<DbSchemaWrap> {
+        self.0.read().unwrap().clone()
+    }
+    pub fn set(&self, db_schema: DbSchemaWrap) {
+        *self.0.write().unwrap() = Arc::new(db_schema);
+    }
+}
@@ -25,8 +25,11 @@ use serde_json::json;
 use crate::config::{VhostConfig, SchemaStructure::*};
 // use log::{debug};
 use subzero_core::{
-    api::{ApiRequest, ApiResponse, ContentType::*, SingleVal, ListVal, Payload, QueryNode::*, Condition, Filter, Query, Field, Preferences, Count},
-    error::Error::{SingularityError, PutMatchingPkError, PermissionDenied},
+    api::{
+        ApiRequest, ApiResponse, ContentType::*, SingleVal, ListVal, Payload, QueryNode::*, Condition, Filter, Query, Field, Preferences, Count,
+        PlanFormat, PlanOption,
+    },
+    error::Error::{SingularityError, PutMatchingPkError, PermissionDenied, MaxAffectedViolation, UnsupportedFeature},
     schema::DbSchema,
     formatter::{
         Param,
@@ -40,11 +43,12 @@ use subzero_core::dynamic_statement::{param, sql, JoinIterator};
 use crate::error::{Result, Error, *};
 use async_trait::async_trait;
 
-use super::{Backend, DbSchemaWrap, include_files};
+use super::{Backend, DbSchemaWrap, include_files, DbSchemaCache, rollback_transaction, exceeds_max_affected};
 
 use std::borrow::Cow;
 use std::{collections::HashMap, fs};
 use std::path::Path;
+use std::sync::Arc;
 use http::Method;
 
 #[derive(Debug, PartialEq, Eq, Clone)]
@@ -116,6 +120,20 @@ async fn execute<'a>(
 ) -> Result<ApiResponse> {
     // println!("------------ pool before {:?}", pool);
     let return_representation = return_representation(request.method, &request.query, &request.preferences);
+    // mutations are followed by a select of the changed rows so there is no single statement to explain
+    if matches!(request.accept_content_type, Plan(..)) && matches!(request.query.node, Insert { .. } | Update { .. } | Delete { .. }) {
+        return Err(to_core_error(UnsupportedFeature {
+            message: "The execution plan is only available for read requests".to_string(),
+        }));
+    }
+    // explain analyze reports the plan only in the tree format
+    if let Plan(PlanFormat::Json, options) = &request.accept_content_type {
+        if options.contains(&PlanOption::Analyze) {
+            return Err(to_core_error(UnsupportedFeature {
+                message: "The analyze option is only available for the text plan in mysql".to_string(),
+            }));
+        }
+    }
     let mut client = pool.get_conn().await.context(MysqlDbSnafu { authenticated })?;
 
     let opts = TxOpts::default()
@@ -155,6 +173,30 @@ async fn execute<'a>(
     // }
     let (main_statement, main_parameters, _) = generate(fmt_main_query(db_schema, request.schema_name, request, env).context(CoreSnafu)?);
     debug!("main_statement {}\n{:?}", main_statement, main_parameters);
+    if let Plan(..) = request.accept_content_type {
+        let plan: Vec<String> = transaction
+            .exec(
+                &main_statement,
+                main_parameters
+                    .into_iter()
+                    .map(wrap_param)
+                    .collect::<Vec<_>>()
+                    .iter()
+                    .map(to_value)
+                    .collect::<Vec<_>>(),
+            )
+            .await
+            .context(MysqlDbSnafu { authenticated })?;
+        transaction.rollback().await.context(MysqlDbSnafu { authenticated })?;
+        return Ok(ApiResponse {
+            page_total: 0,
+            total_result_set: None,
+            top_level_offset: 0,
+            response_headers: None,
+            response_status: None,
+            body: plan.join("\n"),
+        });
+    }
     let response: Option<DbResponse> = transaction
         .exec_first(
             &main_statement,
@@ -371,6 +413,13 @@ async fn execute<'a>(
         }));
     }
 
+    if exceeds_max_affected(request, api_response.page_total) {
+        transaction.rollback().await.context(MysqlDbSnafu { authenticated })?;
+        return Err(to_core_error(MaxAffectedViolation {
+            count: api_response.page_total,
+        }));
+    }
+
     if request.method == Method::PUT && api_response.page_total != 1 {
         // Makes sure the querystring pk matches the payload pk
         // e.g. PUT /items?id=eq.1 { "id" : 1, .. } is accepted,
@@ -380,7 +429,7 @@ async fn execute<'a>(
         return Err(to_core_error(PutMatchingPkError));
     }
 
-    if config.db_tx_rollback {
+    if rollback_transaction(config, request) {
         transaction.rollback().await.context(MysqlDbSnafu { authenticated })?;
     } else {
         transaction.commit().await.context(MysqlDbSnafu { authenticated })?;
@@ -390,11 +439,55 @@ async fn execute<'a>(
     Ok(api_response)
 }
 
+async fn load_schema(vhost: &str, pool: &Pool, config: &VhostConfig) -> Result<DbSchemaWrap> {
+    let db_schema: DbSchemaWrap = match config.db_schema_structure.clone() {
+        SqlFile(f) => match fs::read_to_string(vec![&f, &format!("mysql_{f}")].into_iter().find(|f| Path::new(f).exists()).unwrap_or(&f)) {
+            Ok(q) => match wait_for_mysql_connection(vhost, pool).await {
+                Ok(mut client) => {
+                    let authenticated = false;
+                    let query = include_files(q);
+                    let schemas_json = serde_json::to_string(&config.db_schemas).context(JsonSerializeSnafu).context(CoreSnafu)?;
+                    match client.exec_first(&query, vec![schemas_json]).await {
+                        Ok(Some(s)) => {
+                            //let s: String = row.get(0);
+                            Ok(DbSchemaWrap::new(s, |s| {
+                                serde_json::from_str::<DbSchema>(s.as_str())
+                                    .context(JsonDeserializeSnafu)
+                                    .context(CoreSnafu)
+                            }))
+                        }
+                        Ok(None) => Err(Error::Internal {
+                            message: "db schema not found".to_string(),
+                        }),
+                        Err(e) => Err(e).context(MysqlDbSnafu { authenticated }),
+                    }
+                }
+                Err(e) => Err(e).context(MysqlDbSnafu { authenticated: false }),
+            },
+            Err(e) => Err(e).context(ReadFileSnafu { path: f }),
+        },
+        JsonFile(f) => match fs::read_to_string(&f) {
+            Ok(s) => Ok(DbSchemaWrap::new(s, |s| {
+                serde_json::from_str::<DbSchema>(s.as_str())
+                    .context(JsonDeserializeSnafu)
+                    .context(CoreSnafu)
+            })),
+            Err(e) => Err(e).context(ReadFileSnafu { path: f }),
+        },
+        JsonString(s) => Ok(DbSchemaWrap::new(s, |s| {
+            serde_json::from_str::<DbSchema>(s.as_str())
+                .context(JsonDeserializeSnafu)
+                .context(CoreSnafu)
+        })),
+    }?;
+    db_schema.validate()
+}
+
 pub struct MySQLBackend {
-    //vhost: String,
+    vhost: String,
     config: VhostConfig,
     pool: Pool,
-    db_schema: DbSchemaWrap,
+    db_schema: DbSchemaCache,
 }
 
 #[async_trait]
@@ -406,66 +499,31 @@ impl Backend for MySQLBackend {
         })?;
         let pool = Pool::new(opts);
         //read db schema
-        let db_schema: DbSchemaWrap = match config.db_schema_structure.clone() {
-            SqlFile(f) => match fs::read_to_string(vec![&f, &format!("mysql_{f}")].into_iter().find(|f| Path::new(f).exists()).unwrap_or(&f)) {
-                Ok(q) => match wait_for_mysql_connection(&vhost, &pool).await {
-                    Ok(mut client) => {
-                        let authenticated = false;
-                        let query = include_files(q);
-                        let schemas_json = serde_json::to_string(&config.db_schemas).context(JsonSerializeSnafu).context(CoreSnafu)?;
-                        match client.exec_first(&query, vec![schemas_json]).await {
-                            Ok(Some(s)) => {
-                                //let s: String = row.get(0);
-                                Ok(DbSchemaWrap::new(s, |s| {
-                                    serde_json::from_str::<DbSchema>(s.as_str())
-                                        .context(JsonDeserializeSnafu)
-                                        .context(CoreSnafu)
-                                }))
-                            }
-                            Ok(None) => Err(Error::Internal {
-                                message: "db schema not found".to_string(),
-                            }),
-                            Err(e) => Err(e).context(MysqlDbSnafu { authenticated }),
-                        }
-                    }
-                    Err(e) => Err(e).context(MysqlDbSnafu { authenticated: false }),
-                },
-                Err(e) => Err(e).context(ReadFileSnafu { path: f }),
-            },
-            JsonFile(f) => match fs::read_to_string(&f) {
-                Ok(s) => Ok(DbSchemaWrap::new(s, |s| {
-                    serde_json::from_str::<DbSchema>(s.as_str())
-                        .context(JsonDeserializeSnafu)
-                        .context(CoreSnafu)
-                })),
-                Err(e) => Err(e).context(ReadFileSnafu { path: f }),
-            },
-            JsonString(s) => Ok(DbSchemaWrap::new(s, |s| {
-                serde_json::from_str::<DbSchema>(s.as_str())
-                    .context(JsonDeserializeSnafu)
-                    .context(CoreSnafu)
-            })),
-        }?;
+        let db_schema = DbSchemaCache::new(load_schema(&vhost, &pool, &config).await?);
 
-        if let Err(e) = db_schema.with_schema(|s| s.as_ref()) {
-            let message = format!("Backend init failed: {e}");
-            return Err(crate::Error::Internal { message });
-        }
-
-        Ok(MySQLBackend { config, pool, db_schema })
+        Ok(MySQLBackend {
+            vhost,
+            config,
+            pool,
+            db_schema,
+        })
+    }
+    async fn execute(&self, db_schema: &DbSchema, authenticated: bool, request: &ApiRequest, env: &HashMap<&str, &str>) -> Result<ApiResponse> {
+        execute(db_schema, &self.pool, authenticated, request, env, &self.config).await
     }
-    async fn execute(&self, authenticated: bool, request: &ApiRequest, env: &HashMap<&str, &str>) -> Result<ApiResponse> {
-        execute(self.db_schema(), &self.pool, authenticated, request, env, &self.config).await
+    fn db_schema(&self) -> Arc<DbSchemaWrap> {
+        self.db_schema.get()
     }
-    fn db_schema(&self) -> &DbSchema {
-        self.db_schema.borrow_schema().as_ref().unwrap()
+    async fn reload_schema(&self) -> Result<()> {
+        self.db_schema.set(load_schema(&self.vhost, &self.pool, &self.config).await?);
+        Ok(())
     }
     fn config(&self) -> &VhostConfig {
         &self.config
     }
 }
 
-async fn wait_for_mysql_connection(vhost: &String, db_pool: &Pool) -> Result<Conn, MysqlError> {
+async fn wait_for_mysql_connection(vhost: &str, db_pool: &Pool) -> Result<Conn, MysqlError> {
     let mut i = 1;
     let mut time_since_start = 0;
     let max_delay_interval = 10;
@@ -14,35 +14,41 @@
 //
 // You should have received a copy of the GNU Affero General Public License
 // along with this program. If not, see <http://www.gnu.org/licenses/>.
-use tokio_postgres::IsolationLevel;
+use tokio_postgres::{AsyncMessage, IsolationLevel, Row, RowStream};
 use deadpool_postgres::{Manager, ManagerConfig, Pool, RecyclingMethod, Runtime, Timeouts, Object, PoolError};
 use openssl::ssl::{SslConnector, SslMethod, SslVerifyMode};
 use postgres_openssl::MakeTlsConnector;
 use snafu::ResultExt;
 use tokio::time::{Duration, sleep};
+use tokio::sync::mpsc;
 use crate::config::{VhostConfig, SchemaStructure::*};
 // use log::{debug};
 use subzero_core::{
     api::{ApiRequest, ApiResponse, ContentType::*, SingleVal, ListVal, Payload},
-    error::Error::{SingularityError, PutMatchingPkError, PermissionDenied},
+    error::Error::{SingularityError, PutMatchingPkError, PermissionDenied, MaxAffectedViolation},
     schema::DbSchema,
     formatter::{
         Param,
         Param::*,
-        postgresql::{fmt_main_query, generate},
+        postgresql::{fmt_main_query, fmt_stream_query, generate},
         ToParam, Snippet, SqlParam,
     },
     error::JsonDeserializeSnafu,
+    plan_cache::PlanCache,
 };
 use subzero_core::dynamic_statement::{param, sql, JoinIterator};
-use postgres_types::{to_sql_checked, Format, IsNull, ToSql, Type};
+use postgres_types::{to_sql_checked, Format, FromSql, IsNull, ToSql, Type};
 use crate::error::{Result, *};
 use async_trait::async_trait;
 
-use super::{Backend, DbSchemaWrap, include_files};
+use super::{Backend, BodyStream, DbSchemaWrap, include_files, DbSchemaCache, rollback_transaction, exceeds_max_affected};
 
 use std::{collections::HashMap, fs};
 use std::path::Path;
+use std::sync::Arc;
+use std::future::poll_fn;
+use std::pin::Pin;
+use rocket::futures::{stream, TryStreamExt};
 use http::Method;
 use bytes::{BufMut, BytesMut};
 use std::error::Error;
@@ -101,6 +107,18 @@ impl ToSql for WrapParam<'_> {
     to_sql_checked!();
 }
 
+// a line of the explain output, json or text depending on the requested format
+struct PlanText(String);
+
+impl<'a> FromSql<'a> for PlanText {
+    fn from_sql(_ty: &Type, raw: &'a [u8]) -> Result<Self, Box<dyn Error + Sync + Send>> {
+        Ok(PlanText(std::str::from_utf8(raw)?.to_string()))
+    }
+    fn accepts(ty: &Type) -> bool {
+        matches!(*ty, Type::JSON | Type::TEXT)
+    }
+}
+
 fn wrap_param(p: &'_ (dyn ToParam + Sync)) -> WrapParam<'_> {
     WrapParam(p.to_param())
 }
@@ -109,20 +127,29 @@ fn cast_param<'a>(p: &'a WrapParam<'a>) -> &'a (dyn ToSql + Sync) {
 }
 
 pub fn fmt_env_query<'a>(env: &'a HashMap<&'a str, &'a str>) -> Snippet<'a> {
+    // sorted so that the statement text is the same for every request and the prepared statement is reused
+    let mut env = env.iter().collect::<Vec<_>>();
+    env.sort_by_key(|x| x.0);
     "select "
         + if env.is_empty() {
             sql("null")
         } else {
-            env.iter()
+            env.into_iter()
                 .map(|(k, v)| "set_config(" + param(k as &SqlParam) + ", " + param(v as &SqlParam) + ", true)")
                 .join(",")
         }
 }
 async fn execute<'a>(
-    schema: &DbSchema<'a>, pool: &Pool, authenticated: bool, request: &ApiRequest<'_>, env: &HashMap<&str, &str>, config: &VhostConfig,
+    schema: &DbSchema<'a>, pool: &Pool, plan_cache: &PlanCache, authenticated: bool, request: &ApiRequest<'_>, env: &HashMap<&str, &str>,
+    config: &VhostConfig,
 ) -> Result<ApiResponse> {
     let mut client = pool.get().await.context(PgDbPoolSnafu)?;
-    let (main_statement, main_parameters, _) = generate(fmt_main_query(schema, request.schema_name, request, env).context(CoreSnafu)?);
+    let (main_statement, main_parameters) = plan_cache
+        .statement(request, env, || {
+            let (s, p, _) = generate(fmt_main_query(schema, request.schema_name, request, env)?);
+            Ok((s, p))
+        })
+        .context(CoreSnafu)?;
 
     let transaction = client
         .build_transaction()
@@ -168,10 +195,7 @@ async fn execute<'a>(
 
     debug!("main_statement {}\n{:?}", main_statement, main_parameters);
 
-    let main_stm = transaction
-        .prepare_cached(main_statement.as_str())
-        .await
-        .context(PgDbSnafu { authenticated })?;
+    let main_stm = transaction.prepare_cached(&main_statement).await.context(PgDbSnafu { authenticated })?;
 
     let rows = transaction
         .query(
@@ -188,6 +212,19 @@ async fn execute<'a>(
         .await
         .context(PgDbSnafu { authenticated })?;
 
+    // the plan is the output of explain, with analyze the statement did run so its changes are never kept
+    if let Plan(..) = request.accept_content_type {
+        transaction.rollback().await.context(PgDbSnafu { authenticated })?;
+        return Ok(ApiResponse {
+            page_total: 0,
+            total_result_set: None,
+            top_level_offset: 0,
+            response_headers: None,
+            response_status: None,
+            body: rows.iter().map(|r| r.get::<_, PlanText>(0).0).collect::<Vec<_>>().join("\n"),
+        });
+    }
+
     let constraints_satisfied: bool = rows[0].get("constraints_satisfied");
     if !constraints_satisfied {
         transaction.rollback().await.context(PgDbSnafu { authenticated })?;
@@ -213,6 +250,13 @@ async fn execute<'a>(
         }));
     }
 
+    if exceeds_max_affected(request, api_response.page_total) {
+        transaction.rollback().await.context(PgDbSnafu { authenticated })?;
+        return Err(to_core_error(MaxAffectedViolation {
+            count: api_response.page_total,
+        }));
+    }
+
     if request.method == Method::PUT && api_response.page_total != 1 {
         // Makes sure the querystring pk matches the payload pk
         // e.g. PUT /items?id=eq.1 { "id" : 1, .. } is accepted,
@@ -222,7 +266,7 @@ async fn execute<'a>(
         return Err(to_core_error(PutMatchingPkError));
     }
 
-    if config.db_tx_rollback {
+    if rollback_transaction(config, request) {
         transaction.rollback().await.context(PgDbSnafu { authenticated })?;
     } else {
         transaction.commit().await.context(PgDbSnafu { authenticated })?;
@@ -231,11 +275,242 @@ async fn execute<'a>(
     Ok(api_response)
 }
 
+// run the queries that precede the main one and read the first row so that errors are reported before the response starts
+async fn start_stream(
+    client: &Object, request: &ApiRequest<'_>, env: &HashMap<&str, &str>, config: &VhostConfig, main_statement: &str,
+    main_parameters: Vec<WrapParam<'_>>,
+) -> Result<(Pin<Box<RowStream>>, Option<Row>), tokio_postgres::Error> {
+    let (env_query, env_parameters, _) = generate(fmt_env_query(env));
+    debug!("env_query: {}\n{:?}", env_query, env_parameters);
+    let env_stm = client.prepare_cached(env_query.as_str()).await?;
+    client
+        .query(
+            &env_stm,
+            env_parameters
+                .into_iter()
+                .map(wrap_param)
+                .collect::<Vec<_>>()
+                .iter()
+                .map(cast_param)
+                .collect::<Vec<_>>()
+                .as_slice(),
+        )
+        .await?;
+    if let Some((s, f)) = &config.db_pre_request {
+        let fn_schema = match s.as_str() {
+            "" => request.schema_name,
+            _ => s.as_str(),
+        };
+        let pre_request_statement = format!(r#"select "{f}".* from "{fn_schema}"."{f}"()"#);
+        debug!("pre_statement {}", pre_request_statement);
+        let pre_request_stm = client.prepare_cached(pre_request_statement.as_str()).await?;
+        client.query(&pre_request_stm, &[]).await?;
+    }
+
+    debug!("stream_statement {}\n{:?}", main_statement, main_parameters);
+    let main_stm = client.prepare_cached(main_statement).await?;
+    let mut rows = Box::pin(client.query_raw(&main_stm, main_parameters.iter()).await?);
+    let first_row = rows.try_next().await?;
+    Ok((rows, first_row))
+}
+
+async fn execute_stream<'a>(
+    schema: &DbSchema<'a>, pool: &Pool, authenticated: bool, request: &ApiRequest<'_>, env: &HashMap<&str, &str>, config: &VhostConfig,
+) -> Result<BodyStream> {
+    let client = pool.get().await.context(PgDbPoolSnafu)?;
+    let (main_statement, main_parameters, _) = generate(fmt_stream_query(schema, request.schema_name, request, env).context(CoreSnafu)?);
+
+    // the transaction is started by hand since it has to outlive this function together with the connection
+    client
+        .batch_execute("begin isolation level read committed read only")
+        .await
+        .context(PgDbSnafu { authenticated })?;
+    let main_parameters = main_parameters.into_iter().map(wrap_param).collect::<Vec<_>>();
+    let (mut rows, first_row) = match start_stream(&client, request, env, config, &main_statement, main_parameters).await {
+        Ok(r) => r,
+        Err(e) => {
+            end_stream_transaction(client, "rollback").await;
+            return Err(e).context(PgDbSnafu { authenticated });
+        }
+    };
+
+    let (open, separator, close) = match request.accept_content_type {
+        TextCSV => ("", "\n", ""),
+        _ => ("[", ",", "]"),
+    };
+    let (tx, rx) = mpsc::channel::<Vec<u8>>(16);
+    tokio::spawn(async move {
+        let mut chunk = open.to_string();
+        let mut row = first_row;
+        let mut first = true;
+        let result = loop {
+            match row {
+                Some(r) => {
+                    if !first {
+                        chunk.push_str(separator);
+                    }
+                    first = false;
+                    chunk.push_str(r.get::<_, &str>("row"));
+                    // the client went away, there is no point in reading the rest of the rows
+                    if tx.send(std::mem::take(&mut chunk).into_bytes()).await.is_err() {
+                        break Ok(false);
+                    }
+                }
+                None => break Ok(true),
+            }
+            row = match rows.try_next().await {
+                Ok(r) => r,
+                Err(e) => break Err(e),
+            };
+        };
+        match result {
+            Ok(true) => {
+                chunk.push_str(close);
+                let _ = tx.send(chunk.into_bytes()).await;
+                end_stream_transaction(client, "commit").await;
+            }
+            Ok(false) => end_stream_transaction(client, "rollback").await,
+            Err(e) => {
+                // the status was already sent so the response is cut short
+                error!("Failed to stream the response {e}");
+                end_stream_transaction(client, "rollback").await;
+            }
+        }
+    });
+
+    Ok(Box::pin(stream::unfold(rx, |mut rx| async move { rx.recv().await.map(|c| (c, rx)) })))
+}
+
+// a connection that is still inside the transaction is taken out of the pool instead of being reused
+async fn end_stream_transaction(client: Object, statement: &str) {
+    if let Err(e) = client.batch_execute(statement).await {
+        error!("Failed to {statement} the streamed response {e}");
+        drop(Object::take(client));
+    }
+}
+
+async fn load_schema(vhost: &str, pool: &Pool, config: &VhostConfig) -> Result<DbSchemaWrap> {
+    let db_schema: DbSchemaWrap = match config.db_schema_structure.clone() {
+        SqlFile(f) => match fs::read_to_string(
+            vec![&f, &format!("postgresql_{f}")]
+                .into_iter()
+                .find(|f| Path::new(f).exists())
+                .unwrap_or(&f),
+        ) {
+            Ok(q) => match wait_for_pg_connection(vhost, pool).await {
+                Ok(mut client) => {
+                    let authenticated = false;
+                    let query = include_files(q);
+                    let transaction = client
+                        .build_transaction()
+                        .isolation_level(IsolationLevel::Serializable)
+                        .read_only(true)
+                        .start()
+                        .await
+                        .context(PgDbSnafu { authenticated })?;
+                    let _ = transaction.query("set local schema ''", &[]).await;
+                    match transaction.query(&query, &[&config.db_schemas, &false]).await {
+                        Ok(rows) => {
+                            transaction.commit().await.context(PgDbSnafu { authenticated })?;
+                            //println!("db schema loaded: {}", rows[0].get::<usize, &str>(0));
+                            let s: String = rows[0].get(0);
+                            Ok(DbSchemaWrap::new(s, |s| {
+                                serde_json::from_str::<DbSchema>(s.as_str())
+                                    .context(JsonDeserializeSnafu)
+                                    .context(CoreSnafu)
+                            }))
+                        }
+                        Err(e) => {
+                            transaction.rollback().await.context(PgDbSnafu { authenticated })?;
+                            Err(e).context(PgDbSnafu { authenticated })
+                        }
+                    }
+                }
+                Err(e) => Err(e).context(PgDbPoolSnafu),
+            },
+            Err(e) => Err(e).context(ReadFileSnafu { path: f }),
+        },
+        JsonFile(f) => match fs::read_to_string(&f) {
+            Ok(s) => Ok(DbSchemaWrap::new(s, |s| {
+                serde_json::from_str::<DbSchema>(s.as_str())
+                    .context(JsonDeserializeSnafu)
+                    .context(CoreSnafu)
+            })),
+            Err(e) => Err(e).context(ReadFileSnafu { path: f }),
+        },
+        JsonString(s) => Ok(DbSchemaWrap::new(s, |s| {
+            serde_json::from_str::<DbSchema>(s.as_str())
+                .context(JsonDeserializeSnafu)
+                .context(CoreSnafu)
+        })),
+    }?;
+    db_schema.validate()
+}
+
+// replace the cached schema and drop the statements (generated and prepared) that might reference the old one
+async fn reload_schema(vhost: &str, pool: &Pool, config: &VhostConfig, db_schema: &DbSchemaCache, plan_cache: &PlanCache) -> Result<()> {
+    db_schema.set(load_schema(vhost, pool, config).await?);
+    plan_cache.clear();
+    pool.manager().statement_caches.clear();
+    Ok(())
+}
+
+// reload the schema when a notification is received on the configured channel
+// an empty payload or "reload schema" triggers the reload (same as PostgREST)
+async fn listen_for_schema_changes(vhost: String, pool: Pool, config: VhostConfig, db_schema: Arc<DbSchemaCache>, plan_cache: Arc<PlanCache>) {
+    loop {
+        match tokio_postgres::connect(&config.db_uri, tls_connector()).await {
+            Ok((client, mut connection)) => {
+                let (tx, mut rx) = mpsc::unbounded_channel();
+                let connection_task = tokio::spawn(async move {
+                    while let Some(message) = poll_fn(|cx| connection.poll_message(cx)).await {
+                        match message {
+                            Ok(AsyncMessage::Notification(n)) => {
+                                if tx.send(n.payload().to_string()).is_err() {
+                                    break;
+                                }
+                            }
+                            Ok(_) => {}
+                            Err(_) => break,
+                        }
+                    }
+                });
+                let listen_statement = format!(r#"listen "{}""#, config.db_channel.replace('"', "\"\""));
+                match client.batch_execute(&listen_statement).await {
+                    Ok(_) => {
+                        println!("[{vhost}] Listening for notifications on the {} channel", config.db_channel);
+                        while let Some(payload) = rx.recv().await {
+                            if payload.is_empty() || payload == "reload schema" {
+                                match reload_schema(&vhost, &pool, &config, &db_schema, &plan_cache).await {
+                                    Ok(_) => println!("[{vhost}] Schema cache reloaded"),
+                                    Err(e) => println!("[{vhost}] Failed to reload the schema cache: {e}"),
+                                }
+                            }
+                        }
+                    }
+                    Err(e) => println!("[{vhost}] Failed to listen on the {} channel: {e}", config.db_channel),
+                }
+                connection_task.abort();
+            }
+            Err(e) => println!("[{vhost}] Failed to connect to PostgreSQL for notifications {e:?}"),
+        }
+        println!("[{vhost}] Retrying the notifications connection in 5 seconds..");
+        sleep(Duration::from_secs(5)).await;
+    }
+}
+
+fn tls_connector() -> MakeTlsConnector {
+    let mut builder = SslConnector::builder(SslMethod::tls()).unwrap();
+    builder.set_verify(SslVerifyMode::NONE);
+    MakeTlsConnector::new(builder.build())
+}
+
 pub struct PostgreSQLBackend {
-    //vhost: String,
+    vhost: String,
     config: VhostConfig,
     pool: Pool,
-    db_schema: DbSchemaWrap,
+    db_schema: Arc<DbSchemaCache>,
+    plan_cache: Arc<PlanCache>,
 }
 
 #[async_trait]
@@ -247,11 +522,7 @@ impl Backend for PostgreSQLBackend {
         let mgr_config = ManagerConfig {
             recycling_method: RecyclingMethod::Fast,
         };
-        let mut builder = SslConnector::builder(SslMethod::tls()).unwrap();
-        builder.set_verify(SslVerifyMode::NONE);
-        let tls_connector = MakeTlsConnector::new(builder.build());
-
-        let mgr = Manager::from_config(pg_config, tls_connector, mgr_config);
+        let mgr = Manager::from_config(pg_config, tls_connector(), mgr_config);
         let timeouts = Timeouts {
             create: Some(Duration::from_millis(5000)),
             wait: None,
@@ -265,80 +536,41 @@ impl Backend for PostgreSQLBackend {
             .unwrap();
 
         //read db schema
-        let db_schema: DbSchemaWrap = match config.db_schema_structure.clone() {
-            SqlFile(f) => match fs::read_to_string(
-                vec![&f, &format!("postgresql_{f}")]
-                    .into_iter()
-                    .find(|f| Path::new(f).exists())
-                    .unwrap_or(&f),
-            ) {
-                Ok(q) => match wait_for_pg_connection(&vhost, &pool).await {
-                    Ok(mut client) => {
-                        let authenticated = false;
-                        let query = include_files(q);
-                        let transaction = client
-                            .build_transaction()
-                            .isolation_level(IsolationLevel::Serializable)
-                            .read_only(true)
-                            .start()
-                            .await
-                            .context(PgDbSnafu { authenticated })?;
-                        let _ = transaction.query("set local schema ''", &[]).await;
-                        match transaction.query(&query, &[&config.db_schemas, &false]).await {
-                            Ok(rows) => {
-                                transaction.commit().await.context(PgDbSnafu { authenticated })?;
-                                //println!("db schema loaded: {}", rows[0].get::<usize, &str>(0));
-                                let s: String = rows[0].get(0);
-                                Ok(DbSchemaWrap::new(s, |s| {
-                                    serde_json::from_str::<DbSchema>(s.as_str())
-                                        .context(JsonDeserializeSnafu)
-                                        .context(CoreSnafu)
-                                }))
-                            }
-                            Err(e) => {
-                                transaction.rollback().await.context(PgDbSnafu { authenticated })?;
-                                Err(e).context(PgDbSnafu { authenticated })
-                            }
-                        }
-                    }
-                    Err(e) => Err(e).context(PgDbPoolSnafu),
-                },
-                Err(e) => Err(e).context(ReadFileSnafu { path: f }),
-            },
-            JsonFile(f) => match fs::read_to_string(&f) {
-                Ok(s) => Ok(DbSchemaWrap::new(s, |s| {
-                    serde_json::from_str::<DbSchema>(s.as_str())
-                        .context(JsonDeserializeSnafu)
-                        .context(CoreSnafu)
-                })),
-                Err(e) => Err(e).context(ReadFileSnafu { path: f }),
-            },
-            JsonString(s) => Ok(DbSchemaWrap::new(s, |s| {
-                serde_json::from_str::<DbSchema>(s.as_str())
-                    .context(JsonDeserializeSnafu)
-                    .context(CoreSnafu)
-            })),
-        }?;
+        let db_schema = Arc::new(DbSchemaCache::new(load_schema(&vhost, &pool, &config).await?));
+        let plan_cache = Arc::new(PlanCache::new(config.db_plan_cache_size));
 
-        if let Err(e) = db_schema.with_schema(|s| s.as_ref()) {
-            let message = format!("Backend init failed: {e}");
-            return Err(crate::Error::Internal { message });
+        if config.db_channel_enabled {
+            tokio::spawn(listen_for_schema_changes(vhost.clone(), pool.clone(), config.clone(), db_schema.clone(), plan_cache.clone()));
         }
 
-        Ok(PostgreSQLBackend { config, pool, db_schema })
+        Ok(PostgreSQLBackend {
+            vhost,
+            config,
+            pool,
+            db_schema,
+            plan_cache,
+        })
+    }
+    async fn execute(&self, db_schema: &DbSchema, authenticated: bool, request: &ApiRequest, env: &HashMap<&str, &str>) -> Result<ApiResponse> {
+        execute(db_schema, &self.pool, &self.plan_cache, authenticated, request, env, &self.config).await
+    }
+    async fn execute_stream(
+        &self, db_schema: &DbSchema, authenticated: bool, request: &ApiRequest, env: &HashMap<&str, &str>,
+    ) -> Result<Option<BodyStream>> {
+        Ok(Some(execute_stream(db_schema, &self.pool, authenticated, request, env, &self.config).await?))
     }
-    async fn execute(&self, authenticated: bool, request: &ApiRequest, env: &HashMap<&str, &str>) -> Result<ApiResponse> {
-        execute(self.db_schema(), &self.pool, authenticated, request, env, &self.config).await
+    fn db_schema(&self) -> Arc<DbSchemaWrap> {
+        self.db_schema.get()
     }
-    fn db_schema(&self) -> &DbSchema {
-        self.db_schema.borrow_schema().as_ref().unwrap()
+    async fn reload_schema(&self) -> Result<()> {
+        reload_schema(&self.vhost, &self.pool, &self.config, &self.db_schema, &self.plan_cache).await
     }
     fn config(&self) -> &VhostConfig {
         &self.config
     }
 }
 
-async fn wait_for_pg_connection(vhost: &String, db_pool: &Pool) -> Result<Object, PoolError> {
+async fn wait_for_pg_connection(vhost: &str, db_pool: &Pool) -> Result<Object, PoolError> {
     let mut i = 1;
     let mut time_since_start = 0;
     let max_delay_interval = 10;
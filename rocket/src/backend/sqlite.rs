@@ -31,10 +31,10 @@ use subzero_core::{
     },
     api::{
         Condition, Field, SelectItem, Filter, ListVal, SingleVal, Payload, ApiRequest, ApiResponse, ContentType::*, Query, QueryNode::*, Preferences,
-        Count,
+        Count, PlanFormat,
     },
     error::{JsonSerializeSnafu, JsonDeserializeSnafu},
-    error::Error::{SingularityError, PutMatchingPkError, PermissionDenied},
+    error::Error::{SingularityError, PutMatchingPkError, PermissionDenied, MaxAffectedViolation, UnsupportedFeature},
     schema::DbSchema,
 };
 //use rocket::log::private::debug;
@@ -44,11 +44,13 @@ use serde_json::{json, Value};
 use http::Method;
 use snafu::ResultExt;
 use async_trait::async_trait;
-use rusqlite::vtab::array;
+use rusqlite::vtab::{array, series};
+use regex::Regex;
 
 use std::collections::HashMap;
+use std::sync::Arc;
 use std::fs;
-use super::{Backend, include_files, DbSchemaWrap};
+use super::{Backend, include_files, DbSchemaWrap, DbSchemaCache, rollback_transaction, exceeds_max_affected};
 use tokio::task;
 use rusqlite::{
     Connection,
@@ -57,7 +59,7 @@ use rusqlite::{
     //types::Value,
     Result as SqliteResult,
     ToSql,
-    functions::FunctionFlags,
+    functions::{Context, FunctionFlags},
 };
 //use std::rc::Rc;
 
@@ -89,6 +91,10 @@ fn execute(
 ) -> Result<ApiResponse> {
     let conn = pool.get().unwrap();
 
+    if let Plan(format, _) = &request.accept_content_type {
+        return explain(&conn, db_schema, authenticated, request, env, format);
+    }
+
     conn.execute_batch("BEGIN DEFERRED").context(SqliteDbSnafu { authenticated })?;
     //let transaction = conn.transaction().context(SqliteDb { authenticated })?;
     let return_representation = return_representation(request.method, &request.query, &request.preferences);
@@ -246,7 +252,11 @@ fn execute(
                         ..
                     })
                 );
-                if config.db_tx_rollback {
+                if exceeds_max_affected(request, ids.len() as u64) {
+                    conn.execute_batch("ROLLBACK").context(SqliteDbSnafu { authenticated })?;
+                    return Err(to_core_error(MaxAffectedViolation { count: ids.len() as u64 }));
+                }
+                if rollback_transaction(config, request) {
                     conn.execute_batch("ROLLBACK").context(SqliteDbSnafu { authenticated })?;
                 } else {
                     conn.execute_batch("COMMIT").context(SqliteDbSnafu { authenticated })?;
@@ -412,6 +422,13 @@ fn execute(
         }));
     }
 
+    if exceeds_max_affected(request, api_response.page_total) {
+        conn.execute_batch("ROLLBACK").context(SqliteDbSnafu { authenticated })?;
+        return Err(to_core_error(MaxAffectedViolation {
+            count: api_response.page_total,
+        }));
+    }
+
     if request.method == Method::PUT && api_response.page_total != 1 {
         // Makes sure the querystring pk matches the payload pk
         // e.g. PUT /items?id=eq.1 { "id" : 1, .. } is accepted,
@@ -421,7 +438,7 @@ fn execute(
         return Err(to_core_error(PutMatchingPkError));
     }
 
-    if config.db_tx_rollback {
+    if rollback_transaction(config, request) {
         conn.execute_batch("ROLLBACK").context(SqliteDbSnafu { authenticated })?;
     } else {
         conn.execute_batch("COMMIT").context(SqliteDbSnafu { authenticated })?;
@@ -430,20 +447,130 @@ fn execute(
     Ok(api_response)
 }
 
+// the query plan is reported as a tree of (id, parent, detail) rows
+fn explain(
+    conn: &Connection, db_schema: &DbSchema<'_>, authenticated: bool, request: &ApiRequest, env: &HashMap<&str, &str>, format: &PlanFormat,
+) -> Result<ApiResponse> {
+    // mutations run in two steps so there is no single statement to explain
+    if matches!(request.query.node, Insert { .. } | Update { .. } | Delete { .. }) {
+        return Err(to_core_error(UnsupportedFeature {
+            message: "The execution plan is only available for read requests".to_string(),
+        }));
+    }
+    let (statement, parameters, _) = generate(fmt_main_query(db_schema, request.schema_name, request, env).context(CoreSnafu)?);
+    debug!("explain_statement: {}\n{:?}", statement, parameters);
+    let mut stmt = conn.prepare(statement.as_str()).context(SqliteDbSnafu { authenticated })?;
+    let nodes = stmt
+        .query_map(params_from_iter(parameters.into_iter().map(wrap_param)), |r| {
+            Ok((r.get::<_, i64>(0)?, r.get::<_, i64>(1)?, r.get::<_, String>(3)?))
+        })
+        .context(SqliteDbSnafu { authenticated })?
+        .collect::<SqliteResult<Vec<_>>>()
+        .context(SqliteDbSnafu { authenticated })?;
+    let body = match format {
+        PlanFormat::Json => serde_json::to_string(
+            &nodes
+                .iter()
+                .map(|(id, parent, detail)| json!({"id": id, "parent": parent, "detail": detail}))
+                .collect::<Vec<_>>(),
+        )
+        .context(JsonSerializeSnafu)
+        .context(CoreSnafu)?,
+        PlanFormat::Text => {
+            // parents come before their children so the depth of a node is known when it is reached
+            let mut depths: HashMap<i64, usize> = HashMap::new();
+            nodes
+                .iter()
+                .map(|(id, parent, detail)| {
+                    let depth = depths.get(parent).map(|d| d + 1).unwrap_or(0);
+                    depths.insert(*id, depth);
+                    format!("{}{}", "  ".repeat(depth), detail)
+                })
+                .collect::<Vec<_>>()
+                .join("\n")
+        }
+    };
+    Ok(ApiResponse {
+        page_total: 0,
+        total_result_set: None,
+        top_level_offset: 0,
+        response_headers: None,
+        response_status: None,
+        body,
+    })
+}
+
+fn load_schema(pool: &Pool<SqliteConnectionManager>, config: &VhostConfig) -> Result<DbSchemaWrap> {
+    let db_schema: DbSchemaWrap = match config.db_schema_structure.clone() {
+        SqlFile(f) => match fs::read_to_string(vec![&f, &format!("sqlite_{f}")].into_iter().find(|f| Path::new(f).exists()).unwrap_or(&f)) {
+            Ok(q) => match pool.get() {
+                Ok(conn) => task::block_in_place(|| {
+                    let authenticated = false;
+                    let query = include_files(q);
+                    //println!("schema query: {query}");
+                    let mut stmt = conn.prepare(query.as_str()).context(SqliteDbSnafu { authenticated })?;
+                    let mut rows = stmt.query([]).context(SqliteDbSnafu { authenticated })?;
+                    match rows.next().context(SqliteDbSnafu { authenticated })? {
+                        Some(r) => {
+                            //println!("json db_schema: {}", r.get::<usize, String>(0).context(SqliteDbSnafu { authenticated })?.as_str());
+                            let s: String = r.get::<usize, String>(0).context(SqliteDbSnafu { authenticated })?;
+                            Ok(DbSchemaWrap::new(s, |s| {
+                                serde_json::from_str::<DbSchema>(s.as_str())
+                                    .context(JsonDeserializeSnafu)
+                                    .context(CoreSnafu)
+                            }))
+                        }
+                        None => Err(Error::Internal {
+                            message: "sqlite structure query did not return any rows".to_string(),
+                        }),
+                    }
+                }),
+                Err(e) => Err(e).context(SqliteDbPoolSnafu),
+            },
+            Err(e) => Err(e).context(ReadFileSnafu { path: f }),
+        },
+        JsonFile(f) => match fs::read_to_string(&f) {
+            Ok(s) => Ok(DbSchemaWrap::new(s, |s| {
+                serde_json::from_str::<DbSchema>(s.as_str())
+                    .context(JsonDeserializeSnafu)
+                    .context(CoreSnafu)
+            })),
+            Err(e) => Err(e).context(ReadFileSnafu { path: f }),
+        },
+        JsonString(s) => Ok(DbSchemaWrap::new(s, |s| {
+            serde_json::from_str::<DbSchema>(s.as_str())
+                .context(JsonDeserializeSnafu)
+                .context(CoreSnafu)
+        })),
+    }?;
+    db_schema.validate()
+}
+
 pub struct SQLiteBackend {
     //vhost: String,
     config: VhostConfig,
     pool: Pool<SqliteConnectionManager>,
-    db_schema: DbSchemaWrap,
+    db_schema: DbSchemaCache,
 }
 
-fn cs(x: String, y: String) -> SqliteResult<bool> {
-    let x_json: Result<Value, _> = serde_json::from_str(&x);
-    let y_json: Result<Value, _> = serde_json::from_str(&y);
+// the cell value as json, text that is not valid json yields None
+fn json_arg(v: ValueRef) -> Option<Value> {
+    match v {
+        ValueRef::Null => None,
+        ValueRef::Integer(i) => Some(Value::from(i)),
+        ValueRef::Real(f) => Some(Value::from(f)),
+        ValueRef::Text(t) | ValueRef::Blob(t) => serde_json::from_slice(t).ok(),
+    }
+}
 
-    match (x_json, y_json) {
-        (Ok(x_val), Ok(y_val)) => Ok(json_contains(&x_val, &y_val)),
-        _ => Ok(false), // Return false in case of any parsing error
+// evaluates a json operator, null arguments result in null and values that are not json do not match
+fn json_operator(ctx: &Context, op: fn(&Value, &Value) -> bool) -> SqliteResult<Option<bool>> {
+    match (ctx.get_raw(0), ctx.get_raw(1)) {
+        (ValueRef::Null, _) | (_, ValueRef::Null) => Ok(None),
+        (x, y) => Ok(Some(match (json_arg(x), json_arg(y)) {
+            (Some(x_val), Some(y_val)) => op(&x_val, &y_val),
+            _ => false,
+        })),
     }
 }
 
@@ -458,15 +585,39 @@ fn json_contains(x: &Value, y: &Value) -> bool {
     }
 }
 
+fn json_overlaps(x: &Value, y: &Value) -> bool {
+    match y {
+        Value::Array(y_arr) => y_arr.iter().any(|y_item| json_contains(x, y_item)),
+        _ => json_contains(x, y),
+    }
+}
+
 #[derive(Debug)]
 struct MyConnectionCustomizer;
 // we implement this only for testing in rust, the user will register his own functions
 impl CustomizeConnection<Connection, rusqlite::Error> for MyConnectionCustomizer {
     fn on_acquire(&self, conn: &mut Connection) -> Result<(), rusqlite::Error> {
+        // used by the cs/cd/ov operators
         conn.create_scalar_function("cs", 2, FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC, |ctx| {
-            let x = ctx.get::<String>(0)?;
-            let y = ctx.get::<String>(1)?;
-            cs(x, y)
+            json_operator(ctx, json_contains)
+        })?;
+        conn.create_scalar_function("cd", 2, FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC, |ctx| {
+            json_operator(ctx, |x, y| json_contains(y, x))
+        })?;
+        conn.create_scalar_function("ov", 2, FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC, |ctx| {
+            json_operator(ctx, json_overlaps)
+        })?;
+        // used by the match/imatch operators (the regexp operator calls regexp(pattern, value))
+        conn.create_scalar_function("regexp", 2, FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC, |ctx| {
+            let re: Arc<Regex> =
+                ctx.get_or_create_aux(0, |vr| -> std::result::Result<_, Box<dyn std::error::Error + Send + Sync>> { Ok(Regex::new(vr.as_str()?)?) })?;
+            let text = match ctx.get_raw(1) {
+                ValueRef::Null => return Ok(None),
+                ValueRef::Integer(i) => i.to_string(),
+                ValueRef::Real(f) => f.to_string(),
+                ValueRef::Text(t) | ValueRef::Blob(t) => String::from_utf8_lossy(t).into_owned(),
+            };
+            Ok(Some(re.is_match(&text)))
         })
     }
 }
@@ -476,7 +627,10 @@ impl Backend for SQLiteBackend {
     async fn init(_vhost: String, config: VhostConfig) -> Result<Self> {
         //setup db connection
         let db_file = config.db_uri.clone();
-        let manager = SqliteConnectionManager::file(db_file).with_init(|c| array::load_module(c));
+        let manager = SqliteConnectionManager::file(db_file).with_init(|c| {
+            array::load_module(c)?;
+            series::load_module(c)
+        });
         let pool = Pool::builder()
             .connection_customizer(Box::new(MyConnectionCustomizer))
             .max_size(config.db_pool as u32)
@@ -484,59 +638,19 @@ impl Backend for SQLiteBackend {
             .unwrap();
 
         //read db schema
-        let db_schema: DbSchemaWrap = match config.db_schema_structure.clone() {
-            SqlFile(f) => match fs::read_to_string(vec![&f, &format!("sqlite_{f}")].into_iter().find(|f| Path::new(f).exists()).unwrap_or(&f)) {
-                Ok(q) => match pool.get() {
-                    Ok(conn) => task::block_in_place(|| {
-                        let authenticated = false;
-                        let query = include_files(q);
-                        //println!("schema query: {query}");
-                        let mut stmt = conn.prepare(query.as_str()).context(SqliteDbSnafu { authenticated })?;
-                        let mut rows = stmt.query([]).context(SqliteDbSnafu { authenticated })?;
-                        match rows.next().context(SqliteDbSnafu { authenticated })? {
-                            Some(r) => {
-                                //println!("json db_schema: {}", r.get::<usize, String>(0).context(SqliteDbSnafu { authenticated })?.as_str());
-                                let s: String = r.get::<usize, String>(0).context(SqliteDbSnafu { authenticated })?;
-                                Ok(DbSchemaWrap::new(s, |s| {
-                                    serde_json::from_str::<DbSchema>(s.as_str())
-                                        .context(JsonDeserializeSnafu)
-                                        .context(CoreSnafu)
-                                }))
-                            }
-                            None => Err(Error::Internal {
-                                message: "sqlite structure query did not return any rows".to_string(),
-                            }),
-                        }
-                    }),
-                    Err(e) => Err(e).context(SqliteDbPoolSnafu),
-                },
-                Err(e) => Err(e).context(ReadFileSnafu { path: f }),
-            },
-            JsonFile(f) => match fs::read_to_string(&f) {
-                Ok(s) => Ok(DbSchemaWrap::new(s, |s| {
-                    serde_json::from_str::<DbSchema>(s.as_str())
-                        .context(JsonDeserializeSnafu)
-                        .context(CoreSnafu)
-                })),
-                Err(e) => Err(e).context(ReadFileSnafu { path: f }),
-            },
-            JsonString(s) => Ok(DbSchemaWrap::new(s, |s| {
-                serde_json::from_str::<DbSchema>(s.as_str())
-                    .context(JsonDeserializeSnafu)
-                    .context(CoreSnafu)
-            })),
-        }?;
-        if let Err(e) = db_schema.with_schema(|s| s.as_ref()) {
-            let message = format!("Backend init failed: {e}");
-            return Err(crate::Error::Internal { message });
-        }
+        let db_schema = DbSchemaCache::new(load_schema(&pool, &config)?);
+
         Ok(SQLiteBackend { config, pool, db_schema })
     }
-    async fn execute(&self, authenticated: bool, request: &ApiRequest, env: &HashMap<&str, &str>) -> Result<ApiResponse> {
-        execute(self.db_schema(), &self.pool, authenticated, request, env, &self.config)
+    async fn execute(&self, db_schema: &DbSchema, authenticated: bool, request: &ApiRequest, env: &HashMap<&str, &str>) -> Result<ApiResponse> {
+        execute(db_schema, &self.pool, authenticated, request, env, &self.config)
+    }
+    fn db_schema(&self) -> Arc<DbSchemaWrap> {
+        self.db_schema.get()
     }
-    fn db_schema(&self) -> &DbSchema {
-        self.db_schema.borrow_schema().as_ref().unwrap()
+    async fn reload_schema(&self) -> Result<()> {
+        self.db_schema.set(load_schema(&self.pool, &self.config)?);
+        Ok(())
     }
     fn config(&self) -> &VhostConfig {
         &self.config
@@ -0,0 +1,166 @@
+// Copyright (c) 2022-2025 subZero Cloud S.R.L
+//
+// This file is part of subZero - The All-in-One library suite for internal tools development
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+// jwt verification using a shared secret (HS*), a public key (RS*, PS*, ES*, EdDSA) or a JWKS document
+use jsonwebtoken::{
+    decode, decode_header,
+    jwk::{AlgorithmParameters, Jwk, JwkSet},
+    Algorithm::{self, *},
+    DecodingKey, Validation,
+};
+use serde_json::Value as JsonValue;
+use snafu::ResultExt;
+use std::{
+    collections::HashMap,
+    fs,
+    sync::{Arc, RwLock},
+    time::{Duration, Instant},
+};
+use subzero_core::error::{Error as CoreError, JsonDeserializeSnafu};
+
+use crate::config::VhostConfig;
+use crate::error::{Error, Result, CoreSnafu, ReadFileSnafu, to_core_error};
+
+// do not fetch the same JWKS document more often than this when tokens come with unknown key ids
+const JWKS_MIN_REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+// the JWKS document is fetched while authenticating a request so a slow endpoint must not hold it for long
+const JWKS_CONNECT_TIMEOUT: Duration = Duration::from_secs(3);
+const JWKS_FETCH_TIMEOUT: Duration = Duration::from_secs(5);
+
+lazy_static! {
+    // JWKS documents keyed by the file path or url they were loaded from
+    static ref JWKS_CACHE: RwLock<HashMap<String, (Instant, Arc<JwkSet>)>> = RwLock::new(HashMap::new());
+    // keys parsed from the configured PEM keyed by the configured value and the key type
+    static ref PEM_KEY_CACHE: RwLock<HashMap<(String, &'static str), DecodingKey>> = RwLock::new(HashMap::new());
+    static ref JWKS_CLIENT: reqwest::Client = reqwest::Client::builder()
+        .connect_timeout(JWKS_CONNECT_TIMEOUT)
+        .timeout(JWKS_FETCH_TIMEOUT)
+        .build()
+        .expect("JWKS http client");
+}
+
+// parses a PEM encoded public key of a given type
+type PemParser = fn(&[u8]) -> jsonwebtoken::errors::Result<DecodingKey>;
+
+fn invalid_token(message: String) -> Error {
+    to_core_error(CoreError::JwtTokenInvalid { message })
+}
+
+async fn load_jwks(source: &str) -> Result<JwkSet> {
+    let body = if source.starts_with("http://") || source.starts_with("https://") {
+        let response = JWKS_CLIENT.get(source).send().await.map_err(|e| Error::Internal {
+            message: format!("Failed to fetch the JWKS from {source}: {e}"),
+        })?;
+        response.text().await.map_err(|e| Error::Internal {
+            message: format!("Failed to fetch the JWKS from {source}: {e}"),
+        })?
+    } else {
+        fs::read_to_string(source).context(ReadFileSnafu { path: source })?
+    };
+    serde_json::from_str::<JwkSet>(&body).context(JsonDeserializeSnafu).context(CoreSnafu)
+}
+
+async fn get_jwks(source: &str, refresh: bool) -> Result<Arc<JwkSet>> {
+    let cached = JWKS_CACHE.read().unwrap().get(source).cloned();
+    match cached {
+        Some((fetched_at, jwks)) if !refresh || fetched_at.elapsed() < JWKS_MIN_REFRESH_INTERVAL => Ok(jwks),
+        _ => {
+            let jwks = Arc::new(load_jwks(source).await?);
+            JWKS_CACHE.write().unwrap().insert(source.to_string(), (Instant::now(), jwks.clone()));
+            Ok(jwks)
+        }
+    }
+}
+
+fn key_matches_algorithm(jwk: &Jwk, alg: Algorithm) -> bool {
+    matches!(
+        (&jwk.algorithm, alg),
+        (AlgorithmParameters::RSA(_), RS256 | RS384 | RS512 | PS256 | PS384 | PS512)
+            | (AlgorithmParameters::EllipticCurve(_), ES256 | ES384)
+            | (AlgorithmParameters::OctetKeyPair(_), EdDSA)
+    )
+}
+
+fn find_jwk(jwks: &JwkSet, kid: Option<&str>, alg: Algorithm) -> Option<Jwk> {
+    match kid {
+        Some(kid) => jwks.find(kid).cloned(),
+        None => jwks.keys.iter().find(|k| key_matches_algorithm(k, alg)).cloned(),
+    }
+}
+
+async fn jwks_key(source: &str, kid: Option<&str>, alg: Algorithm) -> Result<DecodingKey> {
+    let jwk = match find_jwk(&*get_jwks(source, false).await?, kid, alg) {
+        Some(jwk) => Some(jwk),
+        // the keys might have been rotated, try to fetch the document again
+        None => find_jwk(&*get_jwks(source, true).await?, kid, alg),
+    };
+    match jwk {
+        Some(jwk) => DecodingKey::from_jwk(&jwk).map_err(|e| invalid_token(format!("{e}"))),
+        None => Err(invalid_token("No suitable key found to verify the JWT".to_string())),
+    }
+}
+
+// the public key can be configured either as the PEM content or as a path to a PEM file
+// the key is read and parsed on first use and then reused for the following requests
+fn pem_key(pem: &str, alg: Algorithm) -> Result<DecodingKey> {
+    let (key_type, parse): (&str, PemParser) = match alg {
+        RS256 | RS384 | RS512 | PS256 | PS384 | PS512 => ("rsa", DecodingKey::from_rsa_pem),
+        ES256 | ES384 => ("ec", DecodingKey::from_ec_pem),
+        EdDSA => ("ed", DecodingKey::from_ed_pem),
+        HS256 | HS384 | HS512 => return Err(invalid_token("Wrong algorithm for the configured key".to_string())),
+    };
+    let cache_key = (pem.to_string(), key_type);
+    if let Some(key) = PEM_KEY_CACHE.read().unwrap().get(&cache_key) {
+        return Ok(key.clone());
+    }
+    let contents = if pem.trim_start().starts_with("-----BEGIN") {
+        pem.to_string()
+    } else {
+        fs::read_to_string(pem).context(ReadFileSnafu { path: pem })?
+    };
+    let key = parse(contents.as_bytes()).map_err(|e| invalid_token(format!("{e}")))?;
+    PEM_KEY_CACHE.write().unwrap().insert(cache_key, key.clone());
+    Ok(key)
+}
+
+// verify the token signature and the aud/iss/nbf claims and return the claims
+// the exp claim is checked by the caller
+pub async fn decode_jwt(config: &VhostConfig, token: &str) -> Result<JsonValue> {
+    let header = decode_header(token).map_err(|e| invalid_token(format!("{e}")))?;
+    let key = match (header.alg, &config.jwt_secret, &config.jwt_public_key, &config.jwt_jwks) {
+        (HS256 | HS384 | HS512, Some(secret), ..) => DecodingKey::from_secret(secret.as_bytes()),
+        (HS256 | HS384 | HS512, None, ..) => return Err(invalid_token("Wrong algorithm for the configured key".to_string())),
+        (alg, _, Some(pem), _) => pem_key(pem, alg)?,
+        (alg, _, None, Some(source)) => jwks_key(source, header.kid.as_deref(), alg).await?,
+        (_, _, None, None) => return Err(invalid_token("Wrong algorithm for the configured key".to_string())),
+    };
+
+    let mut validation = Validation::new(header.alg);
+    validation.validate_exp = false;
+    validation.validate_nbf = true;
+    validation.set_required_spec_claims::<&str>(&[]);
+    match &config.jwt_aud {
+        Some(aud) => validation.set_audience(&[aud]),
+        None => validation.validate_aud = false,
+    }
+    if let Some(iss) = &config.jwt_iss {
+        validation.set_issuer(&[iss]);
+    }
+
+    decode::<JsonValue>(token, &key, &validation)
+        .map(|t| t.claims)
+        .map_err(|e| invalid_token(format!("{e}")))
+}
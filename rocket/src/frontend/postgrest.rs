@@ -16,7 +16,6 @@
 // along with this program. If not, see <http://www.gnu.org/licenses/>.
 use http::Method;
 use jsonpath_lib::select;
-use jsonwebtoken::{decode, errors::ErrorKind, DecodingKey, Validation};
 use serde_json::{from_value, Value as JsonValue};
 use snafu::ResultExt;
 use std::borrow::Cow;
@@ -26,12 +25,15 @@ use tokio::task;
 
 use subzero_core::api::ApiResponse;
 
-use crate::backend::Backend;
+use crate::backend::{Backend, ResponseBody, stream_response};
+use super::jwt::decode_jwt;
+use crate::config::VhostConfig;
 
 use subzero_core::{
-    api::{ContentType, ContentType::*, Preferences, QueryNode::*, Representation, Resolution::*, ApiRequest},
+    api::{ContentType, ContentType::*, Preferences, QueryNode::*, Representation, Resolution::*, Transaction, Count, Handling, ApiRequest},
     error::{*},
-    parser::postgrest::parse,
+    openapi,
+    parser::postgrest::{parse, AVAILABLE_MEDIA_TYPES},
     permissions::{check_safe_functions, check_privileges, insert_policy_conditions, replace_select_star},
 };
 
@@ -155,17 +157,8 @@ fn get_env<'a>(
     env
 }
 
-#[allow(clippy::borrowed_box)]
-#[allow(clippy::too_many_arguments)]
-pub async fn handle<'a>(
-    root: &'a str, method: &Method, path: &'a str, get: Vec<(&'a str, &'a str)>, body: Option<&'a str>, headers: HashMap<&'a str, &'a str>,
-    cookies: HashMap<&'a str, &'a str>, backend: &Box<dyn Backend + Send + Sync>,
-) -> Result<(u16, ContentType, Vec<(String, String)>, String)> {
-    let mut response_headers = vec![];
-    let config = backend.config();
-    let db_schema = backend.db_schema();
-    let disable_internal_permissions = matches!(config.disable_internal_permissions, Some(true));
-    let schema_name = &(match (config.db_schemas.len() > 1, method, headers.get("accept-profile"), headers.get("content-profile")) {
+fn get_schema_name(config: &VhostConfig, method: &Method, headers: &HashMap<&str, &str>) -> Result<String> {
+    match (config.db_schemas.len() > 1, method, headers.get("accept-profile"), headers.get("content-profile")) {
         (false, ..) => Ok(config.db_schemas.first().unwrap().clone()),
         (_, &Method::DELETE, _, Some(content_profile))
         | (_, &Method::POST, _, Some(content_profile))
@@ -190,59 +183,113 @@ pub async fn handle<'a>(
         }
         _ => Ok(config.db_schemas.first().unwrap().clone()),
     }
-    .context(CoreSnafu)?);
+    .context(CoreSnafu)
+}
 
-    if config.db_schemas.len() > 1 {
-        response_headers.push(("Content-Profile".to_string(), schema_name.clone()));
+// check jwt
+async fn get_jwt_claims(config: &VhostConfig, headers: &HashMap<&str, &str>) -> Result<Option<JsonValue>> {
+    if config.jwt_secret.is_none() && config.jwt_public_key.is_none() && config.jwt_jwks.is_none() {
+        return Ok(None);
     }
-
-    // check jwt
-    let jwt_claims = match &config.jwt_secret {
-        Some(key) => match headers.get("authorization") {
-            Some(a) => {
-                let token_str: Vec<&str> = a.split(' ').collect();
-                match token_str[..] {
-                    ["Bearer", t] | ["bearer", t] => {
-                        let mut validation = Validation::default();
-                        validation.validate_exp = false;
-                        validation.set_required_spec_claims::<&str>(&[]);
-                        match decode::<JsonValue>(t, &DecodingKey::from_secret(key.as_bytes()), &validation) {
-                            Ok(c) => {
-                                if let Some(exp) = c.claims.get("exp") {
-                                    if from_value::<u64>(exp.clone()).context(JsonSerializeSnafu).context(CoreSnafu)? < get_current_timestamp() - 1 {
-                                        return Err(to_core_error(Error::JwtTokenInvalid {
-                                            message: "JWT expired".to_string(),
-                                        }));
-                                    }
-                                }
-                                Ok(Some(c.claims))
-                            }
-                            Err(err) => match *err.kind() {
-                                ErrorKind::InvalidToken => Err(Error::JwtTokenInvalid { message: format!("{err}") }),
-                                _ => Err(Error::JwtTokenInvalid { message: format!("{err}") }),
-                            },
+    match headers.get("authorization") {
+        Some(a) => {
+            let token_str: Vec<&str> = a.split(' ').collect();
+            match token_str[..] {
+                ["Bearer", t] | ["bearer", t] => {
+                    let claims = decode_jwt(config, t).await?;
+                    if let Some(exp) = claims.get("exp") {
+                        if from_value::<u64>(exp.clone()).context(JsonSerializeSnafu).context(CoreSnafu)? < get_current_timestamp() - 1 {
+                            return Err(to_core_error(Error::JwtTokenInvalid {
+                                message: "JWT expired".to_string(),
+                            }));
                         }
                     }
-                    _ => Ok(None),
+                    Ok(Some(claims))
                 }
+                _ => Ok(None),
             }
-            None => Ok(None),
-        },
+        }
         None => Ok(None),
     }
-    .context(CoreSnafu)?;
+}
 
-    let (role, authenticated) = match &jwt_claims {
+fn get_role<'a>(config: &'a VhostConfig, jwt_claims: &'a Option<JsonValue>) -> Result<(Option<&'a str>, bool)> {
+    match jwt_claims {
         Some(claims) => match select(claims, format!("${}", config.role_claim_key).as_str()) {
             Ok(v) => match &v[..] {
-                [JsonValue::String(s)] => Ok((Some(s), true)),
-                _ => Ok((config.db_anon_role.as_ref(), true)),
+                [JsonValue::String(s)] => Ok((Some(s.as_str()), true)),
+                _ => Ok((config.db_anon_role.as_deref(), true)),
             },
             Err(e) => Err(Error::JwtTokenInvalid { message: format!("{e}") }),
         },
-        None => Ok((config.db_anon_role.as_ref(), false)),
+        None => Ok((config.db_anon_role.as_deref(), false)),
+    }
+    .context(CoreSnafu)
+}
+
+// the url the api is reached at, built from the scheme and host the client used and the url prefix of the vhost
+fn get_server_url(config: &VhostConfig, headers: &HashMap<&str, &str>) -> Option<String> {
+    let host = headers.get("x-forwarded-host").or_else(|| headers.get("host"))?;
+    let scheme = headers.get("x-forwarded-proto").unwrap_or(&"http");
+    let url_prefix = config.url_prefix.as_deref().unwrap_or("/");
+    Some(format!("{scheme}://{host}/{}", url_prefix.trim_start_matches('/')))
+}
+
+// generate the OpenAPI document for the root path
+// when internal permissions are used, only the endpoints available to the current role are listed
+#[allow(clippy::borrowed_box)]
+pub async fn handle_openapi(headers: HashMap<&str, &str>, backend: &Box<dyn Backend + Send + Sync>) -> Result<(u16, Vec<(String, String)>, String)> {
+    let mut response_headers = vec![];
+    let config = backend.config();
+    // hold on to the current schema for the duration of the request, reloads will not affect it
+    let db_schema_wrap = backend.db_schema();
+    let db_schema = db_schema_wrap.schema();
+    if !openapi::accepts_openapi(headers.get("accept").copied()) {
+        return Err(to_core_error(Error::ContentTypeError {
+            message: format!("None of these Content-Types are available: {}", headers.get("accept").unwrap_or(&"")),
+        }));
+    }
+    let schema_name = &get_schema_name(config, &Method::GET, &headers)?;
+    if config.db_schemas.len() > 1 {
+        response_headers.push(("Content-Profile".to_string(), schema_name.clone()));
+    }
+    let jwt_claims = get_jwt_claims(config, &headers).await?;
+    let (role, authenticated) = get_role(config, &jwt_claims)?;
+    if let (None, false) = (role, authenticated) {
+        return Err(to_core_error(Error::JwtTokenInvalid {
+            message: "unauthenticated requests not allowed".to_string(),
+        }));
     }
-    .context(CoreSnafu)?;
+    let disable_internal_permissions = matches!(config.disable_internal_permissions, Some(true));
+    let role = match (disable_internal_permissions || !db_schema.use_internal_permissions, role) {
+        (true, _) => None,
+        (false, r) => Some(r.unwrap_or_default()),
+    };
+    let server_url = get_server_url(config, &headers);
+    let spec = openapi::generate(db_schema, schema_name, role, server_url.as_deref()).context(CoreSnafu)?;
+    Ok((200, response_headers, spec.to_string()))
+}
+
+#[allow(clippy::borrowed_box)]
+#[allow(clippy::too_many_arguments)]
+pub async fn handle<'a>(
+    root: &'a str, method: &Method, path: &'a str, get: Vec<(&'a str, &'a str)>, body: Option<&'a str>, headers: HashMap<&'a str, &'a str>,
+    cookies: HashMap<&'a str, &'a str>, backend: &Box<dyn Backend + Send + Sync>,
+) -> Result<(u16, ContentType, Vec<(String, String)>, ResponseBody)> {
+    let mut response_headers = vec![];
+    let config = backend.config();
+    // hold on to the current schema for the duration of the request, reloads will not affect it
+    let db_schema_wrap = backend.db_schema();
+    let db_schema = db_schema_wrap.schema();
+    let disable_internal_permissions = matches!(config.disable_internal_permissions, Some(true));
+    let schema_name = &get_schema_name(config, method, &headers)?;
+
+    if config.db_schemas.len() > 1 {
+        response_headers.push(("Content-Profile".to_string(), schema_name.clone()));
+    }
+
+    let jwt_claims = get_jwt_claims(config, &headers).await?;
+    let (role, authenticated) = get_role(config, &jwt_claims)?;
 
     debug!("role: {:?}, jwt_claims: {:?}, disable_internal_permissions: {:?}", role, jwt_claims, disable_internal_permissions);
 
@@ -263,6 +310,32 @@ pub async fn handle<'a>(
     };
     // parse request and generate the query
     let mut request = parse(schema_name, root, db_schema, method.as_str(), path, get, body, headers, cookies, max_rows).context(CoreSnafu)?;
+
+    // the preferences that can not be honored are dropped so they are also not reported in Preference-Applied
+    // max-affected is only enforced together with handling=strict (like PostgREST) and clickhouse has no transactions
+    if let Some(preferences) = request.preferences.as_mut() {
+        if !config.db_tx_allow_override || config.db_type == "clickhouse" {
+            preferences.transaction = None;
+        }
+        if preferences.handling != Some(Handling::Strict) || config.db_type == "clickhouse" {
+            preferences.max_affected = None;
+        }
+    }
+
+    // the execution plan is only available when enabled and, if a list of roles is configured, for those roles
+    if let Plan(..) = request.accept_content_type {
+        let role_allowed = config.db_plan_roles.as_ref().map(|roles| roles.iter().any(|r| r == role)).unwrap_or(true);
+        if !config.db_plan_enabled || !role_allowed {
+            return Err(to_core_error(Error::NotAcceptable {
+                accept: request.headers.get("accept").unwrap_or(&"").to_string(),
+                available: AVAILABLE_MEDIA_TYPES
+                    .iter()
+                    .filter(|(_, t, _)| !matches!(t, Plan(..)))
+                    .map(|(t, ..)| t.to_string())
+                    .collect(),
+            }));
+        }
+    }
     // in case when the role is not set (but authenticated through jwt) the query will be executed with the privileges
     // of the "authenticator" role unless the DbSchema has internal privileges set
 
@@ -290,22 +363,34 @@ pub async fn handle<'a>(
 
     debug!("request: {:?}", request);
 
+    // the rows are sent as they are read so the size of the result is not known when the headers are sent
+    if stream_response(config, &request) {
+        if let Some(body) = backend.execute_stream(db_schema, authenticated, &request, &env).await? {
+            response_headers.push(("Content-Range".to_string(), "*/*".to_string()));
+            return Ok((200, request.accept_content_type.clone(), response_headers, ResponseBody::Stream(body)));
+        }
+    }
+
     let response: ApiResponse = match config.db_type.as_str() {
         #[cfg(feature = "postgresql")]
-        "postgresql" => backend.execute(authenticated, &request, &env).await?,
+        "postgresql" => backend.execute(db_schema, authenticated, &request, &env).await?,
 
         #[cfg(feature = "clickhouse")]
-        "clickhouse" => backend.execute(authenticated, &request, &env).await?,
+        "clickhouse" => backend.execute(db_schema, authenticated, &request, &env).await?,
 
         #[cfg(feature = "sqlite")]
-        "sqlite" => task::block_in_place(|| backend.execute(authenticated, &request, &env)).await?,
+        "sqlite" => task::block_in_place(|| backend.execute(db_schema, authenticated, &request, &env)).await?,
 
         #[cfg(feature = "mysql")]
-        "mysql" => backend.execute(authenticated, &request, &env).await?,
+        "mysql" => backend.execute(db_schema, authenticated, &request, &env).await?,
 
         t => panic!("unsuported database type: {}", t),
     };
 
+    if let Plan(..) = request.accept_content_type {
+        return Ok((200, request.accept_content_type.clone(), response_headers, ResponseBody::Text(response.body)));
+    }
+
     // create and return the response to the client
     let page_total = response.page_total;
     let total_result_set = response.total_result_set;
@@ -320,7 +405,9 @@ pub async fn handle<'a>(
                 ..
             },
         ) => SingularJSON,
+        (GeoJSON, _) => GeoJSON,
         (TextCSV, _) => TextCSV,
+        (t @ (TextPlain | TextXML | OctetStream), _) => t.clone(),
         _ => ApplicationJSON,
     };
 
@@ -376,14 +463,8 @@ pub async fn handle<'a>(
         _ => content_range_status(lower, upper, total),
     };
 
-    if let Some(Preferences { resolution: Some(r), .. }) = request.preferences {
-        response_headers.push((
-            "Preference-Applied".to_string(),
-            match r {
-                MergeDuplicates => "resolution=merge-duplicates".to_string(),
-                IgnoreDuplicates => "resolution=ignore-duplicates".to_string(),
-            },
-        ));
+    if let Some(applied) = preference_applied(method, &request) {
+        response_headers.push(("Preference-Applied".to_string(), applied));
     }
 
     let response_status: Option<String> = response.response_status;
@@ -391,7 +472,56 @@ pub async fn handle<'a>(
         status = response_status_str.parse::<u16>().map_err(|_| Error::GucStatusError).context(CoreSnafu)?;
     }
 
-    Ok((status, content_type, response_headers, response.body))
+    Ok((status, content_type, response_headers, ResponseBody::Text(response.body)))
+}
+
+// the preferences that were honored, planned and estimated counts are not implemented so they are not reported
+// resolution only applies to upserts and max-affected only to updates and deletes
+fn preference_applied(method: &Method, request: &ApiRequest) -> Option<String> {
+    let Preferences {
+        resolution,
+        representation,
+        count,
+        transaction,
+        handling,
+        max_affected,
+    } = request.preferences.as_ref()?;
+    let is_upsert = matches!((method, &request.query.node), (&Method::POST, Insert { on_conflict: Some(_), .. }));
+    let is_update_or_delete = matches!(request.query.node, Update { .. } | Delete { .. });
+    let max_affected = max_affected.filter(|_| is_update_or_delete).map(|m| format!("max-affected={m}"));
+    let applied = [
+        resolution.as_ref().filter(|_| is_upsert).map(|r| match r {
+            MergeDuplicates => "resolution=merge-duplicates",
+            IgnoreDuplicates => "resolution=ignore-duplicates",
+        }),
+        representation.as_ref().map(|r| match r {
+            Representation::Full => "return=representation",
+            Representation::None => "return=minimal",
+            Representation::HeadersOnly => "return=headers-only",
+        }),
+        match count {
+            Some(Count::ExactCount) => Some("count=exact"),
+            _ => None,
+        },
+        transaction.as_ref().map(|t| match t {
+            Transaction::Commit => "tx=commit",
+            Transaction::Rollback => "tx=rollback",
+        }),
+        handling.as_ref().map(|h| match h {
+            Handling::Strict => "handling=strict",
+            Handling::Lenient => "handling=lenient",
+        }),
+        max_affected.as_deref(),
+    ]
+    .iter()
+    .flatten()
+    .copied()
+    .collect::<Vec<_>>();
+    if applied.is_empty() {
+        None
+    } else {
+        Some(applied.join(", "))
+    }
 }
 
 fn content_range_header(lower: i64, upper: i64, total: Option<i64>) -> String {
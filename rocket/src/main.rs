@@ -22,6 +22,9 @@ extern crate rocket;
 use http::Method;
 use snafu::{OptionExt, ResultExt};
 use std::collections::HashMap;
+use std::sync::Arc;
+#[cfg(unix)]
+use tokio::signal::unix::{signal, SignalKind};
 use figment::{
     providers::{Env, Format, Toml},
     Figment, Profile,
@@ -30,7 +33,7 @@ use rocket::{
     routes,
     fs::{FileServer, Options},
     http::{uri::Origin, CookieJar, Header, Status, ContentType as HTTPContentType},
-    Build, Config as RocketConfig, Rocket, State,
+    Build, Config as RocketConfig, Rocket,
 };
 mod frontend;
 use frontend::postgrest;
@@ -38,15 +41,18 @@ use frontend::postgrest;
 //use config::VhostConfig;
 pub use subzero_core::config;
 use subzero_core::{
-    config::VhostConfig,
+    config::{Config, VhostConfig},
     error::{GucStatusSnafu, Error as CoreError},
-    api::ContentType::{SingularJSON, TextCSV, ApplicationJSON, Other},
+    api::{
+        ContentType::{SingularJSON, GeoJSON, TextCSV, TextPlain, TextXML, OctetStream, ApplicationJSON, Plan, Other},
+        PlanFormat,
+    },
 };
 mod error;
 use error::{Error, CoreSnafu};
 
 mod backend;
-use backend::Backend;
+use backend::{Backend, ResponseBody};
 
 #[cfg(feature = "postgresql")]
 use backend::postgresql::PostgreSQLBackend;
@@ -60,56 +66,110 @@ use backend::sqlite::SQLiteBackend;
 #[cfg(feature = "mysql")]
 use backend::mysql::MySQLBackend;
 
+use base64::{Engine as _, engine::general_purpose};
+use subtle::ConstantTimeEq;
+
 mod rocket_util;
-use rocket_util::{AllHeaders, ApiResponse, QueryString, RocketError};
+use rocket_util::{AllHeaders, ApiResponse, QueryString, RocketError, VhostBackend, Vhosts, DEFAULT_VHOST};
 
 type DbBackend = Box<dyn Backend + Send + Sync>;
 lazy_static! {
     static ref SINGLE_CONTENT_TYPE: HTTPContentType = HTTPContentType::parse_flexible("application/vnd.pgrst.object+json").unwrap();
+    static ref OPENAPI_CONTENT_TYPE: HTTPContentType = HTTPContentType::parse_flexible(subzero_core::openapi::OPENAPI_CONTENT_TYPE).unwrap();
 }
 
 // define rocket request handlers, they are just wrappers around handle_request function
 // since rocket does not allow yet a single function to handle multiple verbs
-// #[get("/")]
-// fn index() -> &'static str { "Hello, world!" }
+
+// the root path serves the OpenAPI document describing the api
+#[get("/")]
+async fn index(headers: AllHeaders<'_>, db_backend: VhostBackend<'_>) -> Result<ApiResponse, RocketError> {
+    let headers_str = headers
+        .iter()
+        .map(|h| (h.name().as_str().to_lowercase(), h.value().to_string()))
+        .collect::<HashMap<_, _>>();
+    let (status, response_headers, response_body) =
+        postgrest::handle_openapi(headers_str.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect(), &db_backend)
+            .await
+            .map_err(RocketError)?;
+
+    Ok(ApiResponse {
+        response: (
+            Status::from_code(status)
+                .context(GucStatusSnafu)
+                .context(CoreSnafu)
+                .map_err(RocketError)?,
+            (OPENAPI_CONTENT_TYPE.clone(), response_body.into_bytes()),
+        ),
+        headers: response_headers.into_iter().map(|(n, v)| Header::new(n, v)).collect::<Vec<_>>(),
+        body_stream: None,
+    })
+}
 
 #[get("/<table>?<parameters..>")]
 async fn get<'a>(
-    table: &'a str, origin: &Origin<'_>, parameters: QueryString<'a>, cookies: &CookieJar<'a>, headers: AllHeaders<'a>, db_backend: &State<DbBackend>,
+    table: &'a str, origin: &Origin<'_>, parameters: QueryString<'a>, cookies: &CookieJar<'a>, headers: AllHeaders<'a>, db_backend: VhostBackend<'_>,
 ) -> Result<ApiResponse, RocketError> {
-    handle_request(&Method::GET, table, origin, parameters, None, cookies, headers, db_backend).await
+    handle_request(&Method::GET, table, origin, parameters, None, cookies, headers, &db_backend).await
 }
 
 #[post("/<table>?<parameters..>", data = "<body>")]
 async fn post<'a>(
     table: &'a str, origin: &Origin<'_>, parameters: QueryString<'a>, body: &'a str, cookies: &CookieJar<'a>, headers: AllHeaders<'a>,
-    db_backend: &State<DbBackend>,
+    db_backend: VhostBackend<'_>,
 ) -> Result<ApiResponse, RocketError> {
-    handle_request(&Method::POST, table, origin, parameters, Some(body), cookies, headers, db_backend).await
+    handle_request(&Method::POST, table, origin, parameters, Some(body), cookies, headers, &db_backend).await
 }
 
 #[delete("/<table>?<parameters..>", data = "<body>")]
 async fn delete<'a>(
     table: &'a str, origin: &Origin<'_>, parameters: QueryString<'a>, body: &'a str, cookies: &CookieJar<'a>, headers: AllHeaders<'a>,
-    db_backend: &State<DbBackend>,
+    db_backend: VhostBackend<'_>,
 ) -> Result<ApiResponse, RocketError> {
-    handle_request(&Method::DELETE, table, origin, parameters, Some(body), cookies, headers, db_backend).await
+    handle_request(&Method::DELETE, table, origin, parameters, Some(body), cookies, headers, &db_backend).await
 }
 
 #[patch("/<table>?<parameters..>", data = "<body>")]
 async fn patch<'a>(
     table: &'a str, origin: &Origin<'_>, parameters: QueryString<'a>, body: &'a str, cookies: &CookieJar<'a>, headers: AllHeaders<'a>,
-    db_backend: &State<DbBackend>,
+    db_backend: VhostBackend<'_>,
 ) -> Result<ApiResponse, RocketError> {
-    handle_request(&Method::PATCH, table, origin, parameters, Some(body), cookies, headers, db_backend).await
+    handle_request(&Method::PATCH, table, origin, parameters, Some(body), cookies, headers, &db_backend).await
 }
 
 #[put("/<table>?<parameters..>", data = "<body>")]
 async fn put<'a>(
     table: &'a str, origin: &Origin<'_>, parameters: QueryString<'a>, body: &'a str, cookies: &CookieJar<'a>, headers: AllHeaders<'a>,
-    db_backend: &State<DbBackend>,
+    db_backend: VhostBackend<'_>,
 ) -> Result<ApiResponse, RocketError> {
-    handle_request(&Method::PUT, table, origin, parameters, Some(body), cookies, headers, db_backend).await
+    handle_request(&Method::PUT, table, origin, parameters, Some(body), cookies, headers, &db_backend).await
+}
+
+// reload the schema cache of the vhost, the request needs to present the configured admin token
+#[post("/admin/reload")]
+async fn reload(headers: AllHeaders<'_>, db_backend: VhostBackend<'_>) -> Result<Status, RocketError> {
+    let admin_token = match &db_backend.config().admin_token {
+        Some(t) => t,
+        None => return Ok(Status::NotFound),
+    };
+    match headers.get_one("authorization").and_then(|a| a.strip_prefix("Bearer ")) {
+        // constant time comparison so the token can not be guessed from the response time
+        Some(t) if bool::from(t.as_bytes().ct_eq(admin_token.as_bytes())) => {
+            db_backend.reload_schema().await.map_err(RocketError)?;
+            Ok(Status::Ok)
+        }
+        _ => Ok(Status::Unauthorized),
+    }
+}
+
+// reload the schema cache of all the vhosts
+async fn reload_all(vhosts: &Vhosts) {
+    for (vhost, backend) in vhosts.0.iter() {
+        match backend.reload_schema().await {
+            Ok(_) => println!("[{vhost}] Schema cache reloaded"),
+            Err(e) => println!("[{vhost}] Failed to reload the schema cache: {e}"),
+        }
+    }
 }
 
 // main request handler
@@ -117,7 +177,7 @@ async fn put<'a>(
 #[allow(clippy::too_many_arguments)]
 async fn handle_request(
     method: &Method, table: &str, origin: &Origin<'_>, parameters: QueryString<'_>, body: Option<&str>, cookies: &CookieJar<'_>,
-    headers: AllHeaders<'_>, db_backend: &State<DbBackend>,
+    headers: AllHeaders<'_>, db_backend: &DbBackend,
 ) -> Result<ApiResponse, RocketError> {
     let headers_str = headers
         .iter()
@@ -136,10 +196,16 @@ async fn handle_request(
     .await
     .map_err(RocketError)?;
 
-    let http_content_type = match response_content_type {
+    let http_content_type = match &response_content_type {
         SingularJSON => Ok(SINGLE_CONTENT_TYPE.clone()),
+        GeoJSON => Ok(HTTPContentType::new("application", "geo+json")),
         TextCSV => Ok(HTTPContentType::CSV),
+        TextPlain => Ok(HTTPContentType::Plain),
+        TextXML => Ok(HTTPContentType::XML),
+        OctetStream => Ok(HTTPContentType::Binary),
         ApplicationJSON => Ok(HTTPContentType::JSON),
+        Plan(PlanFormat::Json, _) => Ok(HTTPContentType::new("application", "vnd.pgrst.plan+json")),
+        Plan(PlanFormat::Text, _) => Ok(HTTPContentType::new("application", "vnd.pgrst.plan+text")),
         Other(t) => Err(CoreError::ContentTypeError {
             message: format!("None of these Content-Types are available: {t}"),
         }),
@@ -147,6 +213,22 @@ async fn handle_request(
     .context(CoreSnafu)
     .map_err(RocketError)?;
 
+    // binary values come from the database base64 encoded
+    let (response_body, body_stream) = match (response_content_type, response_body) {
+        (_, ResponseBody::Stream(s)) => (vec![], Some(s)),
+        (OctetStream, ResponseBody::Text(b)) => (
+            general_purpose::STANDARD
+                .decode(b.replace('\n', ""))
+                .map_err(|e| CoreError::InternalError {
+                    message: format!("Failed to decode the binary response: {e}"),
+                })
+                .context(CoreSnafu)
+                .map_err(RocketError)?,
+            None,
+        ),
+        (_, ResponseBody::Text(b)) => (b.into_bytes(), None),
+    };
+
     Ok(ApiResponse {
         response: (
             Status::from_code(status)
@@ -156,55 +238,104 @@ async fn handle_request(
             (http_content_type, response_body),
         ),
         headers: response_headers.into_iter().map(|(n, v)| Header::new(n, v)).collect::<Vec<_>>(),
+        body_stream,
     })
 }
 
-// main function where we read the configuration and initialize the rocket webserver
-#[allow(unreachable_code)]
-async fn start() -> Result<Rocket<Build>, Error> {
+async fn init_backend(vhost: &str, vhost_config: VhostConfig) -> Result<DbBackend, Error> {
+    let backend: DbBackend = match vhost_config.db_type.as_str() {
+        #[cfg(feature = "postgresql")]
+        "postgresql" => Box::new(PostgreSQLBackend::init(vhost.to_string(), vhost_config).await?),
+        #[cfg(feature = "clickhouse")]
+        "clickhouse" => Box::new(ClickhouseBackend::init(vhost.to_string(), vhost_config).await?),
+        #[cfg(feature = "sqlite")]
+        "sqlite" => Box::new(SQLiteBackend::init(vhost.to_string(), vhost_config).await?),
+        #[cfg(feature = "mysql")]
+        "mysql" => Box::new(MySQLBackend::init(vhost.to_string(), vhost_config).await?),
+        t => panic!("unsupported database type: {}", t),
+    };
+    Ok(backend)
+}
+
+// try to read the configuration from both a file and env vars
+// this configuration includes both subzero specific settings (VhostConfig type)
+// and rocket configuration
+fn read_config() -> Figment {
     #[cfg(debug_assertions)]
     let profile = RocketConfig::DEBUG_PROFILE;
 
     #[cfg(not(debug_assertions))]
     let profile = RocketConfig::RELEASE_PROFILE;
 
-    // try to read the configuration from both a file and env vars
-    // this configuration includes both subzero specific settings (VhostConfig type)
-    // and rocket configuration
-    let config = Figment::from(RocketConfig::default())
+    Figment::from(RocketConfig::default())
         .merge(Toml::file(Env::var_or("SUBZERO_CONFIG", "config.toml")).nested())
         .merge(Env::prefixed("SUBZERO_").split("__").ignore(&["PROFILE"]).global())
         .merge(Env::prefixed("PGRST_").split("__").ignore(&["PROFILE"]).global())
-        .select(Profile::from_env_or("SUBZERO_PROFILE", profile));
+        .select(Profile::from_env_or("SUBZERO_PROFILE", profile))
+}
+
+// main function where we read the configuration and initialize the rocket webserver
+async fn start() -> Result<Rocket<Build>, Error> {
+    start_with_config(read_config()).await
+}
 
+#[allow(unreachable_code)]
+async fn start_with_config(config: Figment) -> Result<Rocket<Build>, Error> {
     // extract the subzero specific part of the configuration
-    let vhost_config: VhostConfig = config.extract().expect("config");
+    // when a "vhosts" section is present, each request is routed to the backend of the vhost
+    // matching its Host header, otherwise the top level configuration serves all the requests
+    let vhost_configs: HashMap<String, VhostConfig> = match config.find_value("vhosts") {
+        Ok(_) => config.extract::<Config>().expect("config").vhosts,
+        Err(_) => HashMap::from([(DEFAULT_VHOST.to_string(), config.extract().expect("config"))]),
+    };
 
-    let default_prefix = "/".to_string();
-    #[allow(unused_variables)]
-    let url_prefix = vhost_config.url_prefix.clone().unwrap_or(default_prefix);
+    // the static files are served from the root path so they can not belong to a specific vhost
+    if vhost_configs.len() > 1 && vhost_configs.values().any(|c| c.static_files_dir.is_some()) {
+        return Err(Error::Internal {
+            message: "static_files_dir is not supported when multiple vhosts are configured".to_string(),
+        });
+    }
 
-    //initialize the backend
-    #[allow(unused_variables)]
-    let backend: Box<dyn Backend + Send + Sync> = match vhost_config.db_type.as_str() {
-        #[cfg(feature = "postgresql")]
-        "postgresql" => Box::new(PostgreSQLBackend::init("default".to_string(), vhost_config.clone()).await?),
-        #[cfg(feature = "clickhouse")]
-        "clickhouse" => Box::new(ClickhouseBackend::init("default".to_string(), vhost_config.clone()).await?),
-        #[cfg(feature = "sqlite")]
-        "sqlite" => Box::new(SQLiteBackend::init("default".to_string(), vhost_config.clone()).await?),
-        #[cfg(feature = "mysql")]
-        "mysql" => Box::new(MySQLBackend::init("default".to_string(), vhost_config.clone()).await?),
-        t => panic!("unsupported database type: {}", t),
-    };
+    //initialize the backends, each vhost gets its own connection pool and schema cache
+    let mut backends = HashMap::new();
+    for (vhost, vhost_config) in vhost_configs {
+        let backend = init_backend(&vhost, vhost_config).await?;
+        backends.insert(vhost.to_lowercase(), backend);
+    }
+
+    let default_prefix = "/".to_string();
+    let mut url_prefixes = backends
+        .values()
+        .map(|b| b.config().url_prefix.clone().unwrap_or_else(|| default_prefix.clone()))
+        .collect::<Vec<_>>();
+    url_prefixes.sort();
+    url_prefixes.dedup();
+
+    let static_files_dir = backends.values().find_map(|b| b.config().static_files_dir.clone());
+
+    let vhosts = Vhosts(Arc::new(backends));
+
+    // reload the schema cache of all the vhosts on SIGUSR1
+    #[cfg(unix)]
+    {
+        let vhosts = vhosts.clone();
+        let mut signals = signal(SignalKind::user_defined1()).expect("SIGUSR1 handler");
+        tokio::spawn(async move {
+            while signals.recv().await.is_some() {
+                reload_all(&vhosts).await;
+            }
+        });
+    }
 
     // initialize the web server
-    let mut server = rocket::custom(config)
-        .manage(backend)
-        .mount(&url_prefix, routes![get, post, delete, patch, put])
-        .mount(format!("{}/rpc", &url_prefix), routes![get, post]);
+    let mut server = rocket::custom(config).manage(vhosts);
+    for url_prefix in &url_prefixes {
+        server = server
+            .mount(url_prefix, routes![index, reload, get, post, delete, patch, put])
+            .mount(format!("{}/rpc", url_prefix), routes![get, post]);
+    }
 
-    if let Some(static_dir) = &vhost_config.static_files_dir {
+    if let Some(static_dir) = static_files_dir {
         let options = Options::Index;
         server = server.mount("/", FileServer::new(static_dir, options).rank(-100));
     }
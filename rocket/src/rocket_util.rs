@@ -19,11 +19,16 @@ use rocket::{
     form::{DataField, FromForm, Options, Result as FormResult, ValueField},
     http::{ContentType as HTTPContentType, Header, HeaderMap, Status},
     request::{FromRequest, Outcome, Request},
-    response::{Responder, Response, Result},
+    response::{stream::ReaderStream, Responder, Response, Result},
 };
 use rocket::response::{self};
+use rocket::futures::StreamExt;
 use std::io::Cursor;
 use crate::error::Error;
+use crate::DbBackend;
+use crate::backend::BodyStream;
+use std::collections::HashMap;
+use std::sync::Arc;
 use std::ops::Deref;
 //use hyper::{http::HeaderMap as HyperHeaderMap};
 //use bytes::{Bytes};
@@ -71,15 +76,84 @@ impl<'r> Deref for AllHeaders<'r> {
     }
 }
 
-#[derive(Debug)]
+// name of the vhost used when the configuration does not define a "vhosts" section
+// and for requests whose Host header does not match any of the configured vhosts
+pub const DEFAULT_VHOST: &str = "default";
+
+// the backends of all the configured vhosts, keyed by host name
+#[derive(Clone)]
+pub struct Vhosts(pub Arc<HashMap<String, DbBackend>>);
+
+// request guard selecting the backend based on the Host header
+pub struct VhostBackend<'r>(&'r DbBackend);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for VhostBackend<'r> {
+    type Error = std::convert::Infallible;
+    async fn from_request(req: &'r rocket::Request<'_>) -> Outcome<Self, Self::Error> {
+        let vhosts = match req.rocket().state::<Vhosts>() {
+            Some(Vhosts(v)) => v,
+            None => return Outcome::Forward(Status::InternalServerError),
+        };
+        let host = req.headers().get_one("host").map(|h| strip_port(h).to_lowercase());
+        let backend = host.and_then(|h| vhosts.get(&h)).or_else(|| vhosts.get(DEFAULT_VHOST));
+        match backend {
+            Some(b) if matches_url_prefix(b.config().url_prefix.as_deref().unwrap_or("/"), req.uri().path().as_str()) => {
+                Outcome::Success(VhostBackend(b))
+            }
+            _ => Outcome::Forward(Status::NotFound),
+        }
+    }
+}
+
+impl<'r> Deref for VhostBackend<'r> {
+    type Target = DbBackend;
+    fn deref(&self) -> &Self::Target {
+        self.0
+    }
+}
+
+fn strip_port(host: &str) -> &str {
+    match host.rsplit_once(':') {
+        Some((h, port)) if port.chars().all(|c| c.is_ascii_digit()) => h,
+        _ => host,
+    }
+}
+
+// the routes are mounted for the url prefixes of all the vhosts
+// so we check the request was made under the prefix of the selected vhost
+fn matches_url_prefix(url_prefix: &str, path: &str) -> bool {
+    match path.strip_prefix(url_prefix.trim_end_matches('/')) {
+        Some(rest) if rest.is_empty() || rest.starts_with('/') => {
+            let segments = rest.split('/').filter(|s| !s.is_empty()).collect::<Vec<_>>();
+            matches!(segments[..], [] | [_] | ["rpc", _] | ["admin", "reload"])
+        }
+        _ => false,
+    }
+}
+
 pub struct ApiResponse {
-    pub response: (Status, (HTTPContentType, String)),
+    pub response: (Status, (HTTPContentType, Vec<u8>)),
     pub headers: Vec<Header<'static>>,
+    // when set, the body is sent in chunks instead of the bytes in response
+    pub body_stream: Option<BodyStream>,
 }
 
 impl<'r> Responder<'r, 'static> for ApiResponse {
     fn respond_to(self, req: &'r Request<'_>) -> Result<'static> {
-        let mut response = Response::build_from(self.response.respond_to(req)?);
+        let mut response = match self.body_stream {
+            Some(stream) => {
+                // ByteStream would tie the response to the lifetime of the request
+                let (status, (content_type, _)) = self.response;
+                let mut response = Response::build();
+                response
+                    .status(status)
+                    .header(content_type)
+                    .streamed_body(ReaderStream::from(stream.map(Cursor::new)));
+                response
+            }
+            None => Response::build_from(self.response.respond_to(req)?),
+        };
         for h in self.headers {
             if h.name() != "content-type" {
                 response.header_adjoin(h);
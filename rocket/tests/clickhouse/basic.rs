@@ -182,15 +182,13 @@ feature "basic"
         {"id":2,"name":"Windows 10"}
       ]
       "#|]
-      //{ matchStatus = 200, matchHeaders = ["Content-Type" <:> "application/json", "Content-Range" <:> "0-1/*"] }
-      { matchStatus = 200, matchHeaders = ["Content-Type" <:> "application/json"] }
+      { matchStatus = 200, matchHeaders = ["Content-Type" <:> "application/json", "Content-Range" <:> "0-1/*"] }
 
       request methodGet "/projects?select=id,name&id=eq.1" [("Accept", "application/vnd.pgrst.object+json")] "" shouldRespondWith
       [json| r#"
         {"id":1,"name":"Windows 7"}
       "#|]
-      //{ matchStatus = 200, matchHeaders = ["Content-Type" <:> "application/vnd.pgrst.object+json", "Content-Range" <:> "0-0/*"] }
-      { matchStatus = 200, matchHeaders = ["Content-Type" <:> "application/vnd.pgrst.object+json"] }
+      { matchStatus = 200, matchHeaders = ["Content-Type" <:> "application/vnd.pgrst.object+json", "Content-Range" <:> "0-0/*"] }
       // get "/tbl1?select=one,two" shouldRespondWith
       //   [json| r#"
       //       [
@@ -213,6 +211,45 @@ feature "basic"
     //   , matchHeaders = ["Content-Type" <:> "application/json"]
     //   }
 
+  describe "count" $ do
+    it "gives the exact count of a limited query" $
+      request methodGet "/projects?select=id&order=id&limit=2"
+          [("Prefer", "count=exact")]
+          ""
+        shouldRespondWith
+        [json|r#"[{"id":1},{"id":2}]"#|]
+          { matchStatus  = 206
+          , matchHeaders = [ "Content-Type" <:> "application/json"
+                          , "Content-Range" <:> "0-1/5" ]
+          }
+
+    it "takes the offset into account" $
+      request methodGet "/projects?select=id&order=id&limit=2&offset=2"
+          [("Prefer", "count=exact")]
+          ""
+        shouldRespondWith
+        [json|r#"[{"id":3},{"id":4}]"#|]
+          { matchStatus  = 206
+          , matchHeaders = [ "Content-Type" <:> "application/json"
+                          , "Content-Range" <:> "2-3/5" ]
+          }
+
+    it "gives the count of a query without limit" $
+      request methodGet "/projects?select=id&id=lt.3&order=id"
+          [("Prefer", "count=estimated")]
+          ""
+        shouldRespondWith
+        [json|r#"[{"id":1},{"id":2}]"#|]
+          { matchHeaders = [ "Content-Type" <:> "application/json"
+                          , "Content-Range" <:> "0-1/2" ]
+          }
+
+    it "fails for a singular response with more rows" $
+      request methodGet "/projects?select=id" [("Accept", "application/vnd.pgrst.object+json")] ""
+        shouldRespondWith
+        [json|r#"{"details":"Results contain 5 rows, application/vnd.pgrst.object+json requires 1 row","message":"JSON object requested, multiple (or no) rows returned"}"#|]
+          { matchStatus  = 406 }
+
   describe "embeding" $
     it "children" $
       get "/projects?select=id,name,tasks(id,name)&id=in.(1,2)&order=id" shouldRespondWith
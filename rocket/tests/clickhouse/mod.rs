@@ -19,6 +19,8 @@
 #[allow(unused_imports)]
 mod basic;
 #[allow(unused_imports)]
+mod native;
+#[allow(unused_imports)]
 mod permissions;
 mod setup;
 #[allow(unused_imports)]
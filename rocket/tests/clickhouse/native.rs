@@ -0,0 +1,74 @@
+// Copyright (c) 2022-2025 subZero Cloud S.R.L
+//
+// This file is part of subZero - The All-in-One library suite for internal tools development
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+use super::setup::*;
+use super::super::start;
+
+static INIT_CLIENT: Once = Once::new();
+lazy_static! {
+    // the same server reached through the clickhouse+native transport
+    static ref CLIENT_INNER: AsyncOnce<Client> = AsyncOnce::new(async {
+        let db_uri = env::var("SUBZERO_DB_URI").unwrap();
+        env::set_var("SUBZERO_DB_URI", db_uri.replacen("http://", "clickhouse+native://", 1));
+        let client = Client::untracked(start().await.unwrap()).await.expect("valid client");
+        env::set_var("SUBZERO_DB_URI", db_uri);
+        client
+    });
+    static ref CLIENT: &'static AsyncOnce<Client> = {
+        thread::spawn(move || {
+            RUNTIME.block_on(async {
+                CLIENT_INNER.get().await;
+            })
+        })
+        .join()
+        .expect("Thread panicked");
+        &*CLIENT_INNER
+    };
+}
+
+haskell_test! {
+feature "native"
+  describe "native transport" $ do
+    it "filters with typed parameters" $
+      get "/projects?select=id,name&id=in.(1,2)&order=id" shouldRespondWith
+      [json| r#"
+      [
+        {"id":1,"name":"Windows 7"},
+        {"id":2,"name":"Windows 10"}
+      ]
+      "#|]
+      { matchStatus = 200, matchHeaders = ["Content-Type" <:> "application/json", "Content-Range" <:> "0-1/*"] }
+
+    it "filters with text parameters" $
+      get "/projects?select=id&name=in.(IOS,OSX)&order=id" shouldRespondWith
+      [json| r#"[{"id":3},{"id":4}]"#|]
+      { matchStatus = 200 }
+
+    it "escapes the text parameters" $
+      get "/projects?select=id&name=eq.Windows 7" shouldRespondWith
+      [json| r#"[{"id":1}]"#|]
+      { matchStatus = 200 }
+
+    it "embeds and counts" $
+      request methodGet "/projects?select=id,tasks(id)&id=eq.1"
+          [("Prefer", "count=exact")]
+          ""
+        shouldRespondWith
+        [json|r#"[{"id":1,"tasks":[{"id":1},{"id":2}]}]"#|]
+          { matchStatus  = 200
+          , matchHeaders = ["Content-Range" <:> "0-0/1"]
+          }
+}
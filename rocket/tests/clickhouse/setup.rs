@@ -17,16 +17,16 @@
 pub use demonstrate::demonstrate;
 use rocket::http::{Cookie, Header};
 use rocket::local::asynchronous::LocalRequest;
-use std::env;
+pub use std::env;
 use std::path::PathBuf;
 use std::process::Command;
-use std::sync::Once;
+pub use std::sync::Once;
 use lazy_static::LazyStatic;
 pub use crate::haskell_test;
-use std::thread;
+pub use std::thread;
 use tokio::runtime::Builder;
-use rocket::local::asynchronous::Client;
-use async_once::AsyncOnce;
+pub use rocket::local::asynchronous::Client;
+pub use async_once::AsyncOnce;
 use super::super::start;
 
 pub static INIT_DB: Once = Once::new();
@@ -34,7 +34,7 @@ pub static INIT_CLIENT: Once = Once::new();
 lazy_static! {
     //pub static ref CLIENT: AsyncOnce<Client> = AsyncOnce::new(async { Client::untracked(start().await.unwrap()).await.expect("valid client") });
     static ref CLIENT_INNER: AsyncOnce<Client> = AsyncOnce::new(async { Client::untracked(start().await.unwrap()).await.expect("valid client") });
-    static ref RUNTIME: tokio::runtime::Runtime = Builder::new_multi_thread().enable_all().build().unwrap();
+    pub static ref RUNTIME: tokio::runtime::Runtime = Builder::new_multi_thread().enable_all().build().unwrap();
     pub static ref CLIENT: &'static AsyncOnce<Client> = {
         thread::spawn(move || {
             RUNTIME.block_on(async {
@@ -92,6 +92,40 @@ feature "basic"
           {"user_id":3,"total":2}
         ]"#|]
         { matchHeaders = ["Content-Type" <:> "application/json"] }
+    it "can call an aggregate on a column and group by the other columns" $
+      get "/users_tasks?select=user_id,total:task_id.count()&order=user_id.asc" shouldRespondWith
+        [json|r#"[
+          {"user_id":1,"total":4},
+          {"user_id":2,"total":3},
+          {"user_id":3,"total":2}
+        ]"#|]
+        { matchHeaders = ["Content-Type" <:> "application/json"] }
+    it "can count the rows of each group" $
+      get "/users_tasks?select=user_id,count()&order=user_id.asc" shouldRespondWith
+        [json|r#"[
+          {"user_id":1,"count":4},
+          {"user_id":2,"count":3},
+          {"user_id":3,"count":2}
+        ]"#|]
+        { matchHeaders = ["Content-Type" <:> "application/json"] }
+    it "can call aggregates without grouping" $
+      get "/users_tasks?select=task_id.max(),task_id.min()" shouldRespondWith
+        [json|r#"[{"max":7,"min":1}]"#|]
+        { matchHeaders = ["Content-Type" <:> "application/json"] }
+    it "can group by an embedded resource" $
+      get "/projects?select=id.sum(),clients(name)&client_id=not.is.null&order=client_id.asc" shouldRespondWith
+        [json|r#"[
+          {"sum":3,"clients":{"name":"Microsoft"}},
+          {"sum":7,"clients":{"name":"Apple"}}
+        ]"#|]
+        { matchHeaders = ["Content-Type" <:> "application/json"] }
+    it "can call aggregates inside an embedded resource" $
+      get "/clients?select=name,projects(id.count())&order=id" shouldRespondWith
+        [json|r#"[
+          {"name":"Microsoft","projects":[{"count":2}]},
+          {"name":"Apple","projects":[{"count":2}]}
+        ]"#|]
+        { matchHeaders = ["Content-Type" <:> "application/json"] }
   describe "delete" $ do
       it "succeeds with 204 and deletion count" $
         request methodDelete "/projects?id=eq.5"
@@ -219,6 +253,10 @@ feature "basic"
       get "/complex_items?select=id&settings=cs.[\"a\"]" shouldRespondWith
         [json| r#"[{"id":3}]"# |]
         { matchHeaders = ["Content-Type" <:> "application/json"] }
+    it "filtering with cs works on nested objects" $
+      get "/complex_items?select=id&settings=cs.{\"foo\":{\"int\":1}}&order=id" shouldRespondWith
+        [json| r#"[{"id":1},{"id":2}]"# |]
+        { matchHeaders = ["Content-Type" <:> "application/json"] }
     it "obtains a json subfield one level with casting" $
       get "/complex_items?id=eq.1&select=settings->foo" shouldRespondWith
         [json| r#"[{"foo":{"int":1,"bar":"baz"}}]"# |] //-- the value of foo here is of type "text"
@@ -250,6 +288,31 @@ feature "basic"
         [json| r#"[{"myInt":1}]"# |] //-- the value in the db is an int, but here we expect a string for now
         { matchHeaders = ["Content-Type" <:> "application/json"] }
 
+  describe "regex operators" $ do
+    it "filters with match" $
+      get "/projects?select=id,name&name=match.[0-9]$&order=id" shouldRespondWith
+        [json| r#"[{"id":1,"name":"Windows 7"},{"id":2,"name":"Windows 10"}]"# |]
+        { matchHeaders = ["Content-Type" <:> "application/json"] }
+
+    it "filters with imatch" $ do
+      get "/projects?select=id,name&name=imatch.os&order=id" shouldRespondWith
+        [json| r#"[{"id":3,"name":"IOS"},{"id":4,"name":"OSX"}]"# |]
+        { matchHeaders = ["Content-Type" <:> "application/json"] }
+      get "/projects?select=id,name&name=match.os&order=id" shouldRespondWith
+        [json| r#"[]"# |]
+        { matchHeaders = ["Content-Type" <:> "application/json"] }
+
+  describe "quantified operators" $ do
+    it "filters with like(any)" $
+      get "/projects?select=id&name=like(any).{Win*,O*}&order=id" shouldRespondWith
+        [json| r#"[{"id":1},{"id":2},{"id":4},{"id":5}]"# |]
+        { matchHeaders = ["Content-Type" <:> "application/json"] }
+
+    it "filters with gt(all)" $
+      get "/projects?select=id&id=gt(all).{1,3}&order=id" shouldRespondWith
+        [json| r#"[{"id":4},{"id":5}]"# |]
+        { matchHeaders = ["Content-Type" <:> "application/json"] }
+
   describe "select" $
     it "simple" $
       get "/tbl1?select=one,two" shouldRespondWith
@@ -319,6 +382,17 @@ feature "basic"
       , matchHeaders = ["Content-Type" <:> "application/json"]
       }
 
+    it "star in the root and in the embed" $
+      get "/projects?select=*,client:clients(*)&id=eq.1" shouldRespondWith
+        [json| r#"
+        [
+          {"id":1,"name":"Windows 7","client_id":1,"client":{"id":1,"name":"Microsoft"}}
+        ]
+        "#|]
+      { matchStatus = 200
+      , matchHeaders = ["Content-Type" <:> "application/json"]
+      }
+
     it "many" $
       get "/tasks?select=id,name,users(id,name)&id=in.(1,5)" shouldRespondWith
         [json| r#"
@@ -330,4 +404,125 @@ feature "basic"
       { matchStatus = 200
       , matchHeaders = ["Content-Type" <:> "application/json"]
       }
+
+    it "spread parent" $
+      get "/projects?select=id,name,...clients(client_name:name)&id=in.(1,3)" shouldRespondWith
+        [json| r#"
+        [
+          {"id":1,"name":"Windows 7","client_name":"Microsoft"},
+          {"id":3,"name":"IOS","client_name":"Apple"}
+        ]
+        "#|]
+      { matchStatus = 200
+      , matchHeaders = ["Content-Type" <:> "application/json"]
+      }
+
+    it "nested spread" $
+      get "/tasks?select=id,...projects(project:name,...clients(client:name))&id=in.(1,5)" shouldRespondWith
+        [json| r#"
+        [
+          {"id":1,"project":"Windows 7","client":"Microsoft"},
+          {"id":5,"project":"IOS","client":"Apple"}
+        ]
+        "#|]
+      { matchStatus = 200
+      , matchHeaders = ["Content-Type" <:> "application/json"]
+      }
+
+    it "spread with a nested embed" $
+      get "/tasks?select=id,...projects(project:name,clients(name))&id=in.(1,5)" shouldRespondWith
+        [json| r#"
+        [
+          {"id":1,"project":"Windows 7","clients":{"name":"Microsoft"}},
+          {"id":5,"project":"IOS","clients":{"name":"Apple"}}
+        ]
+        "#|]
+      { matchStatus = 200
+      , matchHeaders = ["Content-Type" <:> "application/json"]
+      }
+
+    it "inner embed drops the parent rows without embedded rows" $
+      get "/projects?select=id,client:clients!inner(name)&client.name=eq.Apple" shouldRespondWith
+        [json| r#"
+        [
+          {"id":3,"client":{"name":"Apple"}},
+          {"id":4,"client":{"name":"Apple"}}
+        ]
+        "#|]
+      { matchStatus = 200
+      , matchHeaders = ["Content-Type" <:> "application/json"]
+      }
+
+    it "inner embed on a to-many relationship" $
+      get "/clients?select=id,projects!inner(name)&projects.name=eq.IOS" shouldRespondWith
+        [json| r#"[{"id":2,"projects":[{"name":"IOS"}]}]"#|]
+      { matchStatus = 200
+      , matchHeaders = ["Content-Type" <:> "application/json"]
+      }
+
+    it "not null filter on an embed" $
+      get "/projects?select=id,clients(name)&clients.name=eq.Apple&clients=not.is.null" shouldRespondWith
+        [json| r#"
+        [
+          {"id":3,"clients":{"name":"Apple"}},
+          {"id":4,"clients":{"name":"Apple"}}
+        ]
+        "#|]
+      { matchStatus = 200
+      , matchHeaders = ["Content-Type" <:> "application/json"]
+      }
+
+    it "null filter on an embed" $
+      get "/projects?select=id,clients(name)&clients.name=eq.Apple&clients=is.null" shouldRespondWith
+        [json| r#"
+        [
+          {"id":1,"clients":null},
+          {"id":2,"clients":null},
+          {"id":5,"clients":null}
+        ]
+        "#|]
+      { matchStatus = 200
+      , matchHeaders = ["Content-Type" <:> "application/json"]
+      }
+
+    it "order by a column of a parent" $
+      get "/projects?select=id,client:clients(name)&order=client(name).desc,id.desc&id=lt.5" shouldRespondWith
+        [json| r#"
+        [
+          {"id":2,"client":{"name":"Microsoft"}},
+          {"id":1,"client":{"name":"Microsoft"}},
+          {"id":4,"client":{"name":"Apple"}},
+          {"id":3,"client":{"name":"Apple"}}
+        ]
+        "#|]
+      { matchStatus = 200
+      , matchHeaders = ["Content-Type" <:> "application/json"]
+      }
+
+    it "order by a column of a parent that is not embedded" $
+      get "/projects?select=id&order=clients(name).asc,id&id=lt.5" shouldRespondWith
+        [json| r#"[{"id":3},{"id":4},{"id":1},{"id":2}]"#|]
+      { matchStatus = 200
+      , matchHeaders = ["Content-Type" <:> "application/json"]
+      }
+
+    it "order by a to-many relationship fails" $
+      get "/clients?select=id&order=projects(name)" shouldRespondWith
+        [json| r#"
+        {
+          "message":"A related order on 'projects' is not possible",
+          "details":"'clients' and 'projects' do not form a many-to-one or one-to-one relationship"
+        }
+        "#|]
+      { matchStatus = 400 }
+
+    it "spread on a to-many relationship fails" $
+      get "/clients?select=name,...projects(name)" shouldRespondWith
+        [json| r#"
+        {
+          "message":"A spread operation on 'projects' is not possible",
+          "details":"'clients' and 'projects' do not form a many-to-one or one-to-one relationship"
+        }
+        "#|]
+      { matchStatus = 400 }
 }
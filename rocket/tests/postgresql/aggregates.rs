@@ -81,6 +81,14 @@ feature "aggregates"
                 {"city":"Shalford","total_order_amount":"$13,000.00"}
             ]"#|]
             { matchHeaders = ["Content-Type" <:> "application/json"] }
+    it "can cast the result of an aggregate" $
+        get "/product_orders?select=city,total:order_amount.sum()::numeric&order=city" shouldRespondWith
+            [json|r#"[
+                {"city":"Arlington","total":37000.00},
+                {"city":"GuildFord","total":50500.00},
+                {"city":"Shalford","total":13000.00}
+            ]"#|]
+            { matchHeaders = ["Content-Type" <:> "application/json"] }
     it "can call an aggregate function with partition" $
         get "/product_orders?select=order_id,city,order_amount,grand_total:$sum(order_amount)-p(city)" shouldRespondWith
             [json|r#"[
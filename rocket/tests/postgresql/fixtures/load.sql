@@ -19,3 +19,4 @@
 \ir sql/subzero.custom_relations.sql
 \ir sql/subzero.permissions.sql
 \ir sql/subzero.basic.sql
+\ir sql/subzero.postgis.sql
@@ -151,6 +151,10 @@ CREATE FUNCTION anti_id(test.items) RETURNS bigint
     LANGUAGE sql IMMUTABLE
     AS $_$ SELECT $1.id * -1 $_$;
 
+CREATE FUNCTION next_items(test.items) RETURNS SETOF test.items
+    LANGUAGE sql STABLE
+    AS $_$ SELECT * FROM test.items WHERE id > $1.id $_$;
+
 SET search_path = public, pg_catalog;
 
 CREATE FUNCTION always_false(test.items) RETURNS boolean
@@ -1870,6 +1874,10 @@ union all
 select 'Welcome to PostgREST';
 $$ language sql;
 
+create or replace function welcome_bin() returns bytea as $$
+select convert_to('Welcome to PostgREST', 'UTF8');
+$$ language sql;
+
 create or replace function "welcome.html"() returns text as $_$
 select $$
 <html>
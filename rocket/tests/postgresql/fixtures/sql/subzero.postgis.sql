@@ -0,0 +1,23 @@
+-- the geojson tests need postgis, the fixtures are skipped when the extension is not available
+-- the extension lives in the exposed schema because the search_path of a request only contains that schema
+do $$
+begin
+    if exists (select 1 from pg_available_extensions where name = 'postgis') then
+        drop schema if exists postgis_test cascade;
+        create schema postgis_test;
+        create extension if not exists postgis schema postgis_test;
+
+        create table postgis_test.shops (
+            id int primary key,
+            address text,
+            shop_geom postgis_test.geometry(point, 4326)
+        );
+        insert into postgis_test.shops values
+            (1, '1369 Cambridge St', 'SRID=4326;POINT(-71.10044 42.373695)'),
+            (2, '757 Massachusetts Ave', 'SRID=4326;POINT(-71.10543 42.366432)');
+
+        grant usage on schema postgis_test to postgrest_test_anonymous;
+        grant select on postgis_test.shops to postgrest_test_anonymous;
+    end if;
+end
+$$;
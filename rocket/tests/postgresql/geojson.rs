@@ -0,0 +1,98 @@
+// Copyright (c) 2022-2025 subZero Cloud S.R.L
+//
+// This file is part of subZero - The All-in-One library suite for internal tools development
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+use super::setup::*;
+use super::super::start;
+use serde_json::{json, Value};
+use std::process::Command;
+
+static INIT_CLIENT: Once = Once::new();
+lazy_static! {
+    static ref CLIENT_INNER: AsyncOnce<Client> = AsyncOnce::new(async {
+        let db_schemas = env::var("SUBZERO_DB_SCHEMAS").unwrap();
+        env::set_var("SUBZERO_DB_SCHEMAS", "[postgis_test]");
+        let client = Client::untracked(start().await.unwrap()).await.expect("valid client");
+        env::set_var("SUBZERO_DB_SCHEMAS", db_schemas);
+        client
+    });
+    static ref CLIENT: &'static AsyncOnce<Client> = {
+        thread::spawn(move || {
+            RUNTIME.block_on(async {
+                CLIENT_INNER.get().await;
+            })
+        })
+        .join()
+        .expect("Thread panicked");
+        &*CLIENT_INNER
+    };
+}
+
+// the fixtures are only loaded when postgis is available in the test database
+fn postgis_loaded() -> bool {
+    let output = Command::new("psql")
+        .arg(env::var("SUBZERO_DB_URI").unwrap())
+        .arg("-tAc")
+        .arg("select 1 from pg_namespace where nspname = 'postgis_test'")
+        .output()
+        .expect("failed to execute psql");
+    String::from_utf8_lossy(&output.stdout).trim() == "1"
+}
+
+demonstrate! {
+    describe "geojson" {
+        use super::*;
+        use rocket::http::{Header, Status};
+
+        before {
+            setup_db(&INIT_DB);
+            setup_client(&INIT_CLIENT, &CLIENT);
+        }
+
+        #[rocket::async_test]
+        async it "returns the rows as a feature collection" {
+            if !postgis_loaded() {
+                return;
+            }
+            let client = CLIENT.get().await;
+            let response = client
+                .get("/rest/shops?select=id,address,shop_geom&order=id")
+                .header(Header::new("Accept", "application/geo+json"))
+                .dispatch()
+                .await;
+            assert_eq!(response.status(), Status::Ok);
+            assert_eq!(response.content_type().map(|c| c.to_string()), Some("application/geo+json".to_string()));
+            let body: Value = serde_json::from_str(&response.into_string().await.unwrap()).unwrap();
+            assert_eq!(
+                body,
+                json!({
+                    "type": "FeatureCollection",
+                    "features": [
+                        {
+                            "type": "Feature",
+                            "geometry": {"type": "Point", "coordinates": [-71.10044, 42.373695]},
+                            "properties": {"id": 1, "address": "1369 Cambridge St"}
+                        },
+                        {
+                            "type": "Feature",
+                            "geometry": {"type": "Point", "coordinates": [-71.10543, 42.366432]},
+                            "properties": {"id": 2, "address": "757 Massachusetts Ave"}
+                        }
+                    ]
+                })
+            );
+        }
+    }
+}
@@ -50,6 +50,8 @@ mod delete;
 #[allow(unused_imports)]
 mod embed_disambiguation;
 #[allow(unused_imports)]
+mod geojson;
+#[allow(unused_imports)]
 mod insert;
 #[allow(unused_imports)]
 mod json_operator;
@@ -60,12 +62,22 @@ mod multiple_schemas;
 // #[allow(unused_imports)]
 // mod permissions;
 #[allow(unused_imports)]
+mod plan;
+#[allow(unused_imports)]
+mod preferences;
+#[allow(unused_imports)]
 mod query;
 #[allow(unused_imports)]
+mod reload;
+#[allow(unused_imports)]
 mod rpc;
 #[allow(unused_imports)]
 mod singular;
 #[allow(unused_imports)]
+mod stream;
+#[allow(unused_imports)]
+mod tx;
+#[allow(unused_imports)]
 mod unicode;
 #[allow(unused_imports)]
 mod update;
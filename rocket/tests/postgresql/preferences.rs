@@ -0,0 +1,97 @@
+// Copyright (c) 2022-2025 subZero Cloud S.R.L
+//
+// This file is part of subZero - The All-in-One library suite for internal tools development
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+use super::setup::*;
+
+demonstrate! {
+    describe "Prefer" {
+        use super::*;
+        use rocket::http::{Header, Status};
+
+        before {
+            setup_db(&INIT_DB);
+            setup_client(&INIT_CLIENT, &CLIENT);
+        }
+
+        #[rocket::async_test]
+        async it "ignores missing=default in lenient mode" {
+            let client = CLIENT.get().await;
+            let response = client.get("/rest/items?id=eq.1").header(Header::new("Prefer", "missing=default, count=exact")).dispatch().await;
+            assert_eq!(response.status(), Status::Ok);
+            assert_eq!(response.headers().get_one("Preference-Applied"), Some("count=exact"));
+        }
+
+        #[rocket::async_test]
+        async it "rejects missing=default with handling=strict" {
+            let client = CLIENT.get().await;
+            let response = client.get("/rest/items?id=eq.1").header(Header::new("Prefer", "handling=strict, missing=default")).dispatch().await;
+            assert_eq!(response.status(), Status::BadRequest);
+            let body: serde_json::Value = serde_json::from_str(&response.into_string().await.unwrap()).unwrap();
+            assert_eq!(body["details"], "Invalid preferences: missing=default");
+        }
+
+        #[rocket::async_test]
+        async it "reports resolution for an upsert" {
+            let client = CLIENT.get().await;
+            let response = client
+                .post("/rest/items")
+                .header(Header::new("Prefer", "resolution=merge-duplicates"))
+                .header(Header::new("Content-Type", "application/json"))
+                .body(r#"{"id":1}"#)
+                .dispatch()
+                .await;
+            assert_eq!(response.status(), Status::Created);
+            assert_eq!(response.headers().get_one("Preference-Applied"), Some("resolution=merge-duplicates"));
+        }
+
+        #[rocket::async_test]
+        async it "applies max-affected only with handling=strict" {
+            let client = CLIENT.get().await;
+            let patch = |prefer: &'static str| {
+                client
+                    .patch("/rest/simple_pk")
+                    .header(Header::new("Prefer", prefer))
+                    .header(Header::new("Content-Type", "application/json"))
+                    .body(r#"{"extra":"w"}"#)
+            };
+            let response = patch("max-affected=1").dispatch().await;
+            assert_eq!(response.status(), Status::NoContent);
+            assert_eq!(response.headers().get_one("Preference-Applied"), None);
+            let response = patch("handling=strict, max-affected=2").dispatch().await;
+            assert_eq!(response.status(), Status::NoContent);
+            assert_eq!(response.headers().get_one("Preference-Applied"), Some("handling=strict, max-affected=2"));
+            let response = patch("handling=strict, max-affected=1").dispatch().await;
+            assert_eq!(response.status(), Status::BadRequest);
+        }
+
+        #[rocket::async_test]
+        async it "does not report preferences that do not apply to the request" {
+            let client = CLIENT.get().await;
+            let response = client
+                .patch("/rest/items?id=eq.1")
+                .header(Header::new("Prefer", "resolution=merge-duplicates"))
+                .header(Header::new("Content-Type", "application/json"))
+                .body(r#"{"id":1}"#)
+                .dispatch()
+                .await;
+            assert_eq!(response.status(), Status::NoContent);
+            assert_eq!(response.headers().get_one("Preference-Applied"), None);
+            let response = client.get("/rest/items?id=eq.1").header(Header::new("Prefer", "handling=strict, max-affected=1")).dispatch().await;
+            assert_eq!(response.status(), Status::Ok);
+            assert_eq!(response.headers().get_one("Preference-Applied"), Some("handling=strict"));
+        }
+    }
+}
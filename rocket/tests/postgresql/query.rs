@@ -395,6 +395,32 @@ feature "query"
           [json|r#"[{"id":1,"computed_overload":true}]"#|]
           { matchHeaders = ["Content-Type" <:> "application/json"] }
 
+      it "casts a computed column" $
+        get "/items?id=eq.1&select=id,anti_id::text" shouldRespondWith
+          [json|r#"[{"id":1,"anti_id":"-1"}]"#|]
+          { matchHeaders = ["Content-Type" <:> "application/json"] }
+
+      it "filters by a computed column" $
+        get "/items?anti_id=eq.-2&select=id" shouldRespondWith
+          [json|r#"[{"id":2}]"#|]
+          { matchHeaders = ["Content-Type" <:> "application/json"] }
+
+      it "embeds a set returning function with a computed column" $
+        get "/items?id=gt.12&select=id,anti_id,next_items(id,anti_id)&order=id.asc&next_items.order=id.asc" shouldRespondWith
+          [json|r#"[
+            {"id":13,"anti_id":-13,"next_items":[{"id":14,"anti_id":-14},{"id":15,"anti_id":-15}]},
+            {"id":14,"anti_id":-14,"next_items":[{"id":15,"anti_id":-15}]},
+            {"id":15,"anti_id":-15,"next_items":[]}
+          ]"#|]
+          { matchHeaders = ["Content-Type" <:> "application/json"] }
+
+      it "rejects ordering by a computed column" $
+        get "/items?select=id&order=anti_id.desc" shouldRespondWith
+          [json|r#"{"message":"ordering by the computed column 'anti_id' is not supported"}"#|]
+          { matchStatus  = 400
+          , matchHeaders = ["Content-Type" <:> "application/json"]
+          }
+
    describe "partitioned tables embedding" $ do
     it "can request a table as parent from a partitioned table" $
       get "/car_models?name=in.(DeLorean,Murcielago)&select=name,year,car_brands(name)&order=name.asc" shouldRespondWith
@@ -886,6 +912,11 @@ feature "query"
         , matchHeaders = ["Content-Type" <:> "text/csv; charset=utf-8"]
         }
 
+    it "should respond with 406 to a plan request when plans are not enabled" $
+      request methodGet "/simple_pk"
+              (acceptHdrs "application/vnd.pgrst.plan+json") ""
+        shouldRespondWith 406
+
 //   describe "Canonical location" $ do
 //     it "Sets Content-Location with alphabetized params" $
 //       get "/no_pk?b=eq.1&a=eq.1"
@@ -0,0 +1,97 @@
+// Copyright (c) 2022-2025 subZero Cloud S.R.L
+//
+// This file is part of subZero - The All-in-One library suite for internal tools development
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+use super::setup::*;
+use super::super::start;
+use std::process::Command;
+
+static INIT_CLIENT: Once = Once::new();
+lazy_static! {
+    static ref CLIENT_INNER: AsyncOnce<Client> = AsyncOnce::new(async {
+        // the tables are created in a schema of their own so other tests do not see them
+        psql("create schema if not exists reload_test; grant usage on schema reload_test to postgrest_test_anonymous;");
+        let db_schemas = env::var("SUBZERO_DB_SCHEMAS").unwrap();
+        env::set_var("SUBZERO_DB_SCHEMAS", "[reload_test]");
+        env::set_var("SUBZERO_ADMIN_TOKEN", "reload_secret");
+        let client = Client::untracked(start().await.unwrap()).await.expect("valid client");
+        env::set_var("SUBZERO_DB_SCHEMAS", db_schemas);
+        env::remove_var("SUBZERO_ADMIN_TOKEN");
+        client
+    });
+    static ref CLIENT: &'static AsyncOnce<Client> = {
+        thread::spawn(move || {
+            RUNTIME.block_on(async {
+                CLIENT_INNER.get().await;
+            })
+        })
+        .join()
+        .expect("Thread panicked");
+        &*CLIENT_INNER
+    };
+}
+
+fn psql(sql: &str) {
+    let output = Command::new("psql")
+        .arg(env::var("SUBZERO_DB_URI").unwrap())
+        .arg("-v")
+        .arg("ON_ERROR_STOP=1")
+        .arg("-c")
+        .arg(sql)
+        .output()
+        .expect("failed to execute psql");
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+}
+
+demonstrate! {
+    describe "schema cache reload" {
+        use super::*;
+        use rocket::http::{Header, Status};
+
+        before {
+            setup_db(&INIT_DB);
+            setup_client(&INIT_CLIENT, &CLIENT);
+        }
+
+        #[rocket::async_test]
+        async it "requires the admin token" {
+            let client = CLIENT.get().await;
+            let response = client.post("/rest/admin/reload").dispatch().await;
+            assert_eq!(response.status(), Status::Unauthorized);
+            let response = client.post("/rest/admin/reload").header(Header::new("Authorization", "Bearer wrong")).dispatch().await;
+            assert_eq!(response.status(), Status::Unauthorized);
+        }
+
+        #[rocket::async_test]
+        async it "makes schema changes visible" {
+            let client = CLIENT.get().await;
+            let reload = || client.post("/rest/admin/reload").header(Header::new("Authorization", "Bearer reload_secret"));
+
+            psql("drop table if exists reload_test.reloaded");
+            assert_eq!(reload().dispatch().await.status(), Status::Ok);
+
+            psql("create table reload_test.reloaded (id int); grant select on reload_test.reloaded to postgrest_test_anonymous;");
+            let response = client.get("/rest/reloaded?select=id").dispatch().await;
+            assert_eq!(response.status(), Status::NotFound);
+
+            assert_eq!(reload().dispatch().await.status(), Status::Ok);
+            let response = client.get("/rest/reloaded?select=id").dispatch().await;
+            assert_eq!(response.status(), Status::Ok);
+            assert_eq!(response.into_string().await.unwrap(), "[]");
+
+            psql("drop table reload_test.reloaded");
+        }
+    }
+}
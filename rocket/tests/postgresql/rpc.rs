@@ -418,10 +418,10 @@ feature "rpc"
           it "rejects unknown content type even if payload is good" $ do
             request methodPost "/rpc/sayhello"
               (acceptHdrs "audio/mpeg3") [json| r#"{ "name": "world" }"# |]
-                shouldRespondWith 415
+                shouldRespondWith 406
             request methodGet "/rpc/sayhello?name=world"
               (acceptHdrs "audio/mpeg3") ""
-                shouldRespondWith 415
+                shouldRespondWith 406
           // it "rejects malformed json payload" $ do
           //   p <- request methodPost "/rpc/sayhello"
           //     (acceptHdrs "application/json") "sdfsdf"
@@ -882,24 +882,6 @@ feature "rpc"
         //     //     , matchHeaders = ["Content-Type" <:> "application/octet-stream"]
         //     //     }
 
-        //     // it "can get raw output with Accept: text/plain" $
-        //     //   request methodGet "/rpc/welcome" (acceptHdrs "text/plain") ""
-        //     //     shouldRespondWith [text|"Welcome to PostgREST"|]
-        //     //     { matchStatus = 200
-        //     //     , matchHeaders = ["Content-Type" <:> "text/plain; charset=utf-8"]
-        //     //     }
-
-        //   // describe "Proc that returns set of scalars" $
-        //   //   it "can query without selecting column" $
-        //   //     request methodGet "/rpc/welcome_twice"
-        //   //         (acceptHdrs "text/plain")
-        //   //         ""
-        //   //       shouldRespondWith
-        //   //         "Welcome to PostgRESTWelcome to PostgREST"
-        //   //         { matchStatus = 200
-        //   //         , matchHeaders = ["Content-Type" <:> "text/plain; charset=utf-8"]
-        //   //         }
-
         //   // describe "Proc that returns rows" $ do
         //   //   it "can query if a single column is selected" $
         //   //     request methodPost "/rpc/ret_rows_with_base64_bin?select=img" (acceptHdrs "application/octet-stream") ""
@@ -915,6 +897,32 @@ feature "rpc"
         //   //         [json| r#"{"message":"application/octet-stream requested but more than one column was selected"}"# |]
         //   //         { matchStatus = 406 }
 
+      describe "raw output" $ do
+        it "can get raw output with Accept: text/plain" $
+          request methodGet "/rpc/welcome" (acceptHdrs "text/plain") ""
+            shouldRespondWith [text|"Welcome to PostgREST"|]
+            { matchStatus = 200
+            , matchHeaders = ["Content-Type" <:> "text/plain; charset=utf-8"]
+            }
+
+        it "concatenates a set of scalars" $
+          request methodGet "/rpc/welcome_twice" (acceptHdrs "text/plain") ""
+            shouldRespondWith [text|"Welcome to PostgRESTWelcome to PostgREST"|]
+            { matchStatus = 200
+            , matchHeaders = ["Content-Type" <:> "text/plain; charset=utf-8"]
+            }
+
+        it "can get binary output with Accept: application/octet-stream" $
+          request methodGet "/rpc/welcome_bin" (acceptHdrs "application/octet-stream") ""
+            shouldRespondWith [text|"Welcome to PostgREST"|]
+            { matchStatus = 200
+            , matchHeaders = ["Content-Type" <:> "application/octet-stream"]
+            }
+
+        it "rejects raw output a function does not return" $
+          request methodGet "/rpc/welcome" (acceptHdrs "application/octet-stream") ""
+            shouldRespondWith 406
+
       describe "only for GET rpc" $ do
         it "should fail on mutating procs" $ do
           get "/rpc/callcounter" shouldRespondWith 405
@@ -118,6 +118,15 @@ where
         env::set_var("SUBZERO_DB_SCHEMA_STRUCTURE", "{sql_file=../../../../introspection/postgresql_introspection_query.sql}");
         env::set_var("SUBZERO_DISABLE_INTERNAL_PERMISSIONS", "true");
         env::remove_var("SUBZERO_DB_MAX_ROWS");
+        env::remove_var("SUBZERO_DB_STREAM_RESPONSES");
+        env::remove_var("SUBZERO_DB_PLAN_ENABLED");
+        env::remove_var("SUBZERO_DB_PLAN_ROLES");
+        env::remove_var("SUBZERO_ADMIN_TOKEN");
+        env::remove_var("SUBZERO_DB_TX_ALLOW_OVERRIDE");
+        env::remove_var("SUBZERO_JWT_PUBLIC_KEY");
+        env::remove_var("SUBZERO_JWT_JWKS");
+        env::remove_var("SUBZERO_JWT_AUD");
+        env::remove_var("SUBZERO_JWT_ISS");
         lazy_static::initialize(client);
     });
 }
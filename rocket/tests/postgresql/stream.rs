@@ -0,0 +1,94 @@
+// Copyright (c) 2022-2025 subZero Cloud S.R.L
+//
+// This file is part of subZero - The All-in-One library suite for internal tools development
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+use super::setup::*;
+use super::super::start;
+
+static INIT_CLIENT: Once = Once::new();
+lazy_static! {
+    static ref CLIENT_INNER: AsyncOnce<Client> = AsyncOnce::new(async {
+        env::set_var("SUBZERO_DB_STREAM_RESPONSES", "true");
+        let client = Client::untracked(start().await.unwrap()).await.expect("valid client");
+        env::remove_var("SUBZERO_DB_STREAM_RESPONSES");
+        client
+    });
+    static ref CLIENT: &'static AsyncOnce<Client> = {
+        thread::spawn(move || {
+            RUNTIME.block_on(async {
+                CLIENT_INNER.get().await;
+            })
+        })
+        .join()
+        .expect("Thread panicked");
+        &*CLIENT_INNER
+    };
+}
+
+haskell_test! {
+feature "stream"
+describe "Streaming responses" $ do
+  it "streams json rows" $
+    get "/items?select=id&id=lt.4&order=id"
+      shouldRespondWith
+        [json| r#"[{"id":1},{"id":2},{"id":3}]"# |]
+        { matchStatus  = 200
+        , matchHeaders = ["Content-Range" <:> "*/*", "Content-Type" <:> "application/json"]
+        }
+
+  it "streams an empty result" $
+    get "/items?select=id&id=gt.1000"
+      shouldRespondWith
+        [json| r#"[]"# |]
+        { matchStatus  = 200 }
+
+  it "streams embedded resources" $
+    get "/clients?id=eq.1&select=id,projects(id,tasks(id))"
+      shouldRespondWith
+        [json|r#"[{"id":1,"projects":[{"id":1,"tasks":[{"id":1},{"id":2}]},{"id":2,"tasks":[{"id":3},{"id":4}]}]}]"#|]
+        { matchStatus  = 200 }
+
+  it "streams csv rows" $
+    request methodGet "/simple_pk?select=k,extra"
+            (acceptHdrs "text/csv; version=1") ""
+      shouldRespondWith [text|"k,extra\nxyyx,u\nxYYx,v"|]
+      { matchStatus  = 200
+      , matchHeaders = ["Content-Type" <:> "text/csv; charset=utf-8"]
+      }
+
+  it "streams the csv header of an empty result" $
+    request methodGet "/simple_pk?select=k,extra&k=eq.none"
+            (acceptHdrs "text/csv; version=1") ""
+      shouldRespondWith [text|"k,extra"|]
+      { matchStatus  = 200
+      , matchHeaders = ["Content-Type" <:> "text/csv; charset=utf-8"]
+      }
+
+  it "does not stream when a count is requested" $
+    request methodGet "/items?select=id&id=lt.4&order=id"
+        [("Prefer", "count=exact")]
+        ""
+      shouldRespondWith
+        [json| r#"[{"id":1},{"id":2},{"id":3}]"# |]
+        { matchStatus  = 200
+        , matchHeaders = ["Content-Range" <:> "0-2/3"]
+        }
+
+  it "reports errors raised while reading the rows" $
+    get "/complex_items?select=settings->foo->>bar::integer"
+      shouldRespondWith
+        [json| r#"{"hint":null,"details":null,"code":"22P02","message":"invalid input syntax for type integer: \"baz\""}"# |]
+        { matchStatus  = 400 , matchHeaders = [] }
+}
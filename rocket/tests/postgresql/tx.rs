@@ -0,0 +1,99 @@
+// Copyright (c) 2022-2025 subZero Cloud S.R.L
+//
+// This file is part of subZero - The All-in-One library suite for internal tools development
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+use super::setup::*;
+use super::super::start;
+
+static INIT_CLIENT: Once = Once::new();
+lazy_static! {
+    static ref CLIENT_INNER: AsyncOnce<Client> = AsyncOnce::new(async {
+        env::set_var("SUBZERO_DB_TX_ALLOW_OVERRIDE", "true");
+        let client = Client::untracked(start().await.unwrap()).await.expect("valid client");
+        env::remove_var("SUBZERO_DB_TX_ALLOW_OVERRIDE");
+        client
+    });
+    static ref CLIENT: &'static AsyncOnce<Client> = {
+        thread::spawn(move || {
+            RUNTIME.block_on(async {
+                CLIENT_INNER.get().await;
+            })
+        })
+        .join()
+        .expect("Thread panicked");
+        &*CLIENT_INNER
+    };
+}
+
+demonstrate! {
+    describe "Prefer: tx" {
+        use super::*;
+        use rocket::http::{Header, Status};
+
+        before {
+            setup_db(&INIT_DB);
+            setup_client(&INIT_CLIENT, &CLIENT);
+            setup_client(&super::super::setup::INIT_CLIENT, &super::super::setup::CLIENT);
+        }
+
+        #[rocket::async_test]
+        async it "is applied and reported when overriding is allowed" {
+            let client = CLIENT.get().await;
+            let response = client
+                .post("/rest/items")
+                .header(Header::new("Prefer", "tx=rollback"))
+                .header(Header::new("Content-Type", "application/json"))
+                .body(r#"{"id":2000}"#)
+                .dispatch()
+                .await;
+            assert_eq!(response.status(), Status::Created);
+            assert_eq!(response.headers().get_one("Preference-Applied"), Some("tx=rollback"));
+            let response = client.get("/rest/items?id=eq.2000").dispatch().await;
+            assert_eq!(response.into_string().await.unwrap(), "[]");
+        }
+
+        #[rocket::async_test]
+        async it "rolls back an update exceeding max-affected" {
+            let client = CLIENT.get().await;
+            let response = client
+                .patch("/rest/simple_pk")
+                .header(Header::new("Prefer", "tx=commit, handling=strict, max-affected=1"))
+                .header(Header::new("Content-Type", "application/json"))
+                .body(r#"{"extra":"w"}"#)
+                .dispatch()
+                .await;
+            assert_eq!(response.status(), Status::BadRequest);
+            let response = client.get("/rest/simple_pk?select=extra&order=extra").dispatch().await;
+            let body: serde_json::Value = serde_json::from_str(&response.into_string().await.unwrap()).unwrap();
+            assert_eq!(body, serde_json::json!([{"extra": "u"}, {"extra": "v"}]));
+        }
+
+        #[rocket::async_test]
+        async it "is ignored and not reported by default" {
+            let client = super::super::setup::CLIENT.get().await;
+            let response = client
+                .post("/rest/items")
+                .header(Header::new("Prefer", "tx=commit"))
+                .header(Header::new("Content-Type", "application/json"))
+                .body(r#"{"id":2001}"#)
+                .dispatch()
+                .await;
+            assert_eq!(response.status(), Status::Created);
+            assert_eq!(response.headers().get_one("Preference-Applied"), None);
+            let response = client.get("/rest/items?id=eq.2001").dispatch().await;
+            assert_eq!(response.into_string().await.unwrap(), "[]");
+        }
+    }
+}
@@ -0,0 +1,31 @@
+// Copyright (c) 2022-2025 subZero Cloud S.R.L
+//
+// This file is part of subZero - The All-in-One library suite for internal tools development
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+use super::setup::*;
+
+static INIT_CLIENT: Once = Once::new();
+
+haskell_test! {
+feature "geojson"
+  describe "GeoJSON output" $ do
+    it "is rejected as unsupported" $
+      request methodGet "/tbl1?select=one"
+          (acceptHdrs "application/geo+json")
+          ""
+        shouldRespondWith
+          [json| r#"{"message":"GeoJSON output is not supported in sqlite"}"# |]
+          { matchStatus = 400 }
+}
@@ -25,3 +25,11 @@ mod basic;
 #[allow(unused_imports)]
 #[path = "../common/permissions.rs"]
 mod permissions;
+
+mod geojson;
+
+mod operators;
+
+mod rpc;
+
+mod vhosts;
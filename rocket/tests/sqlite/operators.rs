@@ -0,0 +1,53 @@
+// Copyright (c) 2022-2025 subZero Cloud S.R.L
+//
+// This file is part of subZero - The All-in-One library suite for internal tools development
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+use super::setup::*;
+
+static INIT_CLIENT: Once = Once::new();
+
+haskell_test! {
+feature "operators"
+  describe "json array operators" $ do
+    it "filtering with cs does not match values that are not json" $
+      get "/complex_items?select=id&name=cs.[\"One\"]" shouldRespondWith
+        [json| r#"[]"# |]
+        { matchHeaders = ["Content-Type" <:> "application/json"] }
+
+    it "filtering with cd works" $
+      get "/complex_items?select=id&settings=cd.[1,2,3,\"a\",\"b\",\"c\",\"d\"]" shouldRespondWith
+        [json| r#"[{"id":3}]"# |]
+        { matchHeaders = ["Content-Type" <:> "application/json"] }
+
+    it "filtering with ov works" $ do
+      get "/complex_items?select=id&settings=ov.[\"x\",\"a\"]" shouldRespondWith
+        [json| r#"[{"id":3}]"# |]
+        { matchHeaders = ["Content-Type" <:> "application/json"] }
+      get "/complex_items?select=id&settings=ov.[\"x\",\"y\"]" shouldRespondWith
+        [json| r#"[]"# |]
+        { matchHeaders = ["Content-Type" <:> "application/json"] }
+
+  describe "isdistinct" $ do
+    it "matches different and null values" $
+      get "/tbl1?select=one&two=isdistinct.10" shouldRespondWith
+        [json| r#"[{"one":"goodbye"}]"# |]
+        { matchHeaders = ["Content-Type" <:> "application/json"] }
+
+  describe "unsupported operators" $ do
+    it "returns an error naming the operator" $
+      get "/tbl1?select=one&two=adj.10" shouldRespondWith
+        [json| r#"{"message":"the adj operator is not supported in sqlite"}"# |]
+        { matchStatus = 400 }
+}
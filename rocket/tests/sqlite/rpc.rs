@@ -0,0 +1,54 @@
+// Copyright (c) 2022-2025 subZero Cloud S.R.L
+//
+// This file is part of subZero - The All-in-One library suite for internal tools development
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+use super::setup::*;
+
+static INIT_CLIENT: Once = Once::new();
+
+haskell_test! {
+feature "rpc"
+  describe "a function with a sql body" $ do
+    it "returns a scalar" $ do
+      get "/rpc/add_them?a=1&b=2" shouldRespondWith
+        [text|"3"|]
+        { matchHeaders = ["Content-Type" <:> "application/json"] }
+      post "/rpc/add_them" [json| r#"{ "a": 1, "b": 2 }"# |] shouldRespondWith
+        [text|"3"|]
+        { matchHeaders = ["Content-Type" <:> "application/json"] }
+
+    it "leaves :name inside string literals alone" $
+      get "/rpc/echo_name?name=bob" shouldRespondWith
+        [text| r#""name ':name' is bob""# |]
+        { matchHeaders = ["Content-Type" <:> "application/json"] }
+
+    it "returns a set" $ do
+      get "/rpc/getprojects?client_id=1&select=id,name&order=id" shouldRespondWith
+        [json| r#"[ {"id": 1, "name": "Windows 7"}, {"id": 2, "name": "Windows 10"} ]"# |]
+        { matchHeaders = ["Content-Type" <:> "application/json"] }
+      post "/rpc/getprojects?id=gt.1" [json| r#"{ "client_id": 2 }"# |] shouldRespondWith
+        [json| r#"[ {"id": 3, "name": "IOS", "client_id": 2}, {"id": 4, "name": "OSX", "client_id": 2} ]"# |]
+        { matchHeaders = ["Content-Type" <:> "application/json"] }
+
+  describe "a table-valued function" $ do
+    it "returns the rows" $
+      get "/rpc/generate_series?start=1&stop=3" shouldRespondWith
+        [json| r#"[ {"value": 1}, {"value": 2}, {"value": 3} ]"# |]
+        { matchHeaders = ["Content-Type" <:> "application/json"] }
+
+  describe "unknown function" $ do
+    it "returns 404" $
+      post "/rpc/fakefunc" [json| r#"{}"# |] shouldRespondWith 404
+}
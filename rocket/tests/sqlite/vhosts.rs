@@ -0,0 +1,89 @@
+// Copyright (c) 2022-2025 subZero Cloud S.R.L
+//
+// This file is part of subZero - The All-in-One library suite for internal tools development
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+use super::setup::*;
+use super::super::{read_config, start_with_config};
+use figment::providers::{Format, Toml};
+
+static INIT_CLIENT: Once = Once::new();
+lazy_static! {
+    static ref CLIENT_INNER: AsyncOnce<Client> = AsyncOnce::new(async {
+        // two vhosts using the same database under different url prefixes
+        let db_uri = env::var("SUBZERO_DB_URI").unwrap();
+        let vhost = |url_prefix: &str| {
+            format!(
+                r#"
+                db_type = "sqlite"
+                db_uri = "{db_uri}"
+                db_schemas = ["public"]
+                db_schema_structure = {{ sql_file = "../../../../introspection/sqlite_introspection_query.sql" }}
+                db_anon_role = "anonymous"
+                url_prefix = "{url_prefix}"
+                "#
+            )
+        };
+        // the vhosts are added to the configuration of this client only, the process environment is left untouched
+        let vhosts = format!("[default.vhosts.default]\n{}\n[default.vhosts.api_example_com]\n{}", vhost("/rest"), vhost("/rest/v2"));
+        let config = read_config().merge(Toml::string(&vhosts).nested());
+        Client::untracked(start_with_config(config).await.unwrap()).await.expect("valid client")
+    });
+    static ref CLIENT: &'static AsyncOnce<Client> = {
+        thread::spawn(move || {
+            RUNTIME.block_on(async {
+                CLIENT_INNER.get().await;
+            })
+        })
+        .join()
+        .expect("Thread panicked");
+        &*CLIENT_INNER
+    };
+}
+
+haskell_test! {
+feature "vhosts"
+  describe "routing by the Host header" $ do
+    it "uses the url prefix of the vhost matching the host" $ do
+      request methodGet "/v2/tbl1?select=one&order=one"
+          [("Host", "api.example.com")]
+          ""
+        shouldRespondWith
+          [json| r#"[{"one":"goodbye"},{"one":"hello!"}]"# |]
+          { matchStatus = 200 }
+      request methodGet "/tbl1?select=one&order=one"
+          [("Host", "api.example.com")]
+          ""
+        shouldRespondWith 404
+
+    it "ignores the port in the host" $
+      request methodGet "/v2/tbl1?select=one&order=one"
+          [("Host", "API.example.com:8080")]
+          ""
+        shouldRespondWith
+          [json| r#"[{"one":"goodbye"},{"one":"hello!"}]"# |]
+          { matchStatus = 200 }
+
+    it "falls back to the default vhost for an unknown host" $ do
+      request methodGet "/tbl1?select=one&order=one"
+          [("Host", "unknown.example.com")]
+          ""
+        shouldRespondWith
+          [json| r#"[{"one":"goodbye"},{"one":"hello!"}]"# |]
+          { matchStatus = 200 }
+      request methodGet "/v2/tbl1?select=one&order=one"
+          [("Host", "unknown.example.com")]
+          ""
+        shouldRespondWith 404
+}